@@ -0,0 +1,163 @@
+// Collision cost against levels of 10 to 10,000 boxes: capsule contact
+// queries through the broad phase, the same queries run against every object
+// the way `Player::update` used to work, a full player tick, and a physics
+// tick with a crate on every tenth box, which keeps the broad phase updated
+// as they move.
+//
+//     cargo bench --bench broadphase
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use macroquad::prelude::*;
+use nalgebra::Isometry3;
+use parry3d::query::contact;
+use parry3d::shape::{Capsule, Shape};
+
+use fps_engine::game::{Spawn, State};
+use fps_engine::input::InputCommand;
+use fps_engine::objects::{CollisionBox, PhysicsObject, Transform};
+use fps_engine::player::Player;
+use fps_engine::rigidbody::{BodyConfig, BodyShape, RigidBody};
+
+const TICKS: u32 = 600;
+const DT: f32 = 1. / 60.;
+
+fn side(count: usize) -> usize {
+    (count as f32).sqrt().ceil() as usize
+}
+
+// grid coordinates of tile `i`, centered on the origin
+fn tile(i: usize, count: usize) -> (f32, f32) {
+    let side = side(count);
+    ((i % side) as f32 - side as f32 / 2., (i / side) as f32 - side as f32 / 2.)
+}
+
+// square grid of floor tiles with a pillar on every seventh one, centered on the origin
+fn build_level(count: usize) -> State {
+    let mut state = State::new();
+    let side = side(count);
+    for i in 0..count {
+        let (x, z) = tile(i, count);
+        let obj = if i % 7 == 3 {
+            CollisionBox::new(Transform::from_position(vec3(x, 0.5, z) * 2.), vec3(0.25, 1.5, 0.25))
+        } else {
+            CollisionBox::new(Transform::from_position(vec3(x * 2., -0.5, z * 2.)), vec3(1., 0.5, 1.))
+        };
+        state.add_object(Box::new(obj));
+    }
+    // on the first tile, with the next one in front
+    let corner = -(side as f32);
+    state.spawn = Spawn {
+        position: vec3(corner, 1., corner),
+        ..Default::default()
+    };
+    state
+}
+
+// capsule sliding back and forth across the middle of the level
+fn capsule_path(tick: u32) -> Isometry3<f32> {
+    let x = (tick as f32 * 0.05).sin() * 3.;
+    Isometry3::translation(x, 0.5, 0.)
+}
+
+// microseconds per call of `tick`, over TICKS calls
+fn per_tick(mut tick: impl FnMut(u32)) -> f64 {
+    let start = Instant::now();
+    for i in 0..TICKS {
+        tick(i);
+    }
+    start.elapsed().as_secs_f64() * 1e6 / TICKS as f64
+}
+
+// how many of `objects` the capsule at `capsule_tx` touches
+fn count_contacts<'a>(
+    objects: impl Iterator<Item = &'a dyn PhysicsObject>,
+    capsule: &Capsule,
+    capsule_tx: &Isometry3<f32>,
+) -> usize {
+    objects
+        .filter(|obj| {
+            let obj_tx = obj.get_transform().isometry();
+            contact(capsule_tx, capsule, &obj_tx, obj.get_collider(), 0.).unwrap().is_some()
+        })
+        .count()
+}
+
+fn bench_player(state: &State) -> f64 {
+    let mut player = Player::new(&state.spawn);
+    let time = per_tick(|tick| {
+        // pace back and forth around the spawn, jumping now and then
+        let cmd = InputCommand {
+            forward: tick % 30 < 15,
+            back: tick % 30 >= 15,
+            jump: tick % 60 == 0,
+            ..Default::default()
+        };
+        player.update(state, &cmd, DT);
+    });
+    black_box(player.position);
+    time
+}
+
+fn bench_broadphase(state: &State) -> f64 {
+    let shape = Capsule::new_y(0.25, 0.25);
+    per_tick(|tick| {
+        let player_tx = capsule_path(tick);
+        let aabb = shape.compute_aabb(&player_tx);
+        let near = state.objects_near(&aabb).map(|(_, obj)| obj);
+        black_box(count_contacts(near, &shape, &player_tx));
+    })
+}
+
+fn bench_brute_force(state: &State) -> f64 {
+    let shape = Capsule::new_y(0.25, 0.25);
+    per_tick(|tick| {
+        let player_tx = capsule_path(tick);
+        let all = state.objects().iter().map(|obj| &**obj);
+        black_box(count_contacts(all, &shape, &player_tx));
+    })
+}
+
+fn bench_bodies(count: usize) -> f64 {
+    let mut state = build_level(count);
+    // over every tenth tile, skipping pillars
+    for i in (0..count).step_by(10).filter(|i| i % 7 != 3) {
+        let (x, z) = tile(i, count);
+        state.add_object(Box::new(RigidBody::new(
+            Transform::from_position(vec3(x * 2., 1., z * 2.)),
+            BodyShape::Box(Vec3::splat(0.4)),
+            BodyConfig::default(),
+        )));
+    }
+    let bodies = state.bodies().to_vec();
+    per_tick(|tick| {
+        // thrown up now and then so they don't all fall asleep
+        if tick % 60 == 0 {
+            for &index in bodies.iter() {
+                let center = state.objects()[index].get_transform().position;
+                state.apply_impulse(index, center, vec3(0., 200., 0.));
+            }
+        }
+        state.update_bodies(DT);
+    })
+}
+
+fn main() {
+    println!("microseconds per tick");
+    println!(
+        "{:>8} {:>12} {:>12} {:>12} {:>12}",
+        "objects", "broad phase", "brute force", "player", "bodies"
+    );
+    for count in [10, 100, 1_000, 10_000] {
+        let state = build_level(count);
+        println!(
+            "{:>8} {:>12.2} {:>12.2} {:>12.2} {:>12.2}",
+            count,
+            bench_broadphase(&state),
+            bench_brute_force(&state),
+            bench_player(&state),
+            bench_bodies(count)
+        );
+    }
+}
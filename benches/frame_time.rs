@@ -0,0 +1,143 @@
+// Frame time of drawing the test level from a fixed camera. By default static
+// objects are drawn through the static batch with materials from the registry.
+// --unbatched draws every object by itself, and --recompile additionally
+// compiles each object's material again on every draw and flushes, which is
+// how drawing worked before the registry. --grid N adds an N by N field of
+// boxes and ramps to stress the batch, and --no-shadows skips the sun's shadow
+// map. Needs a window, so it can't run headless.
+//
+//     cargo bench --bench frame_time [-- --grid 40 --unbatched --recompile --no-shadows]
+
+use std::time::Instant;
+
+use macroquad::miniquad::conf::{Conf, Platform};
+use macroquad::prelude::*;
+
+use fps_engine::batch::StaticBatch;
+use fps_engine::game::State;
+use fps_engine::level;
+use fps_engine::materials::Materials;
+use fps_engine::objects::{CollisionBox, CollisionRamp, Transform};
+use fps_engine::shadows::{self, ShadowMap};
+
+const LEVEL: &str = "levels/test.ron";
+const WARMUP_FRAMES: usize = 60;
+const FRAMES: usize = 600;
+
+fn config() -> Conf {
+    Conf {
+        window_title: "frame_time".to_string(),
+        window_width: 1280,
+        window_height: 720,
+        platform: Platform {
+            // no vsync, or every frame takes a whole refresh interval
+            swap_interval: Some(0),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+// alternating boxes and ramps on a grid centered on the origin, below the floor
+// of the test level so it doesn't cover it
+fn add_grid(state: &mut State, size: usize) {
+    let offset = size as f32 / 2.;
+    for x in 0..size {
+        for z in 0..size {
+            let position = vec3(x as f32 - offset, -1., z as f32 - offset) * 2.;
+            let half_extents = vec3(0.5, 0.25 + (x + z) as f32 % 3. * 0.25, 0.5);
+            let transform = Transform::from_position(position);
+            if (x + z) % 2 == 0 {
+                state.add_object(Box::new(CollisionBox::new(transform, half_extents)));
+            } else {
+                state.add_object(Box::new(CollisionRamp::new(transform, half_extents)));
+            }
+        }
+    }
+}
+
+async fn run() {
+    // cargo passes --bench, anything we don't know is ignored
+    let args: Vec<String> = std::env::args().collect();
+    let recompile = args.iter().any(|arg| arg == "--recompile");
+    let unbatched = recompile || args.iter().any(|arg| arg == "--unbatched");
+    let grid = args
+        .iter()
+        .position(|arg| arg == "--grid")
+        .and_then(|i| args.get(i + 1)?.parse().ok())
+        .unwrap_or(0);
+
+    let mut materials = Materials::new();
+    let mut state = level::load(LEVEL, &mut materials).await.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    add_grid(&mut state, grid);
+    let mut batch = StaticBatch::new();
+    let mut shadow_map = ShadowMap::new(shadows::DEFAULT_RESOLUTION);
+    shadow_map.enabled = !args.iter().any(|arg| arg == "--no-shadows");
+    let camera = Camera3D {
+        position: vec3(-8., 6., 12.),
+        target: vec3(0., 0., 0.),
+        up: Vec3::Y,
+        ..Default::default()
+    };
+
+    let mut times = Vec::with_capacity(FRAMES);
+    let mut last = Instant::now();
+    for frame in 0..WARMUP_FRAMES + FRAMES {
+        // the shadow pass always draws from the batch
+        batch.update(&state);
+        shadow_map.render(&state, &batch, camera.position, &materials);
+        clear_background(BLACK);
+        set_camera(&camera);
+        materials.set_lighting(&state.lighting, camera.position);
+        materials.set_shadows(&shadow_map);
+        if !unbatched {
+            batch.draw(&materials);
+        }
+        for (index, obj) in state.objects().iter().enumerate() {
+            if !unbatched && batch.contains(index) {
+                continue;
+            }
+            if recompile {
+                let fresh = Materials::new();
+                obj.draw(&fresh);
+                unsafe { get_internal_gl().flush() };
+            } else {
+                obj.draw(&materials);
+            }
+        }
+        if let Some(skybox) = &state.skybox {
+            skybox.draw(&camera, &materials);
+        }
+        next_frame().await;
+
+        let now = Instant::now();
+        if frame >= WARMUP_FRAMES {
+            times.push((now - last).as_secs_f64() * 1000.);
+        }
+        last = now;
+    }
+
+    times.sort_by(f64::total_cmp);
+    let mean = times.iter().sum::<f64>() / times.len() as f64;
+    println!(
+        "{} objects, {}: mean {:.3} ms, median {:.3} ms, p99 {:.3} ms",
+        state.objects().len(),
+        if recompile {
+            "compiling per draw"
+        } else if unbatched {
+            "drawn one by one"
+        } else {
+            "static batch"
+        },
+        mean,
+        times[times.len() / 2],
+        times[times.len() * 99 / 100],
+    );
+}
+
+fn main() {
+    macroquad::Window::from_config(config(), run());
+}
@@ -0,0 +1,156 @@
+use macroquad::miniquad::TextureId;
+use macroquad::prelude::*;
+
+use crate::game::State;
+use crate::materials::{MaterialHandle, Materials};
+use crate::mesh::split_into_chunks;
+use crate::objects::Transform;
+
+// triangles sharing a material and texture, in world space
+struct Group {
+    material: Option<MaterialHandle>,
+    texture: Option<Texture2D>,
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+impl Group {
+    fn matches(&self, material: Option<MaterialHandle>, texture: Option<TextureId>) -> bool {
+        self.material == material && self.texture.as_ref().map(|t| t.raw_miniquad_id()) == texture
+    }
+}
+
+/// Collects static geometry into one list of triangles per material and
+/// texture. Handed to [`PhysicsObject::batch`](crate::objects::PhysicsObject::batch).
+pub struct BatchBuilder {
+    groups: Vec<Group>,
+}
+
+impl BatchBuilder {
+    /// Adds `mesh`, given in object space, placed by `transform`. Objects
+    /// with no material are drawn with macroquad's default one.
+    pub fn add_mesh(
+        &mut self,
+        material: Option<MaterialHandle>,
+        transform: &Transform,
+        mesh: &Mesh,
+    ) {
+        let texture = mesh.texture.as_ref().map(|t| t.raw_miniquad_id());
+        let index = match self
+            .groups
+            .iter()
+            .position(|g| g.matches(material, texture))
+        {
+            Some(index) => index,
+            None => {
+                self.groups.push(Group {
+                    material,
+                    texture: mesh.texture.clone(),
+                    vertices: Vec::new(),
+                    indices: Vec::new(),
+                });
+                self.groups.len() - 1
+            }
+        };
+        let group = &mut self.groups[index];
+
+        let matrix = transform.matrix();
+        let base = group.vertices.len() as u32;
+        group.vertices.extend(mesh.vertices.iter().map(|v| Vertex {
+            position: matrix.transform_point3(v.position),
+            // rotation only, so normals need no inverse transpose
+            normal: (transform.rotation * v.normal.truncate()).extend(0.),
+            ..*v
+        }));
+        group
+            .indices
+            .extend(mesh.indices.iter().map(|i| base + *i as u32));
+    }
+}
+
+/// Static level geometry merged into a few large meshes per material, so the
+/// whole static set costs a handful of draw calls instead of one or more per
+/// object. Rebuilt when [`State`] reports its static objects changed.
+pub struct StaticBatch {
+    // state generation the batch was built from
+    generation: Option<u64>,
+    batched: Vec<bool>,
+    groups: Vec<(Option<MaterialHandle>, Vec<Mesh>)>,
+}
+
+impl StaticBatch {
+    pub fn new() -> StaticBatch {
+        StaticBatch {
+            generation: None,
+            batched: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+
+    /// Rebuilds the batch if static objects were added or moved since the
+    /// last call. Cheap otherwise; meant to be called once per frame.
+    pub fn update(&mut self, state: &State) {
+        if self.generation == Some(state.static_generation()) {
+            return;
+        }
+
+        let mut builder = BatchBuilder { groups: Vec::new() };
+        self.batched = state
+            .objects()
+            .iter()
+            .map(|obj| obj.is_static() && obj.batch(&mut builder))
+            .collect();
+        self.groups = builder
+            .groups
+            .into_iter()
+            .map(|group| {
+                let mut meshes = split_into_chunks(&group.vertices, &group.indices);
+                for mesh in meshes.iter_mut() {
+                    mesh.texture = group.texture.clone();
+                }
+                (group.material, meshes)
+            })
+            .collect();
+        self.generation = Some(state.static_generation());
+    }
+
+    /// Whether the object at `index` is drawn by the batch rather than by
+    /// itself.
+    pub fn contains(&self, index: usize) -> bool {
+        self.batched.get(index).copied().unwrap_or(false)
+    }
+
+    pub fn draw(&self, materials: &Materials) {
+        for (material, meshes) in self.groups.iter() {
+            match material {
+                Some(material) => materials.apply(*material),
+                None => gl_use_default_material(),
+            }
+            for mesh in meshes.iter() {
+                draw_mesh(mesh);
+            }
+        }
+        gl_use_default_material();
+    }
+
+    /// Draws every batched triangle with the material in use, see
+    /// [`PhysicsObject::draw_shape`](crate::objects::PhysicsObject::draw_shape).
+    pub fn draw_shapes(&self) {
+        for (_, meshes) in self.groups.iter() {
+            for mesh in meshes.iter() {
+                draw_mesh(mesh);
+            }
+        }
+    }
+
+    /// Number of meshes drawn per frame, i.e. roughly the draw call count.
+    pub fn mesh_count(&self) -> usize {
+        self.groups.iter().map(|(_, meshes)| meshes.len()).sum()
+    }
+}
+
+impl Default for StaticBatch {
+    fn default() -> StaticBatch {
+        StaticBatch::new()
+    }
+}
@@ -0,0 +1,129 @@
+// Steps a level without opening a window, for CI and debugging physics.
+//
+//     headless [level.ron] [--ticks N] [--tick-rate N] [--forward] [--back] [--left] [--right]
+//              [--jump] [--sprint] [--crouch] [--fire] [--phi RADIANS]
+//              [--weapon rifle|rocket|grenade]
+
+use fps_engine::input::InputCommand;
+use fps_engine::level::Level;
+use fps_engine::player::{Player, PlayerEvent};
+use fps_engine::timestep::DEFAULT_TICK_RATE;
+use fps_engine::weapon::{Weapon, WeaponConfig};
+
+fn parse_number(arg: Option<String>, flag: &str) -> u32 {
+    arg.and_then(|x| x.parse().ok()).unwrap_or_else(|| {
+        eprintln!("{} expects a number", flag);
+        std::process::exit(1);
+    })
+}
+
+fn main() {
+    let mut level_path = "levels/test.ron".to_string();
+    let mut tick_rate = DEFAULT_TICK_RATE;
+    let mut ticks = 5 * DEFAULT_TICK_RATE;
+    let mut cmd = InputCommand::default();
+    let mut phi = None;
+    let mut weapon_config = WeaponConfig::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ticks" => ticks = parse_number(args.next(), "--ticks"),
+            "--tick-rate" => tick_rate = parse_number(args.next(), "--tick-rate").max(1),
+            "--forward" => cmd.forward = true,
+            "--back" => cmd.back = true,
+            "--left" => cmd.left = true,
+            "--right" => cmd.right = true,
+            "--jump" => cmd.jump = true,
+            "--sprint" => cmd.sprint = true,
+            "--crouch" => cmd.crouch = true,
+            "--fire" => cmd.fire = true,
+            "--phi" => {
+                phi = args.next().and_then(|x| x.parse().ok()).or_else(|| {
+                    eprintln!("--phi expects an angle in radians");
+                    std::process::exit(1);
+                })
+            }
+            "--weapon" => {
+                weapon_config = match args.next().as_deref() {
+                    Some("rifle") => WeaponConfig::default(),
+                    Some("rocket") => WeaponConfig::rocket_launcher(),
+                    Some("grenade") => WeaponConfig::grenade_launcher(),
+                    _ => {
+                        eprintln!("--weapon expects rifle, rocket or grenade");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            _ => level_path = arg,
+        }
+    }
+
+    let mut state = match Level::from_file(&level_path).and_then(|level| level.build_state()) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut player = Player::new(&state.spawn);
+    let mut weapon = Weapon::new(weapon_config);
+    let dt = 1. / tick_rate as f32;
+
+    cmd.theta = state.spawn.theta;
+    cmd.phi = phi.unwrap_or(state.spawn.phi);
+    for tick in 0..ticks {
+        player.update(&state, &cmd, dt);
+        player.push_bodies(&mut state);
+        if !player.is_dead() {
+            for shot in weapon.update(&mut state, &cmd, &player, dt) {
+                if let Some(hit) = shot.hit {
+                    state.apply_impulse(hit.object, hit.point, shot.impulse);
+                }
+                match shot.hit {
+                    Some(hit) => println!(
+                        "t={:.2}s hit object {} at {} normal {}",
+                        (tick + 1) as f32 * dt,
+                        hit.object,
+                        hit.point,
+                        hit.normal
+                    ),
+                    None => println!("t={:.2}s miss", (tick + 1) as f32 * dt),
+                }
+            }
+        }
+        for explosion in state.update_projectiles(&[player.hitbox()], dt) {
+            player.apply_explosion(&explosion);
+            state.apply_explosion(&explosion);
+            println!(
+                "t={:.2}s explosion at {} player velocity {}",
+                (tick + 1) as f32 * dt,
+                explosion.position,
+                player.velocity()
+            );
+        }
+        state.update_bodies(dt);
+        for event in player.take_events() {
+            let time = (tick + 1) as f32 * dt;
+            match event {
+                PlayerEvent::Damaged(damage) => println!(
+                    "t={:.2}s took {:.1} {:?} damage, health {:.1} armor {:.1}",
+                    time,
+                    damage.amount,
+                    damage.kind,
+                    player.health.health(),
+                    player.health.armor()
+                ),
+                PlayerEvent::Died(damage) => println!("t={:.2}s died of {:?}", time, damage.kind),
+                PlayerEvent::Respawned => println!("t={:.2}s respawned at {}", time, player.position),
+            }
+        }
+        if (tick + 1) % tick_rate == 0 {
+            println!("t={:.2}s position={}", (tick + 1) as f32 * dt, player.position);
+        }
+    }
+    println!("final position={}", player.position);
+    for &index in state.bodies().iter() {
+        println!("body {} at {}", index, state.objects()[index].get_transform().position);
+    }
+}
@@ -0,0 +1,267 @@
+// Runs a game for clients to join over UDP, with no window.
+//
+//     cargo run --bin server -- [--config PATH] [level.ron] [--port N] [--max-players N]
+//         [--tick-rate N] [--max-rewind SECONDS] [--relevance-distance UNITS]
+//
+// Settings come from the --config file, or config/server.ron if there is
+// one. Flags override them, and a level named on the command line is played
+// on its own instead of the map list. Type `help` for admin commands; `stop`,
+// Ctrl-C or SIGTERM tell clients and shut down.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use fps_engine::dedicated::{DEFAULT_SERVER_CONFIG_PATH, Match, MapRotation, ServerSettings};
+use fps_engine::game::State;
+use fps_engine::level::{Level, LevelError};
+use fps_engine::server::{Server, ServerEvent};
+use fps_engine::transport::{Transport, UdpTransport};
+
+// how long to sleep between updates when there's nothing to do, so the
+// server doesn't spin a core
+const IDLE_SLEEP: Duration = Duration::from_millis(1);
+// longest to wait on shutdown for clients to ack that they were told
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+const USAGE: &str = "usage: server [--config PATH] [level.ron] [--port N] [--max-players N]
+    [--tick-rate N] [--max-rewind SECONDS] [--relevance-distance UNITS]";
+
+const HELP: &str = "commands:
+  players     list who is playing
+  scores      show the match's scores
+  next        end the match and go on to the next map
+  map PATH    switch to the level at PATH; the rotation carries on after
+  stop        tell everyone and shut down";
+
+// a line on stdout with the time of day, UTC
+macro_rules! log {
+    ($($arg:tt)*) => {{
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs() % 86400);
+        println!(
+            "[{:02}:{:02}:{:02}] {}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            format!($($arg)*)
+        );
+    }};
+}
+
+fn parse_number<T: std::str::FromStr>(value: Option<String>, flag: &str) -> T {
+    value.and_then(|x| x.parse().ok()).unwrap_or_else(|| {
+        eprintln!("{} expects a number", flag);
+        std::process::exit(1);
+    })
+}
+
+fn load(path: &str) -> Result<State, LevelError> {
+    Level::from_file(path).and_then(|level| level.build_state())
+}
+
+// admin commands, a line at a time, read on their own thread so the game
+// doesn't wait on them
+fn read_commands() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn name<T: Transport>(server: &Server<T>, id: u32) -> String {
+    server
+        .players()
+        .find(|(player, _)| *player == id)
+        .map_or_else(|| format!("player {}", id), |(_, name)| name.to_string())
+}
+
+fn log_scores<T: Transport>(server: &Server<T>, game: &Match) {
+    let scores = game.scores();
+    if scores.is_empty() {
+        log!("no scores");
+    }
+    for (id, frags) in scores {
+        log!("  {:>4}  {}", frags, name(server, id));
+    }
+}
+
+// switches to `path`, or says why not
+fn change_level<T: Transport>(server: &mut Server<T>, path: &str) -> bool {
+    match load(path) {
+        Ok(state) => {
+            server.change_level(state, path.to_string());
+            log!("now playing {}", path);
+            true
+        }
+        Err(e) => {
+            log!("can't load {}: {}", path, e);
+            false
+        }
+    }
+}
+
+// on to the next map in the rotation that loads, if any does
+fn rotate<T: Transport>(server: &mut Server<T>, rotation: &mut MapRotation, maps: usize) {
+    for _ in 0..maps {
+        let path = rotation.advance().to_string();
+        if change_level(server, &path) {
+            return;
+        }
+    }
+    log!("no map in the rotation loads; staying on {}", server.config.level);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // the default settings file is optional, one asked for on the command line is not
+    let config_path = args
+        .iter()
+        .position(|arg| arg == "--config")
+        .map(|i| {
+            args.get(i + 1).cloned().unwrap_or_else(|| {
+                eprintln!("--config expects the path of a settings file");
+                std::process::exit(1);
+            })
+        });
+    let settings = match &config_path {
+        Some(path) => ServerSettings::from_file(path),
+        None if std::path::Path::new(DEFAULT_SERVER_CONFIG_PATH).exists() => {
+            ServerSettings::from_file(DEFAULT_SERVER_CONFIG_PATH)
+        }
+        None => Ok(ServerSettings::default()),
+    };
+    let mut settings = settings.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                args.next();
+            }
+            "--port" => settings.port = parse_number(args.next(), "--port"),
+            "--max-players" => settings.max_players = parse_number(args.next(), "--max-players"),
+            "--tick-rate" => settings.tick_rate = parse_number::<u32>(args.next(), "--tick-rate").max(1),
+            "--max-rewind" => settings.max_rewind = parse_number(args.next(), "--max-rewind"),
+            "--relevance-distance" => {
+                settings.relevance_distance = parse_number(args.next(), "--relevance-distance")
+            }
+            _ if arg.starts_with("--") => {
+                eprintln!("unknown option {}\n{}", arg, USAGE);
+                std::process::exit(1);
+            }
+            _ => settings.maps = vec![arg],
+        }
+    }
+
+    let mut rotation = MapRotation::new(settings.maps.clone());
+    let state = load(rotation.current()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let transport = UdpTransport::bind(("0.0.0.0", settings.port)).unwrap_or_else(|e| {
+        eprintln!("can't listen on port {}: {}", settings.port, e);
+        std::process::exit(1);
+    });
+    let mut server = Server::new(transport, state, settings.server_config(rotation.current()));
+    let mut game = Match::new(settings.mode.clone());
+    log!("serving {} on port {} ({:?})", rotation.current(), settings.port, settings.mode);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst)) {
+        eprintln!("can't handle signals, so only `stop` shuts down cleanly: {}", e);
+    }
+    let commands = read_commands();
+
+    let mut last = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        let elapsed = (now - last).as_secs_f32();
+        last = now;
+        if let Err(e) = server.update(elapsed) {
+            log!("{}", e);
+        }
+        game.advance(elapsed);
+        for event in server.take_events() {
+            game.record(&event);
+            match &event {
+                ServerEvent::Joined { id, name, addr } => log!("{} joined from {} as player {}", name, addr, id),
+                ServerEvent::Left { name, reason, .. } => log!("{} left ({:?})", name, reason),
+                ServerEvent::Chat { from, text } => log!("{}: {}", name(&server, *from), text),
+                ServerEvent::Died { id, damage } => match damage.source {
+                    Some(killer) if killer != *id => {
+                        log!("{} killed {}", name(&server, killer), name(&server, *id))
+                    }
+                    _ => log!("{} died of {:?}", name(&server, *id), damage.kind),
+                },
+                ServerEvent::SendFailed { addr, error } => log!("can't send to {}: {}", addr, error),
+            }
+        }
+
+        let mut next_map = game.is_over();
+        if next_map {
+            log!("match over after {:.0} s on {}", game.elapsed(), server.config.level);
+            log_scores(&server, &game);
+        }
+        while let Ok(line) = commands.try_recv() {
+            // the command, and the rest of the line as its argument so paths
+            // can have spaces in them
+            let line = line.trim();
+            let (command, argument) = line
+                .split_once(char::is_whitespace)
+                .map_or((line, ""), |(command, argument)| (command, argument.trim()));
+            match (command, argument) {
+                ("", _) => {}
+                ("players", _) => {
+                    let players: Vec<_> = server.players().collect();
+                    log!("{} of {} players", players.len(), server.config.max_players);
+                    for (id, name) in players {
+                        log!("  {:>4}  {}", id, name);
+                    }
+                }
+                ("scores", _) => log_scores(&server, &game),
+                ("next", _) => next_map = true,
+                ("map", path) if !path.is_empty() => {
+                    if change_level(&mut server, path) {
+                        game = Match::new(settings.mode.clone());
+                    }
+                }
+                ("stop" | "quit", _) => stop.store(true, Ordering::SeqCst),
+                ("help", _) => println!("{}", HELP),
+                _ => println!("unknown command `{}`; try `help`", line),
+            }
+        }
+        if next_map {
+            rotate(&mut server, &mut rotation, settings.maps.len());
+            game = Match::new(settings.mode.clone());
+        }
+        std::thread::sleep(IDLE_SLEEP);
+    }
+
+    log!("shutting down");
+    server.shut_down("server shutting down");
+    let start = Instant::now();
+    let mut last = start;
+    while !server.all_acked() && start.elapsed() < SHUTDOWN_GRACE {
+        let now = Instant::now();
+        if let Err(e) = server.update((now - last).as_secs_f32()) {
+            log!("{}", e);
+        }
+        last = now;
+        std::thread::sleep(IDLE_SLEEP);
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+use macroquad::prelude::*;
+use serde::Deserialize;
+
+pub const DEFAULT_BINDINGS_PATH: &str = "config/input.ron";
+
+/// Something the player can do, independent of which key or button does it.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+pub enum Action {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Jump,
+    Crouch,
+    Sprint,
+    Fire,
+    Reload,
+    /// Switches to the next weapon, wrapping around.
+    NextWeapon,
+    Use,
+}
+
+/// Input settings as written in a `.ron` file, e.g.
+///
+/// ```ron
+/// Bindings(
+///     mouse_sensitivity: 1.5,
+///     invert_y: true,
+///     actions: {
+///         Forward: [Key("W"), Key("Up")],
+///         Jump: [Key("Space"), Pad(South)],
+///         Fire: [Mouse(Left), Pad(RightTrigger2)],
+///     },
+/// )
+/// ```
+///
+/// `actions` replaces the default table as a whole, so an action left out of
+/// it is unbound.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Bindings {
+    /// Multiplier on mouse look speed.
+    pub mouse_sensitivity: f32,
+    pub invert_y: bool,
+    /// Gamepad look speed at full stick deflection, in radians per second.
+    pub stick_look_speed: f32,
+    /// Stick deflection, from 0 to 1, below which a stick counts as centered.
+    pub stick_deadzone: f32,
+    pub actions: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        let key = |name: &str| Binding::Key(KeyName::try_from(name.to_string()).unwrap());
+        let actions = HashMap::from([
+            (Action::Forward, vec![key("W")]),
+            (Action::Back, vec![key("S")]),
+            (Action::Left, vec![key("A")]),
+            (Action::Right, vec![key("D")]),
+            (Action::Jump, vec![key("Space"), Binding::Pad(PadButton::South)]),
+            (
+                Action::Crouch,
+                vec![key("LeftControl"), key("C"), Binding::Pad(PadButton::East)],
+            ),
+            (Action::Sprint, vec![key("LeftShift"), Binding::Pad(PadButton::LeftThumb)]),
+            (
+                Action::Fire,
+                vec![Binding::Mouse(MouseName::Left), Binding::Pad(PadButton::RightTrigger2)],
+            ),
+            (Action::Reload, vec![key("R"), Binding::Pad(PadButton::North)]),
+            (Action::NextWeapon, vec![key("Q"), Binding::Pad(PadButton::RightTrigger)]),
+            (Action::Use, vec![key("E"), Binding::Pad(PadButton::West)]),
+        ]);
+        Bindings {
+            mouse_sensitivity: 1.,
+            invert_y: false,
+            stick_look_speed: 3.,
+            stick_deadzone: 0.2,
+            actions,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Binding {
+    Key(KeyName),
+    Mouse(MouseName),
+    Pad(PadButton),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum MouseName {
+    Left,
+    Right,
+    Middle,
+}
+
+impl MouseName {
+    pub fn button(self) -> MouseButton {
+        match self {
+            MouseName::Left => MouseButton::Left,
+            MouseName::Right => MouseButton::Right,
+            MouseName::Middle => MouseButton::Middle,
+        }
+    }
+}
+
+/// Gamepad buttons, named by position as in gilrs: `South` is A on an Xbox
+/// pad and cross on a PlayStation one.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum PadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+// names accepted by Key(...): every KeyCode variant but Unknown
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Space", KeyCode::Space), ("Apostrophe", KeyCode::Apostrophe),
+    ("Comma", KeyCode::Comma), ("Minus", KeyCode::Minus), ("Period", KeyCode::Period),
+    ("Slash", KeyCode::Slash), ("Key0", KeyCode::Key0), ("Key1", KeyCode::Key1),
+    ("Key2", KeyCode::Key2), ("Key3", KeyCode::Key3), ("Key4", KeyCode::Key4),
+    ("Key5", KeyCode::Key5), ("Key6", KeyCode::Key6), ("Key7", KeyCode::Key7),
+    ("Key8", KeyCode::Key8), ("Key9", KeyCode::Key9), ("Semicolon", KeyCode::Semicolon),
+    ("Equal", KeyCode::Equal), ("A", KeyCode::A), ("B", KeyCode::B), ("C", KeyCode::C),
+    ("D", KeyCode::D), ("E", KeyCode::E), ("F", KeyCode::F), ("G", KeyCode::G),
+    ("H", KeyCode::H), ("I", KeyCode::I), ("J", KeyCode::J), ("K", KeyCode::K),
+    ("L", KeyCode::L), ("M", KeyCode::M), ("N", KeyCode::N), ("O", KeyCode::O),
+    ("P", KeyCode::P), ("Q", KeyCode::Q), ("R", KeyCode::R), ("S", KeyCode::S),
+    ("T", KeyCode::T), ("U", KeyCode::U), ("V", KeyCode::V), ("W", KeyCode::W),
+    ("X", KeyCode::X), ("Y", KeyCode::Y), ("Z", KeyCode::Z),
+    ("LeftBracket", KeyCode::LeftBracket), ("Backslash", KeyCode::Backslash),
+    ("RightBracket", KeyCode::RightBracket), ("GraveAccent", KeyCode::GraveAccent),
+    ("World1", KeyCode::World1), ("World2", KeyCode::World2), ("Escape", KeyCode::Escape),
+    ("Enter", KeyCode::Enter), ("Tab", KeyCode::Tab), ("Backspace", KeyCode::Backspace),
+    ("Insert", KeyCode::Insert), ("Delete", KeyCode::Delete), ("Right", KeyCode::Right),
+    ("Left", KeyCode::Left), ("Down", KeyCode::Down), ("Up", KeyCode::Up),
+    ("PageUp", KeyCode::PageUp), ("PageDown", KeyCode::PageDown), ("Home", KeyCode::Home),
+    ("End", KeyCode::End), ("CapsLock", KeyCode::CapsLock),
+    ("ScrollLock", KeyCode::ScrollLock), ("NumLock", KeyCode::NumLock),
+    ("PrintScreen", KeyCode::PrintScreen), ("Pause", KeyCode::Pause), ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2), ("F3", KeyCode::F3), ("F4", KeyCode::F4), ("F5", KeyCode::F5),
+    ("F6", KeyCode::F6), ("F7", KeyCode::F7), ("F8", KeyCode::F8), ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10), ("F11", KeyCode::F11), ("F12", KeyCode::F12),
+    ("F13", KeyCode::F13), ("F14", KeyCode::F14), ("F15", KeyCode::F15),
+    ("F16", KeyCode::F16), ("F17", KeyCode::F17), ("F18", KeyCode::F18),
+    ("F19", KeyCode::F19), ("F20", KeyCode::F20), ("F21", KeyCode::F21),
+    ("F22", KeyCode::F22), ("F23", KeyCode::F23), ("F24", KeyCode::F24),
+    ("F25", KeyCode::F25), ("Kp0", KeyCode::Kp0), ("Kp1", KeyCode::Kp1),
+    ("Kp2", KeyCode::Kp2), ("Kp3", KeyCode::Kp3), ("Kp4", KeyCode::Kp4),
+    ("Kp5", KeyCode::Kp5), ("Kp6", KeyCode::Kp6), ("Kp7", KeyCode::Kp7),
+    ("Kp8", KeyCode::Kp8), ("Kp9", KeyCode::Kp9), ("KpDecimal", KeyCode::KpDecimal),
+    ("KpDivide", KeyCode::KpDivide), ("KpMultiply", KeyCode::KpMultiply),
+    ("KpSubtract", KeyCode::KpSubtract), ("KpAdd", KeyCode::KpAdd),
+    ("KpEnter", KeyCode::KpEnter), ("KpEqual", KeyCode::KpEqual),
+    ("LeftShift", KeyCode::LeftShift), ("LeftControl", KeyCode::LeftControl),
+    ("LeftAlt", KeyCode::LeftAlt), ("LeftSuper", KeyCode::LeftSuper),
+    ("RightShift", KeyCode::RightShift), ("RightControl", KeyCode::RightControl),
+    ("RightAlt", KeyCode::RightAlt), ("RightSuper", KeyCode::RightSuper),
+    ("Menu", KeyCode::Menu), ("Back", KeyCode::Back),
+];
+
+/// Keyboard key, written by its `KeyCode` name, e.g. `"W"` or `"LeftShift"`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct KeyName(pub KeyCode);
+
+impl TryFrom<String> for KeyName {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<KeyName, String> {
+        KEY_NAMES
+            .iter()
+            .find(|(name, _)| *name == value)
+            .map(|(_, code)| KeyName(*code))
+            .ok_or_else(|| format!("unknown key: {}", value))
+    }
+}
+
+#[derive(Debug)]
+pub enum BindingsError {
+    Io {
+        path: String,
+        error: std::io::Error,
+    },
+    Parse {
+        path: String,
+        error: Box<ron::error::SpannedError>,
+    },
+}
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindingsError::Io { path, error } => write!(f, "{}: {}", path, error),
+            BindingsError::Parse { path, error } => write!(
+                f,
+                "{}:{}:{}: {}",
+                path, error.span.start.line, error.span.start.col, error.code
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BindingsError {}
+
+impl Bindings {
+    pub fn parse(path: &str, source: &str) -> Result<Bindings, BindingsError> {
+        ron::from_str(source).map_err(|error| BindingsError::Parse {
+            path: path.to_string(),
+            error: Box::new(error),
+        })
+    }
+
+    pub fn from_file(path: &str) -> Result<Bindings, BindingsError> {
+        let source = fs::read_to_string(path).map_err(|error| BindingsError::Io {
+            path: path.to_string(),
+            error,
+        })?;
+        Bindings::parse(path, &source)
+    }
+
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.actions.get(&action).map_or(&[], |b| b.as_slice())
+    }
+}
@@ -0,0 +1,149 @@
+use crate::protocol::DecodeError;
+
+/// Packs values into bytes a few bits at a time, least significant bit
+/// first, for the parts of the protocol where every byte counts.
+#[derive(Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    scratch: u64,
+    pending: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter::default()
+    }
+
+    /// Bits written so far.
+    pub fn bit_len(&self) -> usize {
+        self.bytes.len() * 8 + self.pending as usize
+    }
+
+    /// The bytes written, the last one padded with zeros.
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        if self.pending > 0 {
+            self.bytes.push(self.scratch as u8);
+        }
+        self.bytes
+    }
+
+    /// Takes back everything after the first `bits` bits, as if it had
+    /// never been written.
+    pub fn truncate(&mut self, bits: usize) {
+        if bits >= self.bit_len() {
+            return;
+        }
+        let whole = bits / 8;
+        let pending = (bits % 8) as u32;
+        let partial = if whole < self.bytes.len() {
+            self.bytes[whole] as u64
+        } else {
+            self.scratch
+        };
+        self.bytes.truncate(whole);
+        self.scratch = partial & mask(pending);
+        self.pending = pending;
+    }
+
+    /// Writes the low `bits` bits of `value`, at most 32.
+    pub fn write(&mut self, value: u32, bits: u32) {
+        debug_assert!(bits <= 32);
+        self.scratch |= (value as u64 & mask(bits)) << self.pending;
+        self.pending += bits;
+        while self.pending >= 8 {
+            self.bytes.push(self.scratch as u8);
+            self.scratch >>= 8;
+            self.pending -= 8;
+        }
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.write(value as u32, 1);
+    }
+
+    /// Two's complement, cut to `bits`; the value must fit.
+    pub fn signed(&mut self, value: i32, bits: u32) {
+        self.write(value as u32, bits);
+    }
+
+    /// A value of any size, in six bits of length and then only as many
+    /// bits as it needs, so small numbers stay small.
+    pub fn uint(&mut self, value: u32) {
+        let bits = 32 - value.leading_zeros();
+        self.write(bits, 6);
+        self.write(value, bits);
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.write(value.to_bits(), 32);
+    }
+}
+
+/// Reads back what a [`BitWriter`] wrote.
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader { bytes, position: 0 }
+    }
+
+    pub fn read(&mut self, bits: u32) -> Result<u32, DecodeError> {
+        debug_assert!(bits <= 32);
+        if self.position + bits as usize > self.bytes.len() * 8 {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let mut value = 0u64;
+        let mut done = 0;
+        while done < bits {
+            let byte = self.bytes[self.position / 8] as u64;
+            let offset = (self.position % 8) as u32;
+            let take = (8 - offset).min(bits - done);
+            value |= ((byte >> offset) & mask(take)) << done;
+            done += take;
+            self.position += take as usize;
+        }
+        Ok(value as u32)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, DecodeError> {
+        Ok(self.read(1)? != 0)
+    }
+
+    pub fn signed(&mut self, bits: u32) -> Result<i32, DecodeError> {
+        let value = self.read(bits)?;
+        // sign extend
+        let shift = 32 - bits;
+        Ok(((value << shift) as i32) >> shift)
+    }
+
+    pub fn uint(&mut self) -> Result<u32, DecodeError> {
+        let bits = self.read(6)?;
+        if bits > 32 {
+            return Err(DecodeError::Malformed);
+        }
+        self.read(bits)
+    }
+
+    pub fn f32(&mut self) -> Result<f32, DecodeError> {
+        Ok(f32::from_bits(self.read(32)?))
+    }
+}
+
+fn mask(bits: u32) -> u64 {
+    (1u64 << bits) - 1
+}
+
+/// `value` in steps of `1 / scale`, rounded, and clamped to what fits in a
+/// signed number of `bits` bits.
+pub fn quantize(value: f32, scale: f32, bits: u32) -> i32 {
+    let limit = (1i64 << (bits - 1)) as f32;
+    (value * scale).round().clamp(-limit, limit - 1.) as i32
+}
+
+/// Back from [`quantize`]d steps.
+pub fn dequantize(steps: i32, scale: f32) -> f32 {
+    steps as f32 / scale
+}
@@ -0,0 +1,59 @@
+use parry3d::bounding_volume::Aabb;
+use parry3d::partitioning::{Bvh, BvhWorkspace};
+
+// moving objects are stored with their box enlarged by this much, so small
+// moves don't touch the tree at all
+const MOVE_MARGIN: f32 = 0.25;
+
+/// Bounding volume hierarchy over the world-space AABBs of `State::objects`,
+/// keyed by index in that list. Collision queries ask it for candidates first
+/// and only run the narrow phase against objects whose boxes overlap.
+pub struct BroadPhase {
+    bvh: Bvh,
+    workspace: BvhWorkspace,
+    changed: bool,
+}
+
+impl BroadPhase {
+    pub fn new() -> BroadPhase {
+        BroadPhase {
+            bvh: Bvh::new(),
+            workspace: BvhWorkspace::default(),
+            changed: false,
+        }
+    }
+
+    pub fn insert(&mut self, index: usize, aabb: Aabb) {
+        self.bvh.insert(aabb, index as u32);
+    }
+
+    /// Moves an object already in the tree. Queries stay correct right away,
+    /// but the tree loosens over time until [`BroadPhase::maintain`] is run.
+    pub fn update(&mut self, index: usize, aabb: Aabb) {
+        self.bvh
+            .insert_with_change_detection(aabb, index as u32, MOVE_MARGIN);
+        self.changed = true;
+    }
+
+    /// Tightens and rebalances the parts of the tree touched by `update`.
+    /// Cheap when nothing moved; run once per tick by
+    /// [`State::update_bodies`](crate::game::State::update_bodies).
+    pub fn maintain(&mut self) {
+        if self.changed {
+            self.bvh.refit(&mut self.workspace);
+            self.bvh.optimize_incremental(&mut self.workspace);
+            self.changed = false;
+        }
+    }
+
+    /// Indices of all objects whose boxes overlap `aabb`.
+    pub fn query_aabb<'a>(&'a self, aabb: &'a Aabb) -> impl Iterator<Item = usize> + 'a {
+        self.bvh.intersect_aabb(aabb).map(|i| i as usize)
+    }
+}
+
+impl Default for BroadPhase {
+    fn default() -> BroadPhase {
+        BroadPhase::new()
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+
+use macroquad::prelude::*;
+
+use crate::connection::{Connection, DEFAULT_TIMEOUT};
+use crate::game::State;
+use crate::input::InputCommand;
+use crate::objects::Transform;
+use crate::prediction::{INPUT_BUFFER_SIZE, InputBuffer};
+use crate::projectile::{Projectile, ProjectileConfig};
+use crate::protocol::{ClientMessage, InputPacket, ServerMessage};
+use crate::snapshot::{SNAPSHOT_HISTORY, Snapshot};
+use crate::transport::Transport;
+use crate::world::World;
+
+/// Commands repeated in each input packet, so a few lost packets lose no input.
+pub const INPUT_REDUNDANCY: usize = 4;
+// a packet goes out at least this often, in seconds, even with nothing to say
+const KEEPALIVE_INTERVAL: f64 = 0.1;
+
+/// Something the server told the client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientEvent {
+    /// The server took us in. Load `level` and [`Client::attach`] it.
+    Connected { player_id: u32, level: String },
+    Refused(String),
+    PlayerJoined { id: u32, name: String },
+    PlayerLeft { id: u32 },
+    Chat { from: u32, text: String },
+    /// The server switched levels. Load `level` and [`Client::attach`] it.
+    LevelChanged { level: String },
+    /// The server shut down, for the given reason.
+    ServerClosed(String),
+    /// Nothing heard from the server for too long.
+    TimedOut,
+}
+
+/// Plays on a [`crate::server::Server`]: sends it our commands and mirrors
+/// the world from the snapshots it sends back.
+///
+/// Our own player doesn't wait on the server. Each command moves them at
+/// once with the same movement code the server runs, and is kept until the
+/// server reports running it. Every snapshot then puts the player where the
+/// server says and replays the commands it hadn't run yet on top, so the
+/// prediction is corrected without input lag.
+pub struct Client<T: Transport> {
+    transport: T,
+    connection: Connection,
+    time: f64,
+    player_id: Option<u32>,
+    tick_rate: u32,
+    world: Option<World>,
+    // newest snapshot, kept until there's a world to apply it to
+    snapshot: Option<Snapshot>,
+    // every snapshot received lately, for the server to send deltas against
+    baselines: VecDeque<Snapshot>,
+    inputs: InputBuffer,
+    input_tick: u32,
+    correction: f32,
+    names: Vec<(u32, String)>,
+    events: Vec<ClientEvent>,
+    timed_out: bool,
+}
+
+impl<T: Transport> Client<T> {
+    /// Starts joining the server at `server` as `name`. The join goes out
+    /// with the next [`Client::update`].
+    pub fn connect(transport: T, server: SocketAddr, name: &str) -> Client<T> {
+        let mut connection = Connection::new(server, 0.);
+        let join = ClientMessage::Join {
+            name: name.to_string(),
+        };
+        connection.send_message(join.encode());
+        Client {
+            transport,
+            connection,
+            time: 0.,
+            player_id: None,
+            tick_rate: 0,
+            world: None,
+            snapshot: None,
+            baselines: VecDeque::new(),
+            inputs: InputBuffer::new(INPUT_BUFFER_SIZE),
+            input_tick: 0,
+            correction: 0.,
+            names: Vec::new(),
+            events: Vec::new(),
+            timed_out: false,
+        }
+    }
+
+    /// Our player's id, once the server has let us in.
+    pub fn player_id(&self) -> Option<u32> {
+        self.player_id
+    }
+
+    /// The server's tick rate, once connected.
+    pub fn tick_rate(&self) -> u32 {
+        self.tick_rate
+    }
+
+    /// The mirrored world, once attached.
+    pub fn world(&self) -> Option<&World> {
+        self.world.as_ref()
+    }
+
+    /// Tick of the newest snapshot received.
+    pub fn snapshot_tick(&self) -> Option<u32> {
+        self.snapshot.as_ref().map(|snapshot| snapshot.tick)
+    }
+
+    /// Name of the player with id `id`, as far as we've heard.
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.names
+            .iter()
+            .find(|(player, _)| *player == id)
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Client tick of the last command sent.
+    pub fn input_tick(&self) -> u32 {
+        self.input_tick
+    }
+
+    /// How far the newest snapshot put our player from where we had
+    /// predicted them for the same tick. Zero while prediction is right.
+    pub fn correction(&self) -> f32 {
+        self.correction
+    }
+
+    /// Round trip time to the server in seconds, once known.
+    pub fn rtt(&self) -> Option<f32> {
+        self.connection.rtt()
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.connection.bytes_received()
+    }
+
+    /// Events since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<ClientEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Gives the client the level the server named in
+    /// [`ClientEvent::Connected`] or [`ClientEvent::LevelChanged`], to mirror
+    /// the game into.
+    pub fn attach(&mut self, state: State) {
+        self.world = Some(World::new(state));
+        if let Some(snapshot) = self.snapshot.take() {
+            self.reconcile(&snapshot);
+            self.snapshot = Some(snapshot);
+        }
+    }
+
+    /// Sends the command for our next tick and predicts its effect on our
+    /// player. Ignored until connected.
+    pub fn send_command(&mut self, cmd: InputCommand) -> io::Result<()> {
+        let Some(id) = self.player_id else {
+            return Ok(());
+        };
+        self.input_tick += 1;
+        let dt = self.dt();
+        let predicted = self.world.as_mut().and_then(|world| {
+            let World { state, avatars, .. } = world;
+            let player = &mut avatars.iter_mut().find(|avatar| avatar.player.id == id)?.player;
+            player.update(state, &cmd, dt);
+            // what happens to the player is for the server to say
+            player.take_events();
+            Some(player.position)
+        });
+        // others are shown as of the newest snapshot, so that's what we aim at
+        let view_tick = self.snapshot_tick().unwrap_or(0);
+        self.inputs.push(self.input_tick, cmd, view_tick, predicted);
+        self.send()
+    }
+
+    /// Says something to everyone in the game.
+    pub fn chat(&mut self, text: &str) {
+        self.connection
+            .send_message(ClientMessage::Chat(text.to_string()).encode());
+    }
+
+    /// Tells the server we're going, right away. Nothing waits for an
+    /// answer; if the packet is lost the server times us out instead.
+    pub fn leave(&mut self) -> io::Result<()> {
+        self.connection.send_message(ClientMessage::Leave.encode());
+        self.send()
+    }
+
+    /// Handles everything received and keeps the connection alive.
+    pub fn update(&mut self, frame_time: f32) -> io::Result<()> {
+        self.time += frame_time as f64;
+        while let Some((from, packet)) = self.transport.receive()? {
+            if from != self.connection.addr {
+                continue;
+            }
+            let Ok(Some(payload)) = self.connection.receive(self.time, &packet) else {
+                continue;
+            };
+            while let Some(data) = self.connection.receive_message() {
+                if let Ok(message) = ServerMessage::decode(&data) {
+                    self.handle_message(message);
+                }
+            }
+            if payload.is_empty() {
+                continue;
+            }
+            let baselines = &self.baselines;
+            let Ok(snapshot) = Snapshot::decode(&payload, |tick| {
+                baselines.iter().find(|baseline| baseline.tick == tick)
+            }) else {
+                continue;
+            };
+            self.keep_baseline(snapshot.clone());
+            // snapshots arriving out of order are older news
+            if self.snapshot_tick().is_none_or(|tick| snapshot.tick > tick) {
+                self.reconcile(&snapshot);
+                self.snapshot = Some(snapshot);
+            }
+        }
+
+        if !self.timed_out && self.connection.timed_out(self.time, DEFAULT_TIMEOUT) {
+            self.timed_out = true;
+            self.events.push(ClientEvent::TimedOut);
+        }
+        if self.connection.has_due_messages(self.time)
+            || self.connection.since_sent(self.time) >= KEEPALIVE_INTERVAL
+        {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    fn handle_message(&mut self, message: ServerMessage) {
+        let event = match message {
+            ServerMessage::Welcome {
+                player_id,
+                tick_rate,
+                level,
+            } => {
+                self.player_id = Some(player_id);
+                self.tick_rate = tick_rate;
+                ClientEvent::Connected { player_id, level }
+            }
+            ServerMessage::Refused(reason) => ClientEvent::Refused(reason),
+            ServerMessage::PlayerJoined { id, name } => {
+                self.names.retain(|(player, _)| *player != id);
+                self.names.push((id, name.clone()));
+                ClientEvent::PlayerJoined { id, name }
+            }
+            ServerMessage::PlayerLeft { id } => {
+                self.names.retain(|(player, _)| *player != id);
+                ClientEvent::PlayerLeft { id }
+            }
+            ServerMessage::Chat { from, text } => ClientEvent::Chat { from, text },
+            ServerMessage::ChangeLevel { level } => {
+                // snapshots wait for the new level to be attached
+                self.world = None;
+                ClientEvent::LevelChanged { level }
+            }
+            ServerMessage::Shutdown(reason) => ClientEvent::ServerClosed(reason),
+        };
+        self.events.push(event);
+    }
+
+    // keeps `snapshot` as long as the server may use it as a baseline
+    fn keep_baseline(&mut self, snapshot: Snapshot) {
+        let newest = self.baselines.iter().map(|kept| kept.tick).fold(snapshot.tick, u32::max);
+        self.baselines.push_back(snapshot);
+        self.baselines.retain(|kept| newest - kept.tick < SNAPSHOT_HISTORY);
+    }
+
+    // length of the server's tick
+    fn dt(&self) -> f32 {
+        1. / self.tick_rate.max(1) as f32
+    }
+
+    // mirrors `snapshot`, then replays on our player the commands the
+    // server hadn't run yet
+    fn reconcile(&mut self, snapshot: &Snapshot) {
+        let dt = self.dt();
+        let Some(world) = &mut self.world else {
+            return;
+        };
+        apply_snapshot(world, snapshot);
+        let Some(id) = self.player_id else {
+            return;
+        };
+        let World { state, avatars, .. } = world;
+        let Some(avatar) = avatars.iter_mut().find(|avatar| avatar.player.id == id) else {
+            return;
+        };
+        if let Some(predicted) = self
+            .inputs
+            .get(snapshot.last_input)
+            .and_then(|input| input.predicted)
+        {
+            self.correction = avatar.player.position.distance(predicted);
+        }
+        let pending: Vec<_> = self.inputs.after(snapshot.last_input).collect();
+        for input in pending {
+            avatar.player.update(state, &input.command, dt);
+            if let Some(replayed) = self.inputs.get_mut(input.tick) {
+                replayed.predicted = Some(avatar.player.position);
+            }
+        }
+        avatar.player.take_events();
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        let commands = self.inputs.recent(INPUT_REDUNDANCY);
+        let payload = if commands.is_empty() {
+            Vec::new()
+        } else {
+            InputPacket {
+                tick: self.input_tick,
+                commands,
+            }
+            .encode()
+        };
+        let packet = self.connection.build_packet(self.time, &payload);
+        self.transport.send(self.connection.addr, &packet)
+    }
+}
+
+// makes `world` look like the server's
+fn apply_snapshot(world: &mut World, snapshot: &Snapshot) {
+    world.tick = snapshot.tick;
+    world
+        .avatars
+        .retain(|avatar| snapshot.players.iter().any(|player| player.id == avatar.player.id));
+    for player in snapshot.players.iter() {
+        match world.avatar_mut(player.id) {
+            Some(avatar) => avatar.player.set_state(player),
+            None => world.insert_player(player.id).player.set_state(player),
+        }
+    }
+
+    let state = &mut world.state;
+    for body in snapshot.bodies.iter() {
+        let index = body.object as usize;
+        let Some(rigid_body) = state.rigid_body_mut(index) else {
+            continue;
+        };
+        rigid_body.set_transform(Transform::new(body.position, body.rotation));
+        state.object_moved(index);
+    }
+
+    state.projectiles = snapshot
+        .projectiles
+        .iter()
+        .map(|projectile| {
+            let [r, g, b] = projectile.color;
+            Projectile {
+                config: ProjectileConfig {
+                    radius: projectile.radius,
+                    color: Color::from_rgba(r, g, b, 255),
+                    ..ProjectileConfig::rocket()
+                },
+                position: projectile.position,
+                velocity: projectile.velocity,
+                age: 0.,
+                owner: None,
+            }
+        })
+        .collect();
+}
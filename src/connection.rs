@@ -0,0 +1,301 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+use crate::protocol::{DecodeError, MAX_PACKET_SIZE, PROTOCOL_ID, Reader, Writer};
+
+/// Seconds of silence after which the other end is taken to be gone.
+pub const DEFAULT_TIMEOUT: f64 = 10.;
+/// Unacked reliable messages are sent again after this many seconds.
+pub const RESEND_INTERVAL: f64 = 0.1;
+/// Bytes of every packet before its messages and payload: protocol id,
+/// sequence, ack, ack bits and message count.
+pub const HEADER_SIZE: usize = 4 + 2 + 2 + 4 + 1;
+/// Largest reliable message that fits in a packet on its own, after the
+/// header and the message's id and length.
+pub const MAX_MESSAGE_SIZE: usize = MAX_PACKET_SIZE - HEADER_SIZE - 2 - 2;
+// sent packets remembered for acks; older ones count as lost
+const SENT_WINDOW: usize = 64;
+// weight of each new round trip sample in the smoothed rtt
+const RTT_SMOOTHING: f32 = 0.1;
+
+// whether sequence number `a` is newer than `b`, allowing for wraparound
+fn sequence_greater(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+struct SentPacket {
+    sequence: u16,
+    time: f64,
+    messages: Vec<u16>,
+}
+
+struct PendingMessage {
+    id: u16,
+    data: Vec<u8>,
+    last_sent: Option<f64>,
+}
+
+/// One end of a conversation over an unreliable [`crate::transport::Transport`].
+///
+/// Every packet carries a sequence number and acks for the last 33 packets
+/// received. On top of that rides a reliable, ordered channel of messages,
+/// resent until acked, and an unreliable payload that is simply lost with
+/// its packet. Timing is driven by the caller's clock, in seconds.
+pub struct Connection {
+    pub addr: SocketAddr,
+    local_sequence: u16,
+    // newest sequence received, and which of the 32 before it were
+    remote_sequence: u16,
+    received_bits: u32,
+    has_received: bool,
+    sent: VecDeque<SentPacket>,
+    newest_acked: Option<u16>,
+    outgoing: VecDeque<PendingMessage>,
+    next_message_id: u16,
+    next_receive_id: u16,
+    incoming: HashMap<u16, Vec<u8>>,
+    rtt: Option<f32>,
+    last_received: f64,
+    last_sent: f64,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+impl Connection {
+    pub fn new(addr: SocketAddr, now: f64) -> Connection {
+        Connection {
+            addr,
+            local_sequence: 0,
+            // acks a sequence the other end won't use until long after it
+            // has been heard from, so nothing is acked by mistake
+            remote_sequence: u16::MAX,
+            received_bits: 0,
+            has_received: false,
+            sent: VecDeque::new(),
+            newest_acked: None,
+            outgoing: VecDeque::new(),
+            next_message_id: 0,
+            next_receive_id: 0,
+            incoming: HashMap::new(),
+            rtt: None,
+            last_received: now,
+            last_sent: now,
+            bytes_sent: 0,
+            bytes_received: 0,
+        }
+    }
+
+    /// Queues a message to be delivered reliably and in order. One over
+    /// [`MAX_MESSAGE_SIZE`] could never be sent and would hold up every
+    /// message after it, so it is dropped.
+    pub fn send_message(&mut self, data: Vec<u8>) {
+        debug_assert!(data.len() <= MAX_MESSAGE_SIZE, "{} byte message", data.len());
+        if data.len() > MAX_MESSAGE_SIZE {
+            return;
+        }
+        self.outgoing.push_back(PendingMessage {
+            id: self.next_message_id,
+            data,
+            last_sent: None,
+        });
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+    }
+
+    /// Whether some reliable message is waiting to be sent or resent.
+    pub fn has_due_messages(&self, now: f64) -> bool {
+        self.outgoing.iter().any(|message| is_due(message, now))
+    }
+
+    /// Whether every reliable message sent so far has been acked.
+    pub fn all_acked(&self) -> bool {
+        self.outgoing.is_empty()
+    }
+
+    /// Sequence number the next packet built will carry.
+    pub fn next_sequence(&self) -> u16 {
+        self.local_sequence
+    }
+
+    /// Sequence number of the newest packet the other end has acked.
+    pub fn newest_acked(&self) -> Option<u16> {
+        self.newest_acked
+    }
+
+    /// Builds the next packet: the header, any reliable messages that are
+    /// due, then `payload`. Messages that don't fit wait for a later packet.
+    pub fn build_packet(&mut self, now: f64, payload: &[u8]) -> Vec<u8> {
+        let mut messages = Writer::new();
+        let mut ids = Vec::new();
+        let mut budget = MAX_PACKET_SIZE.saturating_sub(HEADER_SIZE + payload.len());
+        for message in self.outgoing.iter_mut() {
+            let size = 4 + message.data.len();
+            if !is_due(message, now) {
+                continue;
+            }
+            if ids.len() == u8::MAX as usize || size > budget {
+                break;
+            }
+            messages.u16(message.id);
+            messages.bytes(&message.data);
+            message.last_sent = Some(now);
+            ids.push(message.id);
+            budget -= size;
+        }
+
+        let mut w = Writer::new();
+        w.u32(PROTOCOL_ID);
+        w.u16(self.local_sequence);
+        w.u16(self.remote_sequence);
+        w.u32(self.received_bits);
+        w.u8(ids.len() as u8);
+        w.raw(&messages.into_bytes());
+        w.raw(payload);
+
+        self.sent.push_back(SentPacket {
+            sequence: self.local_sequence,
+            time: now,
+            messages: ids,
+        });
+        if self.sent.len() > SENT_WINDOW {
+            self.sent.pop_front();
+        }
+        self.local_sequence = self.local_sequence.wrapping_add(1);
+        self.last_sent = now;
+        let packet = w.into_bytes();
+        self.bytes_sent += packet.len() as u64;
+        packet
+    }
+
+    /// Takes in a packet from the other end: records it for acking, handles
+    /// the acks it carries and queues its reliable messages. Returns its
+    /// unreliable payload, or None for a duplicate. Packets bigger than
+    /// [`MAX_PACKET_SIZE`] are never built, so those are rejected.
+    pub fn receive(&mut self, now: f64, packet: &[u8]) -> Result<Option<Vec<u8>>, DecodeError> {
+        if packet.len() > MAX_PACKET_SIZE {
+            return Err(DecodeError::Malformed);
+        }
+        let mut r = Reader::new(packet);
+        if r.u32()? != PROTOCOL_ID {
+            return Err(DecodeError::WrongProtocol);
+        }
+        let sequence = r.u16()?;
+        let ack = r.u16()?;
+        let ack_bits = r.u32()?;
+        let count = r.u8()?;
+        let mut messages = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            messages.push((r.u16()?, r.bytes()?));
+        }
+        let payload = r.rest();
+
+        if !self.record_received(sequence) {
+            return Ok(None);
+        }
+        self.last_received = now;
+        self.bytes_received += packet.len() as u64;
+        self.process_acks(now, ack, ack_bits);
+        for (id, data) in messages {
+            // ids already delivered are resends whose ack got lost
+            if id == self.next_receive_id || sequence_greater(id, self.next_receive_id) {
+                self.incoming.entry(id).or_insert_with(|| data.to_vec());
+            }
+        }
+        Ok(Some(payload.to_vec()))
+    }
+
+    /// Next reliable message, in the order they were sent.
+    pub fn receive_message(&mut self) -> Option<Vec<u8>> {
+        let message = self.incoming.remove(&self.next_receive_id)?;
+        self.next_receive_id = self.next_receive_id.wrapping_add(1);
+        Some(message)
+    }
+
+    // returns false if the packet was seen before or is too old to tell
+    fn record_received(&mut self, sequence: u16) -> bool {
+        if !self.has_received {
+            self.has_received = true;
+            self.remote_sequence = sequence;
+            self.received_bits = 0;
+            return true;
+        }
+        if sequence_greater(sequence, self.remote_sequence) {
+            let shift = sequence.wrapping_sub(self.remote_sequence) as u32;
+            self.received_bits = if shift > 32 {
+                0
+            } else {
+                (((self.received_bits as u64) << shift) | (1 << (shift - 1))) as u32
+            };
+            self.remote_sequence = sequence;
+            return true;
+        }
+        let age = self.remote_sequence.wrapping_sub(sequence) as u32;
+        if age == 0 || age > 32 {
+            return false;
+        }
+        let bit = 1 << (age - 1);
+        if self.received_bits & bit != 0 {
+            return false;
+        }
+        self.received_bits |= bit;
+        true
+    }
+
+    fn process_acks(&mut self, now: f64, ack: u16, ack_bits: u32) {
+        let acked = |sequence: u16| {
+            let age = ack.wrapping_sub(sequence) as u32;
+            age == 0 || (1..=32).contains(&age) && ack_bits & (1 << (age - 1)) != 0
+        };
+        let mut delivered = Vec::new();
+        let mut sample = None;
+        let newest_acked = &mut self.newest_acked;
+        self.sent.retain(|packet| {
+            if !acked(packet.sequence) {
+                return true;
+            }
+            if newest_acked.is_none_or(|newest| sequence_greater(packet.sequence, newest)) {
+                *newest_acked = Some(packet.sequence);
+            }
+            delivered.extend(packet.messages.iter().copied());
+            sample = Some((now - packet.time) as f32);
+            false
+        });
+        if let Some(sample) = sample {
+            self.rtt = Some(match self.rtt {
+                Some(rtt) => rtt + (sample - rtt) * RTT_SMOOTHING,
+                None => sample,
+            });
+        }
+        if !delivered.is_empty() {
+            self.outgoing.retain(|message| !delivered.contains(&message.id));
+        }
+    }
+
+    /// Smoothed round trip time in seconds, once a packet has been acked.
+    pub fn rtt(&self) -> Option<f32> {
+        self.rtt
+    }
+
+    /// Seconds since the last packet was sent.
+    pub fn since_sent(&self, now: f64) -> f64 {
+        now - self.last_sent
+    }
+
+    /// Whether nothing has been heard for `timeout` seconds.
+    pub fn timed_out(&self, now: f64, timeout: f64) -> bool {
+        now - self.last_received > timeout
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+}
+
+fn is_due(message: &PendingMessage, now: f64) -> bool {
+    message
+        .last_sent
+        .is_none_or(|sent| now - sent >= RESEND_INTERVAL)
+}
@@ -0,0 +1,404 @@
+use macroquad::prelude::*;
+use nalgebra::{Isometry3, Unit, Vector3};
+
+use parry3d::bounding_volume::BoundingVolume;
+use parry3d::query::{
+    ContactManifold, DefaultQueryDispatcher, PersistentQueryDispatcher, ShapeCastOptions,
+    cast_shapes, intersection_test,
+};
+use parry3d::query::contact::{Contact, contact};
+use parry3d::shape::{Capsule, Shape};
+
+use crate::game::State;
+
+// slide iterations per move, i.e. how many surfaces one move can bend around
+const MAX_SLIDES: usize = 4;
+// overlaps resolved before moving, for when something starts out stuck
+const MAX_DEPENETRATION: usize = 4;
+// moves shorter than this are dropped
+const MIN_MOVE: f32 = 1e-5;
+
+pub struct ControllerConfig {
+    pub radius: f32,
+    /// Half the length of the capsule's straight section.
+    pub half_height: f32,
+    /// Tallest ledge the character walks up onto without jumping.
+    pub max_step_height: f32,
+    /// Steepest slope, in radians, that counts as ground.
+    pub max_slope: f32,
+    /// How far down the character is pulled to stay on descending ground.
+    pub snap_distance: f32,
+    /// Gap kept between the capsule and whatever it touches.
+    pub skin: f32,
+}
+
+impl Default for ControllerConfig {
+    fn default() -> ControllerConfig {
+        ControllerConfig {
+            radius: 0.25,
+            half_height: 0.25,
+            max_step_height: 0.35,
+            max_slope: 50f32.to_radians(),
+            snap_distance: 0.35,
+            skin: 0.01,
+        }
+    }
+}
+
+pub struct MoveResult {
+    /// New feet position.
+    pub position: Vec3,
+    pub grounded: bool,
+    pub ground_normal: Vec3,
+    pub hit_ceiling: bool,
+    pub hit_wall: bool,
+}
+
+struct Hit {
+    // fraction of the attempted move completed before touching
+    toi: f32,
+    // surface normal, pointing back at the character
+    normal: Vec3,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Slide {
+    // walking: steep surfaces act as vertical walls so they can't be climbed
+    Walk,
+    // falling: stop on the first walkable surface instead of sliding down it
+    Fall,
+    // anything else: slide along whatever is hit
+    Free,
+}
+
+/// Moves a capsule through `State::objects` by sweeping it along the desired
+/// motion and sliding along whatever it touches. Positions are feet
+/// positions, i.e. the bottom of the capsule.
+pub struct CharacterController {
+    pub config: ControllerConfig,
+    shape: Capsule,
+}
+
+impl CharacterController {
+    pub fn new(config: ControllerConfig) -> CharacterController {
+        let shape = Capsule::new_y(config.half_height, config.radius);
+        CharacterController { config, shape }
+    }
+
+    pub fn shape(&self) -> &Capsule {
+        &self.shape
+    }
+
+    pub fn height(&self) -> f32 {
+        2. * (self.config.half_height + self.config.radius)
+    }
+
+    pub fn collider_transform(&self, position: Vec3) -> Isometry3<f32> {
+        let center = position + vec3(0., self.config.half_height + self.config.radius, 0.);
+        Isometry3::translation(center.x, center.y, center.z)
+    }
+
+    /// Changes the capsule's height, keeping the feet where they are. Check
+    /// [`CharacterController::fits`] first when growing.
+    pub fn set_half_height(&mut self, half_height: f32) {
+        self.config.half_height = half_height;
+        self.shape = Capsule::new_y(half_height, self.config.radius);
+    }
+
+    /// Whether a capsule with the given half height would be free of overlaps
+    /// at `position`.
+    pub fn fits(&self, state: &State, position: Vec3, half_height: f32) -> bool {
+        let shape = Capsule::new_y(half_height, self.config.radius);
+        let center = position + vec3(0., half_height + self.config.radius, 0.);
+        let tx = Isometry3::translation(center.x, center.y, center.z);
+        let aabb = shape.compute_aabb(&tx);
+        state.objects_near(&aabb).all(|(_, obj)| {
+            let obj_tx = obj.get_transform().isometry();
+            !intersection_test(&tx, &shape, &obj_tx, obj.get_collider()).unwrap_or(false)
+        })
+    }
+
+    /// Objects within `margin` of the capsule at `position`, with the
+    /// closest point on each and the normal there, pointing at the capsule.
+    pub fn touching(&self, state: &State, position: Vec3, margin: f32) -> Vec<(usize, Vec3, Vec3)> {
+        let tx = self.collider_transform(position);
+        let aabb = self.shape.compute_aabb(&tx).loosened(margin);
+        state
+            .objects_near(&aabb)
+            .filter_map(|(index, obj)| {
+                let obj_tx = obj.get_transform().isometry();
+                let hit = contact(&tx, &self.shape, &obj_tx, obj.get_collider(), margin).ok()??;
+                let point = vec3(hit.point2.x, hit.point2.y, hit.point2.z);
+                let normal = -vec3(hit.normal1.x, hit.normal1.y, hit.normal1.z);
+                Some((index, point, normal))
+            })
+            .collect()
+    }
+
+    pub fn is_walkable(&self, normal: Vec3) -> bool {
+        normal.y >= self.config.max_slope.cos() - 1e-4
+    }
+
+    /// Moves from `position` by `translation`. `grounded` is whether the
+    /// character stood on the ground before this move; it enables stepping
+    /// up ledges and snapping down onto descending ground.
+    pub fn move_character(
+        &self,
+        state: &State,
+        position: Vec3,
+        translation: Vec3,
+        grounded: bool,
+    ) -> MoveResult {
+        let start = self.depenetrate(state, position);
+        let horizontal = vec3(translation.x, 0., translation.z);
+        let mut hit_wall = false;
+
+        // horizontal first, so walking never depends on the vertical speed
+        let mode = if grounded { Slide::Walk } else { Slide::Free };
+        let (mut position, blocked) = self.slide(state, start, horizontal, mode);
+        if blocked {
+            hit_wall = true;
+            // at low speeds one tick doesn't carry the capsule far enough over the
+            // ledge to stand on it, so the step always goes at least a radius past
+            // where the flat move stopped
+            let reach = horizontal_distance(position, start) + self.config.radius;
+            let step = horizontal.normalize_or_zero() * horizontal.length().max(reach);
+            if grounded
+                && let Some(stepped) = self.step_up(state, start, step)
+                && horizontal_distance(stepped, start) > horizontal_distance(position, start) + MIN_MOVE
+            {
+                position = stepped;
+                hit_wall = false;
+            }
+        }
+
+        let mut hit_ceiling = false;
+        if translation.y > 0. {
+            let (moved, blocked) = self.slide(state, position, vec3(0., translation.y, 0.), Slide::Free);
+            hit_ceiling = blocked;
+            position = moved;
+        } else if translation.y < 0. {
+            position = self.slide(state, position, vec3(0., translation.y, 0.), Slide::Fall).0;
+        }
+
+        // keep walking down slopes and stairs instead of launching off them.
+        // sliding rather than dropping straight down lets the round bottom
+        // of the capsule roll off the edge of a ledge onto the ground below
+        if grounded && translation.y <= 0. {
+            let down = vec3(0., -self.config.snap_distance, 0.);
+            let (snapped, _) = self.slide(state, position, down, Slide::Fall);
+            if self.ground(state, snapped).is_some() {
+                position = snapped;
+            }
+        }
+
+        let ground = self.ground(state, position);
+
+        MoveResult {
+            position,
+            grounded: ground.is_some(),
+            ground_normal: ground.map_or(Vec3::Y, |hit| hit.normal),
+            hit_ceiling,
+            hit_wall,
+        }
+    }
+
+    // walkable ground right under the feet at `position`, if any
+    fn ground(&self, state: &State, position: Vec3) -> Option<Hit> {
+        self.cast(state, position, vec3(0., -2. * self.config.skin, 0.))
+            .filter(|hit| self.is_walkable(hit.normal))
+    }
+
+    // sweeps along `translation`, bending the move along each surface hit.
+    // returns the end position and whether anything blocked the move
+    fn slide(&self, state: &State, start: Vec3, translation: Vec3, mode: Slide) -> (Vec3, bool) {
+        let mut position = start;
+        let mut remaining = translation;
+        let mut planes: Vec<Vec3> = Vec::new();
+        let mut blocked = false;
+
+        for _ in 0..MAX_SLIDES {
+            if remaining.length() < MIN_MOVE {
+                break;
+            }
+            let Some(hit) = self.cast(state, position, remaining) else {
+                position += remaining;
+                break;
+            };
+            position += remaining * hit.toi;
+            remaining *= 1. - hit.toi;
+
+            let walkable = self.is_walkable(hit.normal);
+            if mode == Slide::Fall && walkable {
+                break;
+            }
+            let mut normal = hit.normal;
+            if mode == Slide::Walk && !walkable {
+                normal = vec3(normal.x, 0., normal.z).normalize_or_zero();
+                if normal == Vec3::ZERO {
+                    break;
+                }
+            }
+            if !walkable {
+                blocked = true;
+            }
+
+            remaining -= normal * remaining.dot(normal);
+            // pushed back into an earlier surface: run along the crease between the two
+            if let Some(previous) = planes.last()
+                && remaining.dot(*previous) < 0.
+            {
+                let crease = previous.cross(normal).normalize_or_zero();
+                remaining = crease * remaining.dot(crease);
+            }
+            planes.push(normal);
+        }
+
+        (position, blocked)
+    }
+
+    // lift by the step height, move, and put the character back down. only
+    // succeeds if it lands on walkable ground
+    fn step_up(&self, state: &State, start: Vec3, horizontal: Vec3) -> Option<Vec3> {
+        let up = vec3(0., self.config.max_step_height, 0.);
+        let raised = start + up * self.cast(state, start, up).map_or(1., |hit| hit.toi);
+        let (moved, _) = self.slide(state, raised, horizontal, Slide::Walk);
+
+        let down = vec3(0., start.y - raised.y - self.config.skin, 0.);
+        let hit = self.cast(state, moved, down)?;
+        if !self.is_walkable(hit.normal) {
+            return None;
+        }
+        Some(moved + down * hit.toi)
+    }
+
+    // earliest surface the capsule would move into while sweeping from
+    // `position` by `translation`. surfaces it only grazes are ignored
+    fn cast(&self, state: &State, position: Vec3, translation: Vec3) -> Option<Hit> {
+        let direction = translation.normalize_or_zero();
+        if direction == Vec3::ZERO {
+            return None;
+        }
+
+        let start_tx = self.collider_transform(position);
+        let sweep = self
+            .shape
+            .compute_aabb(&start_tx)
+            .merged(&self.shape.compute_aabb(&self.collider_transform(position + translation)))
+            .loosened(2. * self.config.skin);
+        let velocity = Vector3::new(translation.x, translation.y, translation.z);
+        let options = ShapeCastOptions {
+            max_time_of_impact: 1.,
+            target_distance: self.config.skin,
+            stop_at_penetration: false,
+            compute_impact_geometry_on_penetration: true,
+        };
+
+        let mut best: Option<Hit> = None;
+        for (_, obj) in state.objects_near(&sweep) {
+            let obj_tx = obj.get_transform().isometry();
+            let Ok(Some(hit)) = cast_shapes(
+                &start_tx,
+                &velocity,
+                &self.shape,
+                &obj_tx,
+                &Vector3::zeros(),
+                obj.get_collider(),
+                options,
+            ) else {
+                continue;
+            };
+            // the capsule is never rotated, so its local normal is the world normal
+            let normal = -vec3(hit.normal1.x, hit.normal1.y, hit.normal1.z);
+            if normal.dot(direction) > -1e-4 {
+                continue;
+            }
+            if best.as_ref().is_none_or(|b| hit.time_of_impact < b.toi) {
+                best = Some(Hit {
+                    toi: hit.time_of_impact.clamp(0., 1.),
+                    normal,
+                });
+            }
+        }
+        best
+    }
+
+    // pushes the capsule out of anything it overlaps, deepest overlap first
+    fn depenetrate(&self, state: &State, position: Vec3) -> Vec3 {
+        let mut position = position;
+        for _ in 0..MAX_DEPENETRATION {
+            let player_tx = self.collider_transform(position);
+            let aabb = self.shape.compute_aabb(&player_tx);
+
+            let mut deepest: Option<Contact> = None;
+            for (_, obj) in state.objects_near(&aabb) {
+                let obj_tx = obj.get_transform().isometry();
+                let obj_shape = obj.get_collider();
+                let oc = if obj_shape.as_composite_shape().is_some() {
+                    deepest_mesh_contact(&player_tx, &self.shape, &obj_tx, obj_shape)
+                } else {
+                    contact(&player_tx, &self.shape, &obj_tx, obj_shape, 0.).ok().flatten()
+                };
+                if let Some(oc) = oc
+                    && oc.dist < 0.
+                    && deepest.is_none_or(|d| oc.dist < d.dist)
+                {
+                    deepest = Some(oc);
+                }
+            }
+
+            let Some(oc) = deepest else {
+                break;
+            };
+            let normal = vec3(oc.normal2.x, oc.normal2.y, oc.normal2.z);
+            position += normal * (self.config.skin - oc.dist);
+        }
+        position
+    }
+}
+
+impl Default for CharacterController {
+    fn default() -> CharacterController {
+        CharacterController::new(ControllerConfig::default())
+    }
+}
+
+fn horizontal_distance(a: Vec3, b: Vec3) -> f32 {
+    vec2(a.x - b.x, a.z - b.z).length()
+}
+
+// Deepest contact between the capsule and a triangle mesh, in world space. Goes
+// through contact manifolds rather than `contact` because only the manifold
+// path applies the mesh's internal edge normals, which is what stops the
+// capsule from catching on the edges between adjacent triangles.
+fn deepest_mesh_contact(
+    player_tx: &Isometry3<f32>,
+    player_shape: &Capsule,
+    obj_tx: &Isometry3<f32>,
+    obj_shape: &dyn Shape,
+) -> Option<Contact> {
+    let mut manifolds: Vec<ContactManifold<(), ()>> = Vec::new();
+    let pos12 = player_tx.inv_mul(obj_tx);
+    DefaultQueryDispatcher
+        .contact_manifolds(&pos12, player_shape, obj_shape, 0., &mut manifolds, &mut None)
+        .ok()?;
+
+    let mut deepest: Option<Contact> = None;
+    for manifold in manifolds.iter() {
+        let Some(point) = manifold.find_deepest_contact() else {
+            continue;
+        };
+        if point.dist > 0. || deepest.is_some_and(|c| c.dist <= point.dist) {
+            continue;
+        }
+        let subshape_tx = obj_tx * manifold.subshape_pos2.unwrap_or_default();
+        deepest = Some(Contact::new(
+            player_tx * point.local_p1,
+            subshape_tx * point.local_p2,
+            player_tx * Unit::new_unchecked(manifold.local_n1),
+            subshape_tx * Unit::new_unchecked(manifold.local_n2),
+            point.dist,
+        ));
+    }
+    deepest
+}
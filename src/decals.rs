@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+
+use macroquad::prelude::*;
+
+use crate::game::{RayHit, State};
+use crate::mesh::MAX_CHUNK_INDICES;
+
+/// Marks left where shots hit. Decals stick to the object they hit, so they
+/// follow it if it moves, and fade out after a while; past `capacity` the
+/// oldest are dropped early.
+pub struct Decals {
+    /// At most 2500.
+    pub capacity: usize,
+    /// Seconds a decal lasts, the last quarter of it spent fading.
+    pub lifetime: f32,
+    pub size: f32,
+    pub color: Color,
+    decals: VecDeque<Decal>,
+    quad: Mesh,
+}
+
+struct Decal {
+    object: usize,
+    // in the object's space
+    position: Vec3,
+    rotation: Quat,
+    age: f32,
+}
+
+// lifted off the surface against z-fighting
+const SURFACE_OFFSET: f32 = 0.005;
+// a few draw calls' worth
+const MAX_CAPACITY: usize = 2500;
+
+impl Decals {
+    pub fn new() -> Decals {
+        Decals {
+            capacity: 256,
+            lifetime: 20.,
+            size: 0.08,
+            color: Color::new(0.05, 0.05, 0.05, 0.9),
+            decals: VecDeque::new(),
+            quad: unit_quad(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.decals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decals.is_empty()
+    }
+
+    /// Leaves a mark at `hit`, facing out of the surface.
+    pub fn add(&mut self, state: &State, hit: &RayHit) {
+        if hit.normal == Vec3::ZERO {
+            // the shot started inside the object, there is no surface to mark
+            return;
+        }
+        if self.decals.len() >= self.capacity.min(MAX_CAPACITY) {
+            self.decals.pop_front();
+        }
+        let transform = state.objects()[hit.object].get_transform();
+        let inverse = transform.rotation.inverse();
+        let normal = inverse * hit.normal;
+        // random-looking but stable spin, so marks don't all line up
+        let spin = hit.point.dot(vec3(12.9898, 78.233, 37.719)).sin() * 43758.545;
+        self.decals.push_back(Decal {
+            object: hit.object,
+            position: inverse * (hit.point - transform.position) + normal * SURFACE_OFFSET,
+            rotation: Quat::from_rotation_arc(Vec3::Z, normal) * Quat::from_rotation_z(spin),
+            age: 0.,
+        });
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for decal in self.decals.iter_mut() {
+            decal.age += dt;
+        }
+        let lifetime = self.lifetime;
+        self.decals.retain(|decal| decal.age < lifetime);
+    }
+
+    pub fn clear(&mut self) {
+        self.decals.clear();
+    }
+
+    /// Draws every decal, batched into as few calls as macroquad takes,
+    /// with its default material so they are unlit.
+    pub fn draw(&self, state: &State) {
+        if self.decals.is_empty() {
+            return;
+        }
+        gl_use_default_material();
+        let fade_start = self.lifetime * 0.75;
+        let per_batch = MAX_CHUNK_INDICES / self.quad.indices.len();
+        let mut mesh = Mesh {
+            vertices: Vec::with_capacity(per_batch * 4),
+            indices: Vec::with_capacity(per_batch * 6),
+            texture: None,
+        };
+        for decal in self.decals.iter() {
+            let Some(obj) = state.objects().get(decal.object) else {
+                continue;
+            };
+            if mesh.indices.len() + self.quad.indices.len() > MAX_CHUNK_INDICES {
+                draw_mesh(&mesh);
+                mesh.vertices.clear();
+                mesh.indices.clear();
+            }
+            let fade = 1. - ((decal.age - fade_start) / (self.lifetime - fade_start)).clamp(0., 1.);
+            let color = Color {
+                a: self.color.a * fade,
+                ..self.color
+            };
+            let matrix = obj.get_transform().matrix()
+                * Mat4::from_scale_rotation_translation(
+                    Vec3::splat(self.size),
+                    decal.rotation,
+                    decal.position,
+                );
+            let base = mesh.vertices.len() as u16;
+            mesh.vertices.extend(self.quad.vertices.iter().map(|v| Vertex {
+                position: matrix.transform_point3(v.position),
+                color: color.into(),
+                ..*v
+            }));
+            mesh.indices.extend(self.quad.indices.iter().map(|i| base + i));
+        }
+        if !mesh.indices.is_empty() {
+            draw_mesh(&mesh);
+        }
+    }
+}
+
+impl Default for Decals {
+    fn default() -> Decals {
+        Decals::new()
+    }
+}
+
+// square in the xy plane facing +z, one unit across
+fn unit_quad() -> Mesh {
+    let corners = [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)];
+    Mesh {
+        vertices: corners
+            .iter()
+            .map(|(x, y)| Vertex::new(*x, *y, 0., x + 0.5, 0.5 - y, WHITE))
+            .collect(),
+        indices: vec![0, 1, 2, 0, 2, 3],
+        texture: None,
+    }
+}
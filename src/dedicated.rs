@@ -0,0 +1,233 @@
+use std::fmt;
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::connection::DEFAULT_TIMEOUT;
+use crate::rewind::DEFAULT_MAX_REWIND;
+use crate::server::{DEFAULT_PORT, DEFAULT_RELEVANCE_DISTANCE, ServerConfig, ServerEvent};
+use crate::timestep::DEFAULT_TICK_RATE;
+
+pub const DEFAULT_SERVER_CONFIG_PATH: &str = "config/server.ron";
+
+/// How a match is played, and so when it's over.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub enum GameMode {
+    /// Everyone for themselves. The match ends when someone reaches
+    /// `frag_limit` kills or after `time_limit` seconds, whichever comes
+    /// first; zero turns either limit off.
+    Deathmatch { frag_limit: u32, time_limit: f32 },
+    /// Nobody keeps score and the match never ends, so the level only
+    /// changes when an admin says so.
+    Sandbox,
+}
+
+/// Settings for a dedicated server as written in a `.ron` file, e.g.
+///
+/// ```ron
+/// ServerSettings(
+///     port: 27960,
+///     max_players: 8,
+///     maps: ["levels/test.ron", "levels/arena.ron"],
+///     mode: Deathmatch(frag_limit: 20, time_limit: 600),
+/// )
+/// ```
+///
+/// Anything left out keeps its default.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ServerSettings {
+    pub port: u16,
+    pub tick_rate: u32,
+    pub max_players: usize,
+    /// Levels played in turn, going back to the first after the last.
+    pub maps: Vec<String>,
+    pub mode: GameMode,
+    /// See [`ServerConfig::max_rewind`].
+    pub max_rewind: f32,
+    /// See [`ServerConfig::relevance_distance`].
+    pub relevance_distance: f32,
+    /// Seconds without a packet before a client is dropped.
+    pub timeout: f64,
+}
+
+impl Default for ServerSettings {
+    fn default() -> ServerSettings {
+        ServerSettings {
+            port: DEFAULT_PORT,
+            tick_rate: DEFAULT_TICK_RATE,
+            max_players: 16,
+            maps: vec!["levels/test.ron".to_string()],
+            mode: GameMode::Deathmatch {
+                frag_limit: 20,
+                time_limit: 600.,
+            },
+            max_rewind: DEFAULT_MAX_REWIND,
+            relevance_distance: DEFAULT_RELEVANCE_DISTANCE,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io {
+        path: String,
+        error: std::io::Error,
+    },
+    Parse {
+        path: String,
+        error: Box<ron::error::SpannedError>,
+    },
+    /// The map list is empty.
+    NoMaps { path: String },
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Io { path, error } => write!(f, "{}: {}", path, error),
+            SettingsError::Parse { path, error } => write!(
+                f,
+                "{}:{}:{}: {}",
+                path, error.span.start.line, error.span.start.col, error.code
+            ),
+            SettingsError::NoMaps { path } => write!(f, "{}: no maps to play", path),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl ServerSettings {
+    pub fn parse(path: &str, source: &str) -> Result<ServerSettings, SettingsError> {
+        let settings: ServerSettings = ron::from_str(source).map_err(|error| SettingsError::Parse {
+            path: path.to_string(),
+            error: Box::new(error),
+        })?;
+        if settings.maps.is_empty() {
+            return Err(SettingsError::NoMaps {
+                path: path.to_string(),
+            });
+        }
+        Ok(settings)
+    }
+
+    pub fn from_file(path: &str) -> Result<ServerSettings, SettingsError> {
+        let source = fs::read_to_string(path).map_err(|error| SettingsError::Io {
+            path: path.to_string(),
+            error,
+        })?;
+        ServerSettings::parse(path, &source)
+    }
+
+    /// The server's part of the settings, starting on `level`.
+    pub fn server_config(&self, level: &str) -> ServerConfig {
+        ServerConfig {
+            max_players: self.max_players,
+            timeout: self.timeout,
+            tick_rate: self.tick_rate.max(1),
+            max_rewind: self.max_rewind,
+            relevance_distance: self.relevance_distance,
+            level: level.to_string(),
+        }
+    }
+}
+
+/// Which map is up and which comes next.
+pub struct MapRotation {
+    maps: Vec<String>,
+    current: usize,
+}
+
+impl MapRotation {
+    /// Starts on the first of `maps`, which mustn't be empty.
+    pub fn new(maps: Vec<String>) -> MapRotation {
+        assert!(!maps.is_empty(), "a map rotation needs maps");
+        MapRotation { maps, current: 0 }
+    }
+
+    pub fn current(&self) -> &str {
+        &self.maps[self.current]
+    }
+
+    /// Moves on to the next map, wrapping around, and returns it.
+    pub fn advance(&mut self) -> &str {
+        self.current = (self.current + 1) % self.maps.len();
+        self.current()
+    }
+}
+
+/// Score keeping for one match on one level, fed the server's events.
+pub struct Match {
+    mode: GameMode,
+    elapsed: f32,
+    // kills minus suicides, by player id
+    frags: Vec<(u32, i32)>,
+}
+
+impl Match {
+    pub fn new(mode: GameMode) -> Match {
+        Match {
+            mode,
+            elapsed: 0.,
+            frags: Vec::new(),
+        }
+    }
+
+    /// Counts deaths towards the score and forgets players who left.
+    pub fn record(&mut self, event: &ServerEvent) {
+        match event {
+            ServerEvent::Died { id, damage } => match damage.source {
+                Some(killer) if killer != *id => *self.frags_mut(killer) += 1,
+                // falling, the kill plane or one's own rocket
+                _ => *self.frags_mut(*id) -= 1,
+            },
+            ServerEvent::Left { id, .. } => self.frags.retain(|(player, _)| player != id),
+            _ => {}
+        }
+    }
+
+    /// Runs the clock on by `dt` seconds.
+    pub fn advance(&mut self, dt: f32) {
+        self.elapsed += dt;
+    }
+
+    /// Seconds since the match started.
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+
+    pub fn is_over(&self) -> bool {
+        match self.mode {
+            GameMode::Deathmatch {
+                frag_limit,
+                time_limit,
+            } => {
+                let leader = self.frags.iter().map(|(_, frags)| *frags).max().unwrap_or(0);
+                (frag_limit > 0 && leader >= frag_limit as i32)
+                    || (time_limit > 0. && self.elapsed >= time_limit)
+            }
+            GameMode::Sandbox => false,
+        }
+    }
+
+    /// Player ids and frags, best first. Players who haven't scored or died
+    /// aren't listed.
+    pub fn scores(&self) -> Vec<(u32, i32)> {
+        let mut scores = self.frags.clone();
+        scores.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        scores
+    }
+
+    fn frags_mut(&mut self, id: u32) -> &mut i32 {
+        let index = match self.frags.iter().position(|(player, _)| *player == id) {
+            Some(index) => index,
+            None => {
+                self.frags.push((id, 0));
+                self.frags.len() - 1
+            }
+        };
+        &mut self.frags[index].1
+    }
+}
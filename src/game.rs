@@ -1,12 +1,222 @@
+use macroquad::prelude::*;
+use nalgebra::{Point3, Vector3};
+use parry3d::bounding_volume::Aabb;
+use parry3d::query::Ray;
+
+use crate::broadphase::BroadPhase;
+use crate::lights::Lighting;
 use crate::objects::PhysicsObject;
+use crate::projectile::{Explosion, Projectile};
+use crate::rewind::Hitbox;
+use crate::rigidbody::{self, RigidBody, PLAYER_MASS};
+use crate::skybox::Skybox;
+
+pub const DEFAULT_KILL_HEIGHT: f32 = -100.;
+
+pub struct Spawn {
+    pub position: Vec3,
+    pub theta: f32,
+    pub phi: f32,
+}
+
+impl Default for Spawn {
+    fn default() -> Spawn {
+        Spawn {
+            position: vec3(0., 10., 0.),
+            theta: 0.,
+            phi: 0.,
+        }
+    }
+}
+
+/// Where a ray cast with [`State::cast_ray`] first hit something.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// Index of the object in [`State::objects`].
+    pub object: usize,
+    pub point: Vec3,
+    /// Surface normal at `point`, facing back towards the ray.
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
 pub struct State {
-    pub objects: Vec<Box<dyn PhysicsObject>>,
+    // only grown through `add_object`, so the broad phase and `bodies` keep up
+    objects: Vec<Box<dyn PhysicsObject>>,
+    broadphase: BroadPhase,
+    pub skybox: Option<Skybox>,
+    pub lighting: Lighting,
+    pub spawn: Spawn,
+    /// Players falling below this height die.
+    pub kill_height: f32,
+    // indices of the objects that are rigid bodies
+    bodies: Vec<usize>,
+    /// Projectiles in flight, stepped by [`State::update_projectiles`].
+    pub projectiles: Vec<Projectile>,
+    // bumped whenever a static object is added or moved
+    static_generation: u64,
 }
 
 impl State {
     pub fn new() -> State {
         State {
-            objects: Vec::new()
+            objects: Vec::new(),
+            broadphase: BroadPhase::new(),
+            skybox: None,
+            lighting: Lighting::default(),
+            spawn: Spawn::default(),
+            kill_height: DEFAULT_KILL_HEIGHT,
+            bodies: Vec::new(),
+            projectiles: Vec::new(),
+            static_generation: 0,
         }
     }
+
+    pub fn add_object(&mut self, obj: Box<dyn PhysicsObject>) -> usize {
+        let index = self.objects.len();
+        self.broadphase.insert(index, obj.aabb());
+        if obj.is_static() {
+            self.static_generation += 1;
+        }
+        if obj.rigid_body().is_some() {
+            self.bodies.push(index);
+        }
+        self.objects.push(obj);
+        index
+    }
+
+    /// Every object in the level, indexed as returned by
+    /// [`State::add_object`].
+    pub fn objects(&self) -> &[Box<dyn PhysicsObject>] {
+        &self.objects
+    }
+
+    /// Indices of the objects that are rigid bodies.
+    pub fn bodies(&self) -> &[usize] {
+        &self.bodies
+    }
+
+    /// The rigid body at `index`, if there is an object there and it is one.
+    /// Call [`State::object_moved`] after moving it.
+    pub(crate) fn rigid_body_mut(&mut self, index: usize) -> Option<&mut RigidBody> {
+        self.objects.get_mut(index)?.rigid_body_mut()
+    }
+
+    /// Must be called after the object at `index` changes its transform.
+    pub fn object_moved(&mut self, index: usize) {
+        self.broadphase.update(index, self.objects[index].aabb());
+        if self.objects[index].is_static() {
+            self.static_generation += 1;
+        }
+    }
+
+    /// Changes whenever the set of static objects or their transforms
+    /// change, so cached static geometry knows to rebuild.
+    pub fn static_generation(&self) -> u64 {
+        self.static_generation
+    }
+
+    /// Objects whose bounding boxes overlap `aabb`, as candidates for exact
+    /// collision queries.
+    pub fn objects_near<'a>(
+        &'a self,
+        aabb: &'a Aabb,
+    ) -> impl Iterator<Item = (usize, &'a dyn PhysicsObject)> + 'a {
+        self.broadphase
+            .query_aabb(aabb)
+            .map(|i| (i, &*self.objects[i]))
+    }
+
+    /// Nearest object hit by a ray from `origin` along the normalized
+    /// `direction`, at most `max_distance` away. A ray starting inside an
+    /// object hits it right away.
+    pub fn cast_ray(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        let end = origin + direction * max_distance;
+        let aabb = Aabb::new(
+            Point3::from(origin.min(end).to_array()),
+            Point3::from(origin.max(end).to_array()),
+        );
+        let ray = Ray::new(
+            Point3::from(origin.to_array()),
+            Vector3::from(direction.to_array()),
+        );
+
+        let mut nearest: Option<RayHit> = None;
+        for (index, obj) in self.objects_near(&aabb) {
+            let max_distance = nearest.map_or(max_distance, |hit| hit.distance);
+            let Some(hit) = obj.get_collider().cast_ray_and_get_normal(
+                &obj.get_transform().isometry(),
+                &ray,
+                max_distance,
+                true,
+            ) else {
+                continue;
+            };
+            nearest = Some(RayHit {
+                object: index,
+                point: origin + direction * hit.time_of_impact,
+                normal: Vec3::from(hit.normal.data.0[0]),
+                distance: hit.time_of_impact,
+            });
+        }
+        nearest
+    }
+
+    /// Steps every rigid body by `dt` seconds, then tightens the broad
+    /// phase around wherever they moved.
+    pub fn update_bodies(&mut self, dt: f32) {
+        rigidbody::step(self, dt);
+        self.broadphase.maintain();
+    }
+
+    /// Pushes the object at `index` by `impulse` at world position `point`,
+    /// if it is a rigid body.
+    pub fn apply_impulse(&mut self, index: usize, point: Vec3, impulse: Vec3) {
+        if let Some(body) = self.rigid_body_mut(index) {
+            body.apply_impulse(point, impulse);
+        }
+    }
+
+    /// Throws rigid bodies in reach of `explosion` away from it, as hard as
+    /// it would throw a player of the same mass.
+    pub fn apply_explosion(&mut self, explosion: &Explosion) {
+        let aabb = Aabb::from_half_extents(
+            Point3::from(explosion.position.to_array()),
+            Vector3::repeat(explosion.radius),
+        );
+        let hit: Vec<usize> = self
+            .objects_near(&aabb)
+            .filter(|(_, obj)| obj.rigid_body().is_some())
+            .map(|(index, _)| index)
+            .collect();
+        for index in hit {
+            let center = self.objects[index].get_transform().position;
+            let push = explosion.knockback_at(center) * PLAYER_MASS;
+            if push != Vec3::ZERO {
+                self.apply_impulse(index, center, push);
+            }
+        }
+    }
+
+    /// Steps every projectile by `dt` seconds, removing those that exploded
+    /// or expired. Projectiles explode on the `players` they hit. Returns
+    /// the explosions, for the caller to apply to players.
+    pub fn update_projectiles(&mut self, players: &[Hitbox], dt: f32) -> Vec<Explosion> {
+        let mut explosions = Vec::new();
+        // taken out so each projectile can query the rest of the state
+        let mut projectiles = std::mem::take(&mut self.projectiles);
+        projectiles.retain_mut(|projectile| {
+            let (done, explosion) = projectile.step(self, players, dt);
+            explosions.extend(explosion);
+            !done
+        });
+        self.projectiles = projectiles;
+        explosions
+    }
+}
+
+impl Default for State {
+    fn default() -> State {
+        State::new()
+    }
 }
@@ -0,0 +1,165 @@
+use macroquad::prelude::*;
+
+use crate::bindings::PadButton;
+
+/// Connected gamepads, read through gilrs. Every pad drives the same player.
+///
+/// Gamepad support is behind the `gamepad` cargo feature since gilrs needs
+/// libudev on Linux; without it this finds no pads and reports nothing.
+pub struct Gamepads {
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>,
+    // why gilrs couldn't start, if it couldn't
+    error: Option<String>,
+    // buttons pressed since the last update
+    pressed: Vec<PadButton>,
+}
+
+impl Gamepads {
+    pub fn new() -> Gamepads {
+        #[cfg(feature = "gamepad")]
+        let (gilrs, error) = match gilrs::Gilrs::new() {
+            Ok(gilrs) => (Some(gilrs), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        #[cfg(not(feature = "gamepad"))]
+        let error = None;
+        Gamepads {
+            #[cfg(feature = "gamepad")]
+            gilrs,
+            error,
+            pressed: Vec::new(),
+        }
+    }
+
+    /// Why gamepads can't be read, if the `gamepad` feature is on but
+    /// starting gilrs failed.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Drains pending gamepad events. Called once per rendered frame.
+    pub fn update(&mut self) {
+        self.pressed.clear();
+        #[cfg(feature = "gamepad")]
+        if let Some(gilrs) = &mut self.gilrs {
+            while let Some(event) = gilrs.next_event() {
+                if let gilrs::EventType::ButtonPressed(button, _) = event.event
+                    && let Some(button) = from_gilrs(button)
+                {
+                    self.pressed.push(button);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "gamepad")]
+    pub fn is_down(&self, button: PadButton) -> bool {
+        self.gilrs.as_ref().is_some_and(|gilrs| {
+            gilrs
+                .gamepads()
+                .any(|(_, pad)| pad.is_pressed(to_gilrs(button)))
+        })
+    }
+
+    #[cfg(not(feature = "gamepad"))]
+    pub fn is_down(&self, _button: PadButton) -> bool {
+        false
+    }
+
+    /// Whether `button` went down since the last [`Gamepads::update`].
+    pub fn is_pressed(&self, button: PadButton) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    /// Left stick, x right and y up, each from -1 to 1.
+    pub fn left_stick(&self) -> Vec2 {
+        self.stick(Stick::Left)
+    }
+
+    /// Right stick, x right and y up, each from -1 to 1.
+    pub fn right_stick(&self) -> Vec2 {
+        self.stick(Stick::Right)
+    }
+
+    // the most deflected of all pads' sticks
+    #[cfg(feature = "gamepad")]
+    fn stick(&self, stick: Stick) -> Vec2 {
+        use gilrs::Axis;
+        let (x, y) = match stick {
+            Stick::Left => (Axis::LeftStickX, Axis::LeftStickY),
+            Stick::Right => (Axis::RightStickX, Axis::RightStickY),
+        };
+        let Some(gilrs) = &self.gilrs else {
+            return Vec2::ZERO;
+        };
+        gilrs
+            .gamepads()
+            .map(|(_, pad)| vec2(pad.value(x), pad.value(y)))
+            .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))
+            .unwrap_or(Vec2::ZERO)
+    }
+
+    #[cfg(not(feature = "gamepad"))]
+    fn stick(&self, _stick: Stick) -> Vec2 {
+        Vec2::ZERO
+    }
+}
+
+enum Stick {
+    Left,
+    Right,
+}
+
+impl Default for Gamepads {
+    fn default() -> Gamepads {
+        Gamepads::new()
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn to_gilrs(button: PadButton) -> gilrs::Button {
+    use gilrs::Button;
+    match button {
+        PadButton::South => Button::South,
+        PadButton::East => Button::East,
+        PadButton::North => Button::North,
+        PadButton::West => Button::West,
+        PadButton::LeftTrigger => Button::LeftTrigger,
+        PadButton::LeftTrigger2 => Button::LeftTrigger2,
+        PadButton::RightTrigger => Button::RightTrigger,
+        PadButton::RightTrigger2 => Button::RightTrigger2,
+        PadButton::Select => Button::Select,
+        PadButton::Start => Button::Start,
+        PadButton::LeftThumb => Button::LeftThumb,
+        PadButton::RightThumb => Button::RightThumb,
+        PadButton::DPadUp => Button::DPadUp,
+        PadButton::DPadDown => Button::DPadDown,
+        PadButton::DPadLeft => Button::DPadLeft,
+        PadButton::DPadRight => Button::DPadRight,
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn from_gilrs(button: gilrs::Button) -> Option<PadButton> {
+    use gilrs::Button;
+    Some(match button {
+        Button::South => PadButton::South,
+        Button::East => PadButton::East,
+        Button::North => PadButton::North,
+        Button::West => PadButton::West,
+        Button::LeftTrigger => PadButton::LeftTrigger,
+        Button::LeftTrigger2 => PadButton::LeftTrigger2,
+        Button::RightTrigger => PadButton::RightTrigger,
+        Button::RightTrigger2 => PadButton::RightTrigger2,
+        Button::Select => PadButton::Select,
+        Button::Start => PadButton::Start,
+        Button::LeftThumb => PadButton::LeftThumb,
+        Button::RightThumb => PadButton::RightThumb,
+        Button::DPadUp => PadButton::DPadUp,
+        Button::DPadDown => PadButton::DPadDown,
+        Button::DPadLeft => PadButton::DPadLeft,
+        Button::DPadRight => PadButton::DPadRight,
+        _ => return None,
+    })
+}
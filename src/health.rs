@@ -0,0 +1,130 @@
+/// What hurt a player.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DamageKind {
+    Bullet,
+    Explosion,
+    Fall,
+    /// Fell below the level's kill plane. Always fatal.
+    KillPlane,
+}
+
+/// One hit on a player.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Damage {
+    pub amount: f32,
+    pub kind: DamageKind,
+    /// Id of the player who dealt it, if any.
+    pub source: Option<u32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct HealthConfig {
+    pub max_health: f32,
+    pub max_armor: f32,
+    /// Fraction of incoming damage armor takes instead of health, while
+    /// there is armor left.
+    pub armor_protection: f32,
+    /// Landing slower than this, in units per second, does no harm.
+    pub safe_fall_speed: f32,
+    /// Damage per unit per second of landing speed over `safe_fall_speed`.
+    pub fall_damage: f32,
+    /// Scales damage from a player's own explosions, so rocket jumps don't
+    /// cost too much.
+    pub self_damage_scale: f32,
+    /// Seconds between dying and respawning.
+    pub respawn_delay: f32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> HealthConfig {
+        HealthConfig {
+            max_health: 100.,
+            max_armor: 100.,
+            armor_protection: 2. / 3.,
+            // a little over a jump from a 3 unit ledge
+            safe_fall_speed: 14.,
+            fall_damage: 5.,
+            self_damage_scale: 0.5,
+            respawn_delay: 2.,
+        }
+    }
+}
+
+/// Health and armor of a player. Dead at zero health.
+#[derive(Clone, Debug)]
+pub struct Health {
+    pub config: HealthConfig,
+    health: f32,
+    armor: f32,
+}
+
+impl Health {
+    /// Full health, no armor.
+    pub fn new(config: HealthConfig) -> Health {
+        Health {
+            health: config.max_health,
+            armor: 0.,
+            config,
+        }
+    }
+
+    pub fn health(&self) -> f32 {
+        self.health
+    }
+
+    pub fn armor(&self) -> f32 {
+        self.armor
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0.
+    }
+
+    /// Adds health, up to the maximum. The dead can't be healed.
+    pub fn heal(&mut self, amount: f32) {
+        if !self.is_dead() {
+            self.health = (self.health + amount).min(self.config.max_health);
+        }
+    }
+
+    /// Adds armor, up to the maximum.
+    pub fn add_armor(&mut self, amount: f32) {
+        self.armor = (self.armor + amount).min(self.config.max_armor);
+    }
+
+    /// Takes `damage`, armor first soaking up its share. Returns the health
+    /// lost.
+    pub fn apply(&mut self, damage: &Damage) -> f32 {
+        if self.is_dead() {
+            return 0.;
+        }
+        if damage.kind == DamageKind::KillPlane {
+            let lost = self.health;
+            self.health = 0.;
+            return lost;
+        }
+        let absorbed = (damage.amount * self.config.armor_protection).min(self.armor);
+        self.armor -= absorbed;
+        let lost = (damage.amount - absorbed).min(self.health);
+        self.health -= lost;
+        lost
+    }
+
+    /// Sets both outright, e.g. to what the server says they are.
+    pub fn set(&mut self, health: f32, armor: f32) {
+        self.health = health.min(self.config.max_health);
+        self.armor = armor.clamp(0., self.config.max_armor);
+    }
+
+    /// Back to full health with no armor, for respawning.
+    pub fn reset(&mut self) {
+        self.health = self.config.max_health;
+        self.armor = 0.;
+    }
+}
+
+impl Default for Health {
+    fn default() -> Health {
+        Health::new(HealthConfig::default())
+    }
+}
@@ -0,0 +1,139 @@
+use std::f32::consts::PI;
+
+use macroquad::prelude::*;
+
+use crate::bindings::{Action, Binding, Bindings};
+use crate::gamepad::Gamepads;
+use crate::game::Spawn;
+
+// how far past the deadzone the move stick has to go to count as a direction
+const STICK_THRESHOLD: f32 = 0.4;
+
+/// Everything the simulation needs to know about the player's controls for
+/// one tick. The simulation never reads the keyboard or mouse directly, so a
+/// command can just as well come from a script or a test as from
+/// [`LocalInput`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InputCommand {
+    pub theta: f32,
+    pub phi: f32,
+    pub forward: bool,
+    pub back: bool,
+    pub left: bool,
+    pub right: bool,
+    pub jump: bool,
+    pub sprint: bool,
+    pub crouch: bool,
+    pub fire: bool,
+    pub reload: bool,
+    /// Pressed this tick, as opposed to held.
+    pub next_weapon: bool,
+    /// The use action, for doors, buttons and pickups.
+    pub interact: bool,
+}
+
+/// Samples the mouse, keyboard and gamepads into [`InputCommand`]s, going
+/// through [`Bindings`] so the simulation only ever sees actions.
+pub struct LocalInput {
+    pub theta: f32,
+    pub phi: f32,
+    pub bindings: Bindings,
+    gamepads: Gamepads,
+    jump_queued: bool,
+    next_weapon_queued: bool,
+}
+
+impl LocalInput {
+    pub fn new(spawn: &Spawn, bindings: Bindings) -> LocalInput {
+        LocalInput {
+            theta: spawn.theta,
+            phi: spawn.phi,
+            bindings,
+            gamepads: Gamepads::new(),
+            jump_queued: false,
+            next_weapon_queued: false,
+        }
+    }
+
+    pub fn gamepads(&self) -> &Gamepads {
+        &self.gamepads
+    }
+
+    /// Reads mouse and stick look and latches one-shot presses. Called once
+    /// per rendered frame, so presses are not lost on frames where no tick runs.
+    pub fn poll(&mut self) {
+        self.gamepads.update();
+
+        let mut delta = mouse_delta_position() * self.bindings.mouse_sensitivity;
+        let stick = self.stick(self.gamepads.right_stick());
+        // stick right turns right, i.e. towards smaller theta, same as the mouse
+        delta += vec2(-stick.x, stick.y) * self.bindings.stick_look_speed * get_frame_time();
+        if self.bindings.invert_y {
+            delta.y = -delta.y;
+        }
+
+        self.theta += delta.x;
+        self.theta = self.theta.rem_euclid(PI * 2.);
+        self.phi += delta.y;
+        self.phi = self.phi.clamp(-PI/2. + 0.001, PI/2. - 0.001);
+
+        if self.is_pressed(Action::Jump) {
+            self.jump_queued = true;
+        }
+        if self.is_pressed(Action::NextWeapon) {
+            self.next_weapon_queued = true;
+        }
+    }
+
+    /// Builds the command for the next tick, consuming latched presses.
+    pub fn command(&mut self) -> InputCommand {
+        // the left stick is read as four directions, like the keys
+        let stick = self.stick(self.gamepads.left_stick());
+        let cmd = InputCommand {
+            theta: self.theta,
+            phi: self.phi,
+            forward: self.is_down(Action::Forward) || stick.y > STICK_THRESHOLD,
+            back: self.is_down(Action::Back) || stick.y < -STICK_THRESHOLD,
+            left: self.is_down(Action::Left) || stick.x < -STICK_THRESHOLD,
+            right: self.is_down(Action::Right) || stick.x > STICK_THRESHOLD,
+            // holding jump keeps jumping on landing, for bunny hopping
+            jump: self.jump_queued || self.is_down(Action::Jump),
+            sprint: self.is_down(Action::Sprint),
+            crouch: self.is_down(Action::Crouch),
+            fire: self.is_down(Action::Fire),
+            reload: self.is_down(Action::Reload),
+            next_weapon: self.next_weapon_queued,
+            interact: self.is_down(Action::Use),
+        };
+        self.jump_queued = false;
+        self.next_weapon_queued = false;
+        cmd
+    }
+
+    pub fn is_down(&self, action: Action) -> bool {
+        self.bindings.get(action).iter().any(|binding| match binding {
+            Binding::Key(key) => is_key_down(key.0),
+            Binding::Mouse(button) => is_mouse_button_down(button.button()),
+            Binding::Pad(button) => self.gamepads.is_down(*button),
+        })
+    }
+
+    /// Whether the action was triggered this frame.
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.bindings.get(action).iter().any(|binding| match binding {
+            Binding::Key(key) => is_key_pressed(key.0),
+            Binding::Mouse(button) => is_mouse_button_pressed(button.button()),
+            Binding::Pad(button) => self.gamepads.is_pressed(*button),
+        })
+    }
+
+    // zero inside the deadzone, rescaled to still reach 1 at full deflection
+    fn stick(&self, value: Vec2) -> Vec2 {
+        let deadzone = self.bindings.stick_deadzone;
+        let length = value.length();
+        if length <= deadzone {
+            return Vec2::ZERO;
+        }
+        value * ((length.min(1.) - deadzone) / (1. - deadzone) / length)
+    }
+}
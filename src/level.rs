@@ -0,0 +1,1017 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use macroquad::prelude::*;
+use serde::Deserialize;
+
+use crate::game::{DEFAULT_KILL_HEIGHT, Spawn, State};
+use crate::lights::{Light, Lighting, Sun};
+use crate::materials::{self, MaterialHandle, Materials, Surface, UvMapping};
+use crate::mesh::{MeshCollider, MeshError, MeshObject, ObjData};
+use crate::objects::{CollisionBox, CollisionRamp, PhysicsObject, Transform};
+use crate::rigidbody::{BodyConfig, BodyShape, RigidBody};
+use crate::skybox::{FACE_NAMES, Skybox, SkyboxError};
+
+// default kill plane distance below the lowest object
+const KILL_MARGIN: f32 = 20.;
+
+/// Level description as written in a `.ron` file, e.g.
+///
+/// ```ron
+/// Level(
+///     skybox: Some((image: Cross("textures/skybox3.png"), rotation: (0, 45, 0))),
+///     lighting: (
+///         ambient: (0.2, 0.2, 0.25),
+///         sun: Some((direction: (-0.4, -1, -0.3), color: (1, 0.95, 0.9))),
+///         lights: [
+///             Point(position: (0, 3, 0), color: (1, 0.5, 0.2), intensity: 2, range: 8),
+///             Spot(position: (5, 4, 5), direction: (0, -1, 0), range: 10, cone: (20, 30)),
+///         ],
+///     ),
+///     materials: [
+///         (name: "brick", diffuse: Some("textures/brick.png"), mapping: Triplanar, tile_size: 2),
+///     ],
+///     spawn: (position: (0, 10, 0), theta: 0, phi: 0),
+///     kill_height: Some(-20),
+///     objects: [
+///         Box(position: (0, -0.5, 0), half_extents: (10, 0.5, 10), material: Some("world")),
+///         Box(position: (5, 1, 5), half_extents: (1, 1, 1), color: Some((0.8, 0.2, 0.2))),
+///         Box(position: (-5, 1, 5), half_extents: (1, 1, 1), material: Some("brick")),
+///         Ramp(position: (0, 1, -5), rotation: (0, 90, 0), half_extents: (1, 1, 1)),
+///         Body(shape: Box((0.4, 0.4, 0.4)), position: (2, 0.4, 2), mass: Some(30)),
+///         Mesh(path: "models/arch/arch.obj", position: (-7, 0, 0)),
+///     ],
+///     models: [
+///         (path: "models/crate.obj", position: (3, 0.5, 3), collider: Box((0.5, 0.5, 0.5))),
+///     ],
+/// )
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Level {
+    #[serde(default)]
+    pub skybox: Option<SkyboxDef>,
+    #[serde(default)]
+    pub lighting: LightingDef,
+    #[serde(default)]
+    pub materials: Vec<MaterialDef>,
+    #[serde(default)]
+    pub spawn: SpawnDef,
+    /// Players falling below this die. Defaults to a little under the
+    /// lowest object.
+    #[serde(default)]
+    pub kill_height: Option<f32>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDef>,
+    #[serde(default)]
+    pub models: Vec<ModelDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpawnDef {
+    pub position: (f32, f32, f32),
+    #[serde(default)]
+    pub theta: f32,
+    #[serde(default)]
+    pub phi: f32,
+}
+
+impl Default for SpawnDef {
+    fn default() -> SpawnDef {
+        let spawn = Spawn::default();
+        SpawnDef {
+            position: spawn.position.into(),
+            theta: spawn.theta,
+            phi: spawn.phi,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum ObjectDef {
+    Box {
+        position: (f32, f32, f32),
+        #[serde(default)]
+        rotation: Rotation,
+        half_extents: HalfExtents,
+        /// Name of a material from the level's `materials` or a built-in
+        /// one, "world" if not given.
+        #[serde(default)]
+        material: Option<MaterialName>,
+        /// Base color from 0 to 1 per channel, light gray if not given.
+        #[serde(default)]
+        color: Option<(f32, f32, f32)>,
+        #[serde(default)]
+        texture: Option<TextureFile>,
+    },
+    Ramp {
+        position: (f32, f32, f32),
+        #[serde(default)]
+        rotation: Rotation,
+        half_extents: HalfExtents,
+        /// Name of a material from the level's `materials` or a built-in
+        /// one, "world" if not given.
+        #[serde(default)]
+        material: Option<MaterialName>,
+        /// Base color from 0 to 1 per channel, light gray if not given.
+        #[serde(default)]
+        color: Option<(f32, f32, f32)>,
+        #[serde(default)]
+        texture: Option<TextureFile>,
+    },
+    /// A rigid body, e.g. a crate or barrel, that falls, tumbles and can be
+    /// pushed around.
+    Body {
+        shape: BodyShapeDef,
+        position: (f32, f32, f32),
+        #[serde(default)]
+        rotation: Rotation,
+        /// 20 if not given, where a player counts as 80.
+        #[serde(default)]
+        mass: Option<Positive>,
+        #[serde(default)]
+        friction: Option<NonNegative>,
+        /// How bouncy, from 0 to 1.
+        #[serde(default)]
+        restitution: Option<Fraction>,
+        /// Name of a material from the level's `materials` or a built-in
+        /// one, "world" if not given.
+        #[serde(default)]
+        material: Option<MaterialName>,
+        /// Base color from 0 to 1 per channel, light gray if not given.
+        #[serde(default)]
+        color: Option<(f32, f32, f32)>,
+        #[serde(default)]
+        texture: Option<TextureFile>,
+    },
+    /// Static level geometry loaded from an OBJ, collided against triangle
+    /// by triangle so it can be concave.
+    Mesh {
+        path: ObjFile,
+        position: (f32, f32, f32),
+        #[serde(default)]
+        rotation: Rotation,
+        #[serde(default = "default_scale")]
+        scale: Scale,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum BodyShapeDef {
+    Box(HalfExtents),
+    /// Upright cylinder.
+    Cylinder { radius: Positive, half_height: Positive },
+}
+
+impl BodyShapeDef {
+    pub fn to_body_shape(&self) -> BodyShape {
+        match self {
+            BodyShapeDef::Box(half_extents) => BodyShape::Box(half_extents.0),
+            BodyShapeDef::Cylinder {
+                radius,
+                half_height,
+            } => BodyShape::Cylinder {
+                radius: radius.0,
+                half_height: half_height.0,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SkyboxDef {
+    pub image: SkyImage,
+    #[serde(default)]
+    pub rotation: Rotation,
+    /// Color multiplied with the sky, from 0 to 1 per channel.
+    #[serde(default = "white")]
+    pub tint: (f32, f32, f32),
+}
+
+fn white() -> (f32, f32, f32) {
+    (1., 1., 1.)
+}
+
+fn one() -> f32 {
+    1.
+}
+
+/// Level lights. Colors are from 0 to 1 per channel and are multiplied by
+/// `intensity`. Without a `lighting` entry the level gets
+/// [`Lighting::default`].
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightingDef {
+    pub ambient: (f32, f32, f32),
+    #[serde(default)]
+    pub sun: Option<SunDef>,
+    #[serde(default)]
+    pub lights: Vec<LightDef>,
+}
+
+impl Default for LightingDef {
+    fn default() -> LightingDef {
+        let lighting = Lighting::default();
+        LightingDef {
+            ambient: lighting.ambient.into(),
+            sun: lighting.sun.map(|sun| SunDef {
+                direction: Direction(sun.direction),
+                color: sun.color.into(),
+                intensity: 1.,
+            }),
+            lights: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SunDef {
+    /// Direction the light travels in, i.e. pointing down for a sun overhead.
+    pub direction: Direction,
+    #[serde(default = "white")]
+    pub color: (f32, f32, f32),
+    #[serde(default = "one")]
+    pub intensity: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum LightDef {
+    Point {
+        position: (f32, f32, f32),
+        #[serde(default = "white")]
+        color: (f32, f32, f32),
+        #[serde(default = "one")]
+        intensity: f32,
+        /// Distance at which the light has faded out completely.
+        range: Positive,
+    },
+    Spot {
+        position: (f32, f32, f32),
+        direction: Direction,
+        #[serde(default = "white")]
+        color: (f32, f32, f32),
+        #[serde(default = "one")]
+        intensity: f32,
+        range: Positive,
+        cone: Cone,
+    },
+}
+
+impl LightingDef {
+    pub fn to_lighting(&self) -> Lighting {
+        let color = |color: (f32, f32, f32), intensity: f32| Vec3::from(color) * intensity;
+        Lighting {
+            ambient: Vec3::from(self.ambient),
+            sun: self.sun.as_ref().map(|sun| Sun {
+                direction: sun.direction.0,
+                color: color(sun.color, sun.intensity),
+            }),
+            lights: self
+                .lights
+                .iter()
+                .map(|light| match light {
+                    LightDef::Point {
+                        position,
+                        color: c,
+                        intensity,
+                        range,
+                    } => Light::Point {
+                        position: Vec3::from(*position),
+                        color: color(*c, *intensity),
+                        range: range.0,
+                    },
+                    LightDef::Spot {
+                        position,
+                        direction,
+                        color: c,
+                        intensity,
+                        range,
+                        cone,
+                    } => Light::Spot {
+                        position: Vec3::from(*position),
+                        direction: direction.0,
+                        color: color(*c, *intensity),
+                        range: range.0,
+                        inner_angle: cone.inner,
+                        outer_angle: cone.outer,
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum SkyImage {
+    /// One image with the faces laid out as a horizontal or vertical cross.
+    Cross(AssetPath),
+    /// One image per face. `front` is the view looking down -z.
+    Faces {
+        right: AssetPath,
+        left: AssetPath,
+        top: AssetPath,
+        bottom: AssetPath,
+        front: AssetPath,
+        back: AssetPath,
+    },
+}
+
+/// Lit material objects can refer to by name. Textures tile every
+/// `tile_size` world units.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDef {
+    pub name: String,
+    #[serde(default = "white")]
+    pub color: (f32, f32, f32),
+    #[serde(default)]
+    pub diffuse: Option<TextureFile>,
+    /// Tangent space normal map, mapped like the diffuse texture.
+    #[serde(default)]
+    pub normal_map: Option<TextureFile>,
+    #[serde(default)]
+    pub mapping: MappingDef,
+    #[serde(default = "unit_tile")]
+    pub tile_size: Positive,
+}
+
+fn unit_tile() -> Positive {
+    Positive(1.)
+}
+
+#[derive(Deserialize, Default)]
+pub enum MappingDef {
+    /// The object's own texture coordinates, generated in world units for
+    /// boxes and ramps.
+    #[default]
+    Mesh,
+    /// Projected along the world axes.
+    Triplanar,
+}
+
+impl MappingDef {
+    pub fn to_uv_mapping(&self) -> UvMapping {
+        match self {
+            MappingDef::Mesh => UvMapping::Mesh,
+            MappingDef::Triplanar => UvMapping::Triplanar,
+        }
+    }
+}
+
+/// Euler angles in degrees about the x, y and z axes, applied as yaw (y)
+/// first, then pitch (x), then roll (z).
+#[derive(Deserialize, Default)]
+pub struct Rotation(pub f32, pub f32, pub f32);
+
+impl Rotation {
+    pub fn to_quat(&self) -> Quat {
+        Quat::from_euler(
+            EulerRot::YXZ,
+            self.1.to_radians(),
+            self.0.to_radians(),
+            self.2.to_radians(),
+        )
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelDef {
+    pub path: ObjFile,
+    pub position: (f32, f32, f32),
+    #[serde(default)]
+    pub rotation: Rotation,
+    #[serde(default = "default_scale")]
+    pub scale: Scale,
+    #[serde(default)]
+    pub collider: ColliderDef,
+}
+
+fn default_scale() -> Scale {
+    Scale(Vec3::ONE)
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub enum ColliderDef {
+    #[default]
+    ConvexHull,
+    TriMesh,
+    Box(HalfExtents),
+    Ball(Positive),
+    Capsule { half_height: Positive, radius: Positive },
+}
+
+impl ColliderDef {
+    pub fn to_mesh_collider(&self) -> MeshCollider {
+        match self {
+            ColliderDef::ConvexHull => MeshCollider::ConvexHull,
+            ColliderDef::TriMesh => MeshCollider::TriMesh,
+            ColliderDef::Box(half_extents) => MeshCollider::Cuboid(half_extents.0),
+            ColliderDef::Ball(radius) => MeshCollider::Ball(radius.0),
+            ColliderDef::Capsule {
+                half_height,
+                radius,
+            } => MeshCollider::Capsule {
+                half_height: half_height.0,
+                radius: radius.0,
+            },
+        }
+    }
+}
+
+// validated while deserializing so that bad values are reported at the
+// position of the offending entry rather than after the whole file is parsed
+
+fn positive_components(value: (f32, f32, f32)) -> Result<Vec3, String> {
+    let vector = Vec3::from(value);
+    if !vector.is_finite() || vector.min_element() <= 0. {
+        return Err(format!("expected all components to be positive, got {}", vector));
+    }
+    Ok(vector)
+}
+
+/// Half the size of a box along each axis, all positive.
+#[derive(Deserialize)]
+#[serde(try_from = "(f32, f32, f32)")]
+pub struct HalfExtents(pub Vec3);
+
+impl TryFrom<(f32, f32, f32)> for HalfExtents {
+    type Error = String;
+
+    fn try_from(value: (f32, f32, f32)) -> Result<HalfExtents, String> {
+        positive_components(value).map(HalfExtents)
+    }
+}
+
+/// Scale factor along each axis, all positive; a model can't be flattened
+/// or mirrored.
+#[derive(Deserialize)]
+#[serde(try_from = "(f32, f32, f32)")]
+pub struct Scale(pub Vec3);
+
+impl TryFrom<(f32, f32, f32)> for Scale {
+    type Error = String;
+
+    fn try_from(value: (f32, f32, f32)) -> Result<Scale, String> {
+        positive_components(value).map(Scale)
+    }
+}
+
+/// Nonzero vector, normalized when read.
+#[derive(Deserialize)]
+#[serde(try_from = "(f32, f32, f32)")]
+pub struct Direction(pub Vec3);
+
+impl TryFrom<(f32, f32, f32)> for Direction {
+    type Error = String;
+
+    fn try_from(value: (f32, f32, f32)) -> Result<Direction, String> {
+        Vec3::from(value)
+            .try_normalize()
+            .map(Direction)
+            .ok_or_else(|| format!("expected a nonzero direction, got {}", Vec3::from(value)))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "f32")]
+pub struct Positive(pub f32);
+
+impl TryFrom<f32> for Positive {
+    type Error = String;
+
+    fn try_from(value: f32) -> Result<Positive, String> {
+        if !value.is_finite() || value <= 0. {
+            return Err(format!("expected a positive number, got {}", value));
+        }
+        Ok(Positive(value))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "f32")]
+pub struct NonNegative(pub f32);
+
+impl TryFrom<f32> for NonNegative {
+    type Error = String;
+
+    fn try_from(value: f32) -> Result<NonNegative, String> {
+        if !value.is_finite() || value < 0. {
+            return Err(format!("expected a number of at least 0, got {}", value));
+        }
+        Ok(NonNegative(value))
+    }
+}
+
+/// Number from 0 to 1.
+#[derive(Deserialize)]
+#[serde(try_from = "f32")]
+pub struct Fraction(pub f32);
+
+impl TryFrom<f32> for Fraction {
+    type Error = String;
+
+    fn try_from(value: f32) -> Result<Fraction, String> {
+        if !(0. ..=1.).contains(&value) {
+            return Err(format!("expected a number from 0 to 1, got {}", value));
+        }
+        Ok(Fraction(value))
+    }
+}
+
+/// Spot light cone as (inner, outer) angles off its axis in degrees. Stored
+/// in radians.
+#[derive(Deserialize)]
+#[serde(try_from = "(f32, f32)")]
+pub struct Cone {
+    pub inner: f32,
+    pub outer: f32,
+}
+
+impl TryFrom<(f32, f32)> for Cone {
+    type Error = String;
+
+    fn try_from((inner, outer): (f32, f32)) -> Result<Cone, String> {
+        if !(0. <= inner && inner < outer && outer <= 180.) {
+            return Err(format!(
+                "expected 0 <= inner < outer <= 180 degrees, got ({}, {})",
+                inner, outer
+            ));
+        }
+        Ok(Cone {
+            inner: inner.to_radians(),
+            outer: outer.to_radians(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct AssetPath(pub String);
+
+impl TryFrom<String> for AssetPath {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<AssetPath, String> {
+        if !Path::new(&value).is_file() {
+            return Err(format!("no such file: {}", value));
+        }
+        Ok(AssetPath(value))
+    }
+}
+
+/// Image file, decoded when the level is read so a broken one is reported
+/// where it is named.
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct TextureFile {
+    pub path: String,
+    pub image: Image,
+}
+
+impl TryFrom<String> for TextureFile {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<TextureFile, String> {
+        let AssetPath(path) = AssetPath::try_from(value)?;
+        let bytes = fs::read(&path).map_err(|error| format!("{}: {}", path, error))?;
+        let image = Image::from_file_with_format(&bytes, None)
+            .map_err(|error| format!("failed to load texture {}: {}", path, error))?;
+        Ok(TextureFile { path, image })
+    }
+}
+
+/// OBJ file, read along with its MTL and textures when the level is read.
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct ObjFile(pub ObjData);
+
+impl TryFrom<String> for ObjFile {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<ObjFile, String> {
+        let AssetPath(path) = AssetPath::try_from(value)?;
+        ObjData::load(&path).map(ObjFile).map_err(|error| error.to_string())
+    }
+}
+
+thread_local! {
+    // names of the materials the level being parsed defines, for checking
+    // the names its objects use; see `Level::parse`
+    static LEVEL_MATERIALS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Name of a built-in material or one from the level's `materials`.
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct MaterialName(pub String);
+
+impl TryFrom<String> for MaterialName {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<MaterialName, String> {
+        let defined = LEVEL_MATERIALS.with_borrow(|names| names.contains(&value));
+        if !defined && materials::builtin(&value).is_none() {
+            return Err(format!("unknown material: {}", value));
+        }
+        Ok(MaterialName(value))
+    }
+}
+
+// just the material names of a level, read ahead of the rest so objects can
+// use materials defined anywhere in the file
+#[derive(Deserialize)]
+#[serde(rename = "Level")]
+struct MaterialNames {
+    #[serde(default)]
+    materials: Vec<NamedMaterial>,
+}
+
+#[derive(Deserialize)]
+struct NamedMaterial {
+    name: String,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io {
+        path: String,
+        error: std::io::Error,
+    },
+    Parse {
+        path: String,
+        error: Box<ron::error::SpannedError>,
+    },
+    Texture {
+        path: String,
+        error: macroquad::Error,
+    },
+    /// A model whose collider can't be built, e.g. a flat one given a
+    /// convex hull; everything else about models is checked while parsing.
+    Mesh(MeshError),
+    Skybox {
+        path: String,
+        error: SkyboxError,
+    },
+    UnknownMaterial(String),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelError::Io { path, error } => write!(f, "{}: {}", path, error),
+            LevelError::Parse { path, error } => write!(
+                f,
+                "{}:{}:{}: {}",
+                path, error.span.start.line, error.span.start.col, error.code
+            ),
+            LevelError::Texture { path, error } => {
+                write!(f, "failed to load texture {}: {}", path, error)
+            }
+            LevelError::Mesh(error) => write!(f, "{}", error),
+            LevelError::Skybox { path, error } => write!(f, "{}: {}", path, error),
+            LevelError::UnknownMaterial(name) => write!(f, "unknown material: {}", name),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl Level {
+    /// Parses a level, loading the models and textures it names and
+    /// checking the material names it uses, so any of them that are wrong
+    /// are reported at the line and column they are named on.
+    pub fn parse(path: &str, source: &str) -> Result<Level, LevelError> {
+        // a file too broken for this fails again below, with the error
+        let names = ron::from_str::<MaterialNames>(source)
+            .map(|level| level.materials.into_iter().map(|m| m.name).collect())
+            .unwrap_or_default();
+        LEVEL_MATERIALS.set(names);
+        let level = ron::from_str(source);
+        LEVEL_MATERIALS.take();
+        level.map_err(|error| LevelError::Parse {
+            path: path.to_string(),
+            error: Box::new(error),
+        })
+    }
+
+    pub fn from_file(path: &str) -> Result<Level, LevelError> {
+        let source = fs::read_to_string(path).map_err(|error| LevelError::Io {
+            path: path.to_string(),
+            error,
+        })?;
+        Level::parse(path, &source)
+    }
+
+    /// Builds the simulation state. Does not touch the GPU, so the skybox,
+    /// textures and materials are left out and every object is given the
+    /// world material; see [`load`] for the full loader.
+    pub fn build_state(&self) -> Result<State, LevelError> {
+        self.build(None)
+    }
+
+    // textures are loaded, the level's materials registered and material
+    // names resolved against the registry only when there is one, i.e. when
+    // there is a GL context
+    fn build(&self, mut materials: Option<&mut Materials>) -> Result<State, LevelError> {
+        let mut state = State::new();
+        let mut textures = TextureCache::default();
+
+        if let Some(materials) = materials.as_deref_mut() {
+            for def in self.materials.iter() {
+                let surface = Surface {
+                    color: Color::new(def.color.0, def.color.1, def.color.2, 1.),
+                    diffuse: def.diffuse.as_ref().map(|file| textures.load(file)),
+                    normal_map: def.normal_map.as_ref().map(|file| textures.load(file)),
+                    mapping: def.mapping.to_uv_mapping(),
+                    tile_size: def.tile_size.0,
+                };
+                materials.register_surface(&def.name, &surface);
+            }
+        }
+
+        state.lighting = self.lighting.to_lighting();
+
+        state.spawn = Spawn {
+            position: Vec3::from(self.spawn.position),
+            theta: self.spawn.theta,
+            phi: self.spawn.phi,
+        };
+
+        for obj in self.objects.iter() {
+            let obj: Box<dyn PhysicsObject> = match obj {
+                ObjectDef::Box {
+                    position,
+                    rotation,
+                    half_extents,
+                    material,
+                    color,
+                    texture,
+                } => {
+                    let mut obj = CollisionBox::new(
+                        Transform::new(Vec3::from(*position), rotation.to_quat()),
+                        half_extents.0,
+                    );
+                    let look = self.look(materials.as_deref(), &mut textures, material, color, texture)?;
+                    obj.set_look(look);
+                    Box::new(obj)
+                }
+                ObjectDef::Ramp {
+                    position,
+                    rotation,
+                    half_extents,
+                    material,
+                    color,
+                    texture,
+                } => {
+                    let mut obj = CollisionRamp::new(
+                        Transform::new(Vec3::from(*position), rotation.to_quat()),
+                        half_extents.0,
+                    );
+                    let look = self.look(materials.as_deref(), &mut textures, material, color, texture)?;
+                    obj.set_look(look);
+                    Box::new(obj)
+                }
+                ObjectDef::Body {
+                    shape,
+                    position,
+                    rotation,
+                    mass,
+                    friction,
+                    restitution,
+                    material,
+                    color,
+                    texture,
+                } => {
+                    let defaults = BodyConfig::default();
+                    let config = BodyConfig {
+                        mass: mass.as_ref().map_or(defaults.mass, |mass| mass.0),
+                        friction: friction.as_ref().map_or(defaults.friction, |f| f.0),
+                        restitution: restitution.as_ref().map_or(defaults.restitution, |r| r.0),
+                        ..defaults
+                    };
+                    let mut obj = RigidBody::new(
+                        Transform::new(Vec3::from(*position), rotation.to_quat()),
+                        shape.to_body_shape(),
+                        config,
+                    );
+                    let look = self.look(materials.as_deref(), &mut textures, material, color, texture)?;
+                    obj.set_look(look);
+                    Box::new(obj)
+                }
+                ObjectDef::Mesh {
+                    path,
+                    position,
+                    rotation,
+                    scale,
+                } => Box::new(load_mesh(
+                    &path.0,
+                    Transform::new(Vec3::from(*position), rotation.to_quat()),
+                    scale.0,
+                    MeshCollider::TriMesh,
+                    materials.as_deref_mut(),
+                )?),
+            };
+            state.add_object(obj);
+        }
+
+        for model in self.models.iter() {
+            state.add_object(Box::new(load_mesh(
+                &model.path.0,
+                Transform::new(Vec3::from(model.position), model.rotation.to_quat()),
+                model.scale.0,
+                model.collider.to_mesh_collider(),
+                materials.as_deref_mut(),
+            )?));
+        }
+
+        state.kill_height = match self.kill_height {
+            Some(height) => height,
+            None => state
+                .objects()
+                .iter()
+                .map(|obj| obj.aabb().mins.y - KILL_MARGIN)
+                .reduce(f32::min)
+                .unwrap_or(DEFAULT_KILL_HEIGHT),
+        };
+
+        Ok(state)
+    }
+
+    // the material, color and texture an object is given, the texture only
+    // when there is a GL context to upload it to
+    fn look(
+        &self,
+        materials: Option<&Materials>,
+        textures: &mut TextureCache,
+        material: &Option<MaterialName>,
+        color: &Option<(f32, f32, f32)>,
+        texture: &Option<TextureFile>,
+    ) -> Result<Look, LevelError> {
+        Ok(Look {
+            material: self.find_material(materials, material)?,
+            color: color.map(|(r, g, b)| Color::new(r, g, b, 1.)),
+            texture: texture
+                .as_ref()
+                .filter(|_| materials.is_some())
+                .map(|file| textures.load(file)),
+        })
+    }
+
+    fn find_material(
+        &self,
+        materials: Option<&Materials>,
+        name: &Option<MaterialName>,
+    ) -> Result<MaterialHandle, LevelError> {
+        let Some(MaterialName(name)) = name else {
+            return Ok(materials::WORLD);
+        };
+        let handle = match materials {
+            Some(materials) => materials.find(name),
+            // nothing is drawn without a registry, so level materials can
+            // stand in with the world one
+            None => materials::builtin(name).or_else(|| {
+                self.materials
+                    .iter()
+                    .any(|def| def.name == *name)
+                    .then_some(materials::WORLD)
+            }),
+        };
+        handle.ok_or_else(|| LevelError::UnknownMaterial(name.clone()))
+    }
+}
+
+fn load_mesh(
+    obj: &ObjData,
+    transform: Transform,
+    scale: Vec3,
+    collider: MeshCollider,
+    materials: Option<&mut Materials>,
+) -> Result<MeshObject, LevelError> {
+    let mut mesh = MeshObject::new(obj, transform, scale, collider).map_err(LevelError::Mesh)?;
+    if let Some(materials) = materials {
+        mesh.load_textures(obj, materials);
+    }
+    Ok(mesh)
+}
+
+// textures used by several objects are uploaded once
+#[derive(Default)]
+struct TextureCache(HashMap<String, Texture2D>);
+
+impl TextureCache {
+    fn load(&mut self, file: &TextureFile) -> Texture2D {
+        self.0
+            .entry(file.path.clone())
+            .or_insert_with(|| materials::tiling_texture(&file.image))
+            .clone()
+    }
+}
+
+struct Look {
+    material: MaterialHandle,
+    color: Option<Color>,
+    texture: Option<Texture2D>,
+}
+
+// boxes, ramps and bodies all take their look from the level the same way
+trait SetLook {
+    fn set_look(&mut self, look: Look);
+}
+
+macro_rules! impl_set_look {
+    ($($ty:ty),*) => {$(
+        impl SetLook for $ty {
+            fn set_look(&mut self, look: Look) {
+                self.material = look.material;
+                if let Some(color) = look.color {
+                    self.set_color(color);
+                }
+                if look.texture.is_some() {
+                    self.set_texture(look.texture);
+                }
+            }
+        }
+    )*};
+}
+
+impl_set_look!(CollisionBox, CollisionRamp, RigidBody);
+
+/// Loads a level with everything needed to draw it, registering the level's
+/// materials in `materials`. Needs a GL context.
+pub async fn load(path: &str, materials: &mut Materials) -> Result<State, LevelError> {
+    let level = Level::from_file(path)?;
+    let mut state = level.build(Some(materials))?;
+
+    if let Some(skybox) = &level.skybox {
+        state.skybox = Some(load_skybox(skybox).await?);
+    }
+
+    Ok(state)
+}
+
+async fn load_skybox(def: &SkyboxDef) -> Result<Skybox, LevelError> {
+    let mut skybox = match &def.image {
+        SkyImage::Cross(path) => {
+            let image = load_sky_image(path).await?;
+            Skybox::from_cross(&image).map_err(|error| LevelError::Skybox {
+                path: path.0.clone(),
+                error,
+            })?
+        }
+        SkyImage::Faces {
+            right,
+            left,
+            top,
+            bottom,
+            front,
+            back,
+        } => {
+            let faces = [
+                load_sky_image(right).await?,
+                load_sky_image(left).await?,
+                load_sky_image(top).await?,
+                load_sky_image(bottom).await?,
+                load_sky_image(front).await?,
+                load_sky_image(back).await?,
+            ];
+            Skybox::from_faces(&faces).map_err(|error| {
+                // report the file of the face that didn't fit
+                let paths = [right, left, top, bottom, front, back];
+                let face = match &error {
+                    SkyboxError::FaceSize { face, .. } => {
+                        FACE_NAMES.iter().position(|name| name == face).unwrap_or(0)
+                    }
+                    SkyboxError::NotACross { .. } => 0,
+                };
+                LevelError::Skybox {
+                    path: paths[face].0.clone(),
+                    error,
+                }
+            })?
+        }
+    };
+    skybox.rotation = def.rotation.to_quat();
+    skybox.tint = Color::new(def.tint.0, def.tint.1, def.tint.2, 1.);
+    Ok(skybox)
+}
+
+async fn load_sky_image(path: &AssetPath) -> Result<Image, LevelError> {
+    load_image(&path.0)
+        .await
+        .map_err(|error| LevelError::Texture {
+            path: path.0.clone(),
+            error,
+        })
+}
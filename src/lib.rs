@@ -0,0 +1,32 @@
+pub mod batch;
+pub mod bits;
+pub mod bindings;
+pub mod broadphase;
+pub mod client;
+pub mod connection;
+pub mod controller;
+pub mod decals;
+pub mod dedicated;
+pub mod game;
+pub mod gamepad;
+pub mod health;
+pub mod input;
+pub mod level;
+pub mod lights;
+pub mod materials;
+pub mod mesh;
+pub mod objects;
+pub mod player;
+pub mod prediction;
+pub mod projectile;
+pub mod protocol;
+pub mod rewind;
+pub mod rigidbody;
+pub mod server;
+pub mod shadows;
+pub mod skybox;
+pub mod snapshot;
+pub mod timestep;
+pub mod transport;
+pub mod weapon;
+pub mod world;
@@ -0,0 +1,124 @@
+use macroquad::prelude::*;
+
+/// Most point and spot lights the world shader takes at once. When a level
+/// has more, the ones nearest the camera are used.
+pub const MAX_LIGHTS: usize = 8;
+
+/// Directional light infinitely far away, lighting everything from the same
+/// direction.
+#[derive(Clone, Copy, Debug)]
+pub struct Sun {
+    /// Direction the light travels in, normalized.
+    pub direction: Vec3,
+    /// Color times intensity, so may go above 1.
+    pub color: Vec3,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Light {
+    /// Shines in every direction, fading out to nothing at `range`.
+    Point {
+        position: Vec3,
+        color: Vec3,
+        range: f32,
+    },
+    /// Point light limited to a cone around `direction`. Full strength up to
+    /// `inner_angle` off the axis, fading out by `outer_angle` (radians).
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+impl Light {
+    pub fn position(&self) -> Vec3 {
+        match self {
+            Light::Point { position, .. } | Light::Spot { position, .. } => *position,
+        }
+    }
+
+    pub fn range(&self) -> f32 {
+        match self {
+            Light::Point { range, .. } | Light::Spot { range, .. } => *range,
+        }
+    }
+}
+
+/// Every light in the level, passed to the world shader as uniforms.
+pub struct Lighting {
+    /// Light reaching every surface regardless of direction.
+    pub ambient: Vec3,
+    pub sun: Option<Sun>,
+    pub lights: Vec<Light>,
+}
+
+impl Lighting {
+    /// Sets the lighting uniforms of a lit material. `eye` is the camera
+    /// position, used for specular highlights and to pick which lights to
+    /// use when there are more than [`MAX_LIGHTS`].
+    pub fn apply(&self, material: &Material, eye: Vec3) {
+        let (sun_direction, sun_color) = match &self.sun {
+            Some(sun) => (sun.direction, sun.color),
+            None => (Vec3::NEG_Y, Vec3::ZERO),
+        };
+        material.set_uniform("Ambient", self.ambient.extend(0.));
+        material.set_uniform("SunDirection", sun_direction.extend(0.));
+        material.set_uniform("SunColor", sun_color.extend(0.));
+        material.set_uniform("CameraPosition", eye.extend(1.));
+
+        let mut nearest: Vec<&Light> = self.lights.iter().collect();
+        if nearest.len() > MAX_LIGHTS {
+            // distance to the edge of each light's reach
+            let reach = |light: &Light| light.position().distance(eye) - light.range();
+            nearest.sort_by(|a, b| reach(a).total_cmp(&reach(b)));
+            nearest.truncate(MAX_LIGHTS);
+        }
+
+        // unused slots stay black, so the shader can always loop over all of them
+        let mut positions = [Vec4::ZERO; MAX_LIGHTS];
+        let mut colors = [Vec4::ZERO; MAX_LIGHTS];
+        let mut spots = [Vec4::ZERO; MAX_LIGHTS];
+        for (i, light) in nearest.iter().enumerate() {
+            positions[i] = light.position().extend(light.range());
+            match light {
+                // a cone wider than all directions, cos -1 and beyond
+                Light::Point { color, .. } => {
+                    colors[i] = color.extend(-1.);
+                    spots[i] = vec4(0., -1., 0., -2.);
+                }
+                Light::Spot {
+                    direction,
+                    color,
+                    inner_angle,
+                    outer_angle,
+                    ..
+                } => {
+                    colors[i] = color.extend(inner_angle.cos());
+                    spots[i] = direction.extend(outer_angle.cos());
+                }
+            }
+        }
+        material.set_uniform_array("LightPosition", &positions[..]);
+        material.set_uniform_array("LightColor", &colors[..]);
+        material.set_uniform_array("LightSpot", &spots[..]);
+    }
+}
+
+impl Default for Lighting {
+    /// Dim ambient light and a white sun from high up, so a level without
+    /// any lights is still readable.
+    fn default() -> Lighting {
+        Lighting {
+            ambient: Vec3::splat(0.25),
+            sun: Some(Sun {
+                direction: vec3(-0.4, -1., -0.3).normalize(),
+                color: Vec3::splat(0.9),
+            }),
+            lights: Vec::new(),
+        }
+    }
+}
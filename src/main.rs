@@ -1,77 +1,356 @@
-mod game;
-mod materials;
-mod objects;
-mod player;
-
-use std::f32::consts::PI;
 use macroquad::prelude::*;
-use crate::objects::{PhysicsObject, CollisionBox, CollisionRamp, Skybox};
+use fps_engine::batch::StaticBatch;
+use fps_engine::bindings::{Bindings, DEFAULT_BINDINGS_PATH};
+use fps_engine::client::{Client, ClientEvent};
+use fps_engine::decals::Decals;
+use fps_engine::health::DamageKind;
+use fps_engine::input::LocalInput;
+use fps_engine::level;
+use fps_engine::materials::Materials;
+use fps_engine::player::PlayerEvent;
+use fps_engine::server::DEFAULT_PORT;
+use fps_engine::shadows::{self, ShadowMap};
+use fps_engine::timestep::{DEFAULT_TICK_RATE, FixedTimestep};
+use fps_engine::transport::UdpTransport;
+use fps_engine::world::World;
+
+const USAGE: &str = "usage: fps-engine [level.ron] [--bindings PATH] [--tick-rate N]
+    [--shadow-resolution TEXELS] [--connect ADDRESS] [--name NAME]";
+
+const TOGGLE_SHADOWS_KEY: KeyCode = KeyCode::F1;
+const SHADOW_DEBUG_KEY: KeyCode = KeyCode::F2;
+// how long an explosion stays on screen, in seconds
+const EXPLOSION_FLASH_TIME: f32 = 0.25;
+// how long the screen stays red after taking damage, in seconds
+const DAMAGE_FLASH_TIME: f32 = 0.3;
+
+// how other players are drawn
+const PLAYER_COLOR: Color = SKYBLUE;
+
+// either the whole game runs here, or a server runs it and we mirror it
+enum Session {
+    Local(Box<World>),
+    Remote(Box<Client<UdpTransport>>),
+}
+
+// what the HUD says killed the player
+fn cause_of_death(kind: DamageKind) -> &'static str {
+    match kind {
+        DamageKind::Bullet => "shot",
+        DamageKind::Explosion => "caught in an explosion",
+        DamageKind::Fall => "fell too far",
+        DamageKind::KillPlane => "fell out of the world",
+    }
+}
+
+// joins the server at `addr`, showing a message until it answers. Returns
+// the client and the level the server is running.
+async fn connect(addr: &str, name: &str) -> (Client<UdpTransport>, String) {
+    // a bare host gets the default port
+    let addr = if addr.contains(':') {
+        addr.to_string()
+    } else {
+        format!("{}:{}", addr, DEFAULT_PORT)
+    };
+    let server = std::net::ToSocketAddrs::to_socket_addrs(&addr)
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .unwrap_or_else(|| {
+            eprintln!("can't resolve {}", addr);
+            std::process::exit(1);
+        });
+    let transport = UdpTransport::bind(("0.0.0.0", 0)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let mut client = Client::connect(transport, server, name);
+    loop {
+        if let Err(e) = client.update(get_frame_time()) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        for event in client.take_events() {
+            match event {
+                ClientEvent::Connected { level, .. } => return (client, level),
+                ClientEvent::Refused(reason) => {
+                    eprintln!("{} refused to let us in: {}", addr, reason);
+                    std::process::exit(1);
+                }
+                ClientEvent::TimedOut => {
+                    eprintln!("no answer from {}", addr);
+                    std::process::exit(1);
+                }
+                _ => {}
+            }
+        }
+        clear_background(BLACK);
+        draw_text(format!("connecting to {}", addr), 20., 40., 32., GREEN);
+        next_frame().await
+    }
+}
 
 #[macroquad::main("fps-engine")]
 async fn main() {
 
-    let mut state = game::State::new();
-    let mut player = player::Player::new();
-
-    state.objects.push(
-        Box::new(
-            CollisionBox::new(
-                vec3(0., -0.5, 0.),
-                vec3(10., 0.5, 10.)
-            )
-        )
-    );
-    state.objects.push(
-        Box::new(
-            CollisionBox::new(
-                vec3(5., 1., 5.),
-                vec3(2., 1., 2.)
-            )
-        )
-    );
-    state.objects.push(
-        Box::new(
-            CollisionRamp::new(
-                vec3(0., 1., -5.),
-                0.,
-                vec3(1., 1., 1.,)
-            )
-        )
-    );
-    state.objects.push(
-        Box::new(
-            CollisionRamp::new(
-                vec3(1., 1., 5.),
-                PI/2.,
-                vec3(1., 1., 2.,)
-            )
-        )
-    );
+    let mut level_path = "levels/test.ron".to_string();
+    let mut tick_rate = DEFAULT_TICK_RATE;
+    let mut bindings_path = None;
+    let mut shadow_resolution = shadows::DEFAULT_RESOLUTION;
+    let mut server_addr = None;
+    let mut name = "player".to_string();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--bindings" {
+            bindings_path = Some(args.next().unwrap_or_else(|| {
+                eprintln!("--bindings expects the path of a bindings file");
+                std::process::exit(1);
+            }));
+        } else if arg == "--tick-rate" {
+            tick_rate = args.next().and_then(|x| x.parse().ok()).unwrap_or_else(|| {
+                eprintln!("--tick-rate expects a number of ticks per second");
+                std::process::exit(1);
+            });
+        } else if arg == "--shadow-resolution" {
+            // 0 turns shadows off
+            shadow_resolution = args.next().and_then(|x| x.parse().ok()).unwrap_or_else(|| {
+                eprintln!("--shadow-resolution expects a size in texels, or 0 for no shadows");
+                std::process::exit(1);
+            });
+        } else if arg == "--connect" {
+            server_addr = Some(args.next().unwrap_or_else(|| {
+                eprintln!("--connect expects a server address");
+                std::process::exit(1);
+            }));
+        } else if arg == "--name" {
+            name = args.next().unwrap_or_else(|| {
+                eprintln!("--name expects a player name");
+                std::process::exit(1);
+            });
+        } else if arg.starts_with("--") {
+            eprintln!("unknown option {}\n{}", arg, USAGE);
+            std::process::exit(1);
+        } else {
+            level_path = arg;
+        }
+    }
+
+    // playing on a server means playing its level, at its tick rate
+    let client = match &server_addr {
+        Some(addr) => {
+            let (client, level) = connect(addr, &name).await;
+            level_path = level;
+            tick_rate = client.tick_rate();
+            Some(client)
+        }
+        None => None,
+    };
+
+    let mut materials = Materials::new();
+    let state = match level::load(&level_path, &mut materials).await {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // the default bindings file is optional, one asked for on the command line is not
+    let bindings = match &bindings_path {
+        Some(path) => Bindings::from_file(path),
+        None if std::path::Path::new(DEFAULT_BINDINGS_PATH).exists() => {
+            Bindings::from_file(DEFAULT_BINDINGS_PATH)
+        }
+        None => Ok(Bindings::default()),
+    };
+    let bindings = bindings.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    let mut input = LocalInput::new(&state.spawn, bindings);
+    if let Some(error) = input.gamepads().error() {
+        eprintln!("gamepads unavailable: {}", error);
+    }
+    let (mut session, player_id) = match client {
+        Some(mut client) => {
+            let id = client.player_id().unwrap();
+            client.attach(state);
+            (Session::Remote(Box::new(client)), id)
+        }
+        None => {
+            let mut world = World::new(state);
+            let id = world.add_player();
+            (Session::Local(Box::new(world)), id)
+        }
+    };
+    let mut damage_flash: f32 = 0.;
+    // what killed the player last, when playing locally
+    let mut death: Option<DamageKind> = None;
+    // explosions still being drawn, with their age
+    let mut flashes = Vec::new();
+    let mut decals = Decals::new();
+    let mut timestep = FixedTimestep::new(tick_rate);
+    let mut batch = StaticBatch::new();
+    // none at all at resolution 0, so F1 can't turn shadows on
+    let mut shadow_map = (shadow_resolution > 0).then(|| ShadowMap::new(shadow_resolution));
+    let mut show_shadow_map = false;
 
     set_cursor_grab(true);
     show_mouse(false);
 
-    let skybox_texture = load_texture("textures/skybox3.png").await.unwrap();
-    let skybox = Skybox::new(100., skybox_texture.clone());
-
     loop {
         clear_background(BLACK);
         set_default_camera();
         //draw_texture(&skybox_texture, 0., 0., WHITE);
 
 
-        player.update(&mut state);
+        input.poll();
+        if is_key_pressed(TOGGLE_SHADOWS_KEY)
+            && let Some(shadow_map) = &mut shadow_map
+        {
+            shadow_map.enabled = !shadow_map.enabled;
+        }
+        if is_key_pressed(SHADOW_DEBUG_KEY) {
+            show_shadow_map = !show_shadow_map;
+        }
+        for _ in 0..timestep.advance(get_frame_time()) {
+            let cmd = input.command();
+            match &mut session {
+                Session::Local(world) => {
+                    let events = world.tick(&[(player_id, cmd)], timestep.dt());
+                    for (_, shot) in events.shots {
+                        if let Some(hit) = shot.hit {
+                            decals.add(&world.state, &hit);
+                        }
+                    }
+                    for explosion in events.explosions {
+                        flashes.push((explosion, 0.));
+                    }
+                    let player = &mut world.avatar_mut(player_id).unwrap().player;
+                    for event in player.take_events() {
+                        match event {
+                            PlayerEvent::Damaged(_) => damage_flash = DAMAGE_FLASH_TIME,
+                            PlayerEvent::Died(damage) => death = Some(damage.kind),
+                            PlayerEvent::Respawned => {
+                                input.theta = player.theta;
+                                input.phi = player.phi;
+                            }
+                        }
+                    }
+                }
+                Session::Remote(client) => {
+                    let _ = client.send_command(cmd);
+                }
+            }
+        }
+        let local = matches!(session, Session::Local(_));
+        let world = match &mut session {
+            Session::Local(world) => &**world,
+            Session::Remote(client) => {
+                // the server's word on our player comes without events, so
+                // they are read off the change instead
+                let before = client.world().unwrap().avatar(player_id).map(|avatar| avatar.player.state());
+                if let Err(e) = client.update(get_frame_time()) {
+                    eprintln!("{}", e);
+                }
+                let mut new_level = None;
+                for event in client.take_events() {
+                    match event {
+                        ClientEvent::PlayerJoined { id, name } if id != player_id => {
+                            println!("{} joined", name)
+                        }
+                        ClientEvent::LevelChanged { level } => new_level = Some(level),
+                        ClientEvent::ServerClosed(reason) => {
+                            eprintln!("the server closed: {}", reason);
+                            std::process::exit(0);
+                        }
+                        ClientEvent::PlayerLeft { id } => println!("player {} left", id),
+                        ClientEvent::Chat { from, text } => {
+                            println!("{}: {}", client.name(from).unwrap_or("?"), text)
+                        }
+                        ClientEvent::TimedOut => {
+                            eprintln!("lost the connection to the server");
+                            std::process::exit(1);
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some(level) = new_level {
+                    let state = level::load(&level, &mut materials).await.unwrap_or_else(|e| {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    });
+                    println!("now playing {}", level);
+                    input.theta = state.spawn.theta;
+                    input.phi = state.spawn.phi;
+                    client.attach(state);
+                    decals = Decals::new();
+                    batch = StaticBatch::new();
+                }
+                let world = client.world().unwrap();
+                if let (Some(before), Some(avatar)) = (before, world.avatar(player_id)) {
+                    let after = avatar.player.state();
+                    if after.health + after.armor < before.health + before.armor {
+                        damage_flash = DAMAGE_FLASH_TIME;
+                    }
+                    if before.dead_for.is_some() && after.dead_for.is_none() {
+                        input.theta = after.theta;
+                        input.phi = after.phi;
+                    }
+                }
+                client.world().unwrap()
+            }
+        };
+        decals.update(get_frame_time());
+        damage_flash = (damage_flash - get_frame_time()).max(0.);
+        for (_, age) in flashes.iter_mut() {
+            *age += get_frame_time();
+        }
+        flashes.retain(|(_, age)| *age < EXPLOSION_FLASH_TIME);
+        let state = &world.state;
+        let avatar = world.avatar(player_id).unwrap();
+        let player = &avatar.player;
+        // a client isn't sent its weapons, so has no ammo to show
+        let weapon = if local { avatar.loadout.current() } else { None };
+        let recoil = weapon.map_or(Vec2::ZERO, |weapon| weapon.recoil());
+        let camera = player.camera(timestep.alpha(), input.theta + recoil.y, input.phi + recoil.x);
+        batch.update(state);
+        if let Some(shadow_map) = &mut shadow_map {
+            shadow_map.render(state, &batch, camera.position, &materials);
+        }
+        set_camera(&camera);
+        materials.set_lighting(&state.lighting, camera.position);
+        // without a map the shadow uniforms stay zeroed, which is unshadowed
+        if let Some(shadow_map) = &shadow_map {
+            materials.set_shadows(shadow_map);
+        }
 
         draw_grid_ex(20, 1., GRAY, DARKGRAY, vec3(0., 0.01, 0.), quat(1., 0., 0., 0.));
 
-        for obj in state.objects.iter() {
-            obj.draw();        
+        batch.draw(&materials);
+        for (index, obj) in state.objects().iter().enumerate() {
+            if !batch.contains(index) {
+                obj.draw(&materials);
+            }
         }
 
-        //let material = materials::default_world();
-        //gl_use_material(&material);
-        gl_use_default_material();
-        skybox.draw();
+        decals.draw(state);
+        for projectile in &state.projectiles {
+            projectile.draw();
+        }
+        for other in world.avatars.iter().filter(|other| other.player.id != player_id) {
+            if !other.player.is_dead() {
+                other.player.draw(timestep.alpha(), PLAYER_COLOR);
+            }
+        }
+        for (explosion, age) in &flashes {
+            let t = age / EXPLOSION_FLASH_TIME;
+            let color = Color::new(1., 0.6, 0.1, 0.6 * (1. - t));
+            draw_sphere(explosion.position, explosion.radius * (0.3 + 0.7 * t), None, color);
+        }
+
+        if let Some(skybox) = &state.skybox {
+            skybox.draw(&camera, &materials);
+        }
         
         //crosshair
         gl_use_default_material();
@@ -92,7 +371,38 @@ async fn main() {
             3.,
             GREEN
         );
-        
+
+        if let Some(weapon) = weapon {
+            let ammo = if weapon.is_reloading() {
+                "reloading".to_string()
+            } else {
+                format!("{} / {}", weapon.ammo(), weapon.reserve())
+            };
+            draw_text(&ammo, screen_width() - 160., screen_height() - 30., 32., GREEN);
+        }
+        let health = format!(
+            "{:.0} / {:.0}",
+            player.health.health().ceil(),
+            player.health.armor().ceil()
+        );
+        draw_text(&health, 20., screen_height() - 30., 32., GREEN);
+        if damage_flash > 0. {
+            let alpha = 0.35 * damage_flash / DAMAGE_FLASH_TIME;
+            draw_rectangle(0., 0., screen_width(), screen_height(), Color::new(0.8, 0., 0., alpha));
+        }
+        if player.is_dead() {
+            draw_text("you died", screen_width() / 2. - 70., screen_height() / 3., 48., RED);
+            if let Some(kind) = death {
+                let cause = cause_of_death(kind);
+                let width = measure_text(cause, None, 32, 1.).width;
+                draw_text(cause, (screen_width() - width) / 2., screen_height() / 3. + 40., 32., RED);
+            }
+        }
+
+        if show_shadow_map && let Some(shadow_map) = &shadow_map {
+            shadow_map.draw_debug(&materials, 10., 10., screen_height() / 3.);
+        }
+
         next_frame().await
     }
 
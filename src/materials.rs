@@ -1,90 +1,512 @@
+use std::collections::HashMap;
+
+use macroquad::miniquad::{MipmapFilterMode, TextureWrap};
 use macroquad::prelude::*;
 
-pub fn default_world() -> Material {
+use crate::lights::{Lighting, MAX_LIGHTS};
+use crate::shadows::ShadowMap;
+
+/// Handle to a material compiled by [`Materials`]. Cheap to copy and store
+/// in objects; look the material up with [`Materials::get`] when drawing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialHandle(usize);
+
+// built-in materials, registered by Materials::new in this order
+pub const WORLD: MaterialHandle = MaterialHandle(0);
+pub const SKYBOX: MaterialHandle = MaterialHandle(1);
+pub const SHADOW: MaterialHandle = MaterialHandle(2);
+pub const SHADOW_DEBUG: MaterialHandle = MaterialHandle(3);
+const BUILTIN_NAMES: [&str; 4] = ["world", "skybox", "shadow", "shadow_debug"];
+
+/// Handle of a built-in material by name. Needs no GL context, so it works
+/// for levels loaded without a window.
+pub fn builtin(name: &str) -> Option<MaterialHandle> {
+    BUILTIN_NAMES
+        .iter()
+        .position(|builtin| *builtin == name)
+        .map(MaterialHandle)
+}
+
+/// How a lit material lays its textures onto surfaces.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UvMapping {
+    /// The mesh's own texture coordinates. Boxes and ramps generate theirs
+    /// in world units, so textures keep their size on any size of object.
+    #[default]
+    Mesh,
+    /// Projected along the world axes and blended by surface direction.
+    /// Textures line up across neighbouring objects and never stretch, but
+    /// don't follow an object when it moves.
+    Triplanar,
+}
+
+/// Color and textures of a lit material, see [`Materials::register_surface`].
+pub struct Surface {
+    /// Multiplied with the diffuse texture and the mesh's vertex colors.
+    pub color: Color,
+    pub diffuse: Option<Texture2D>,
+    /// Tangent space normal map, sharing the diffuse texture's mapping.
+    pub normal_map: Option<Texture2D>,
+    pub mapping: UvMapping,
+    /// World units (or uv units, for meshes' own coordinates) covered by one
+    /// repeat of the textures.
+    pub tile_size: f32,
+}
+
+impl Default for Surface {
+    fn default() -> Surface {
+        Surface {
+            color: WHITE,
+            diffuse: None,
+            normal_map: None,
+            mapping: UvMapping::Mesh,
+            tile_size: 1.,
+        }
+    }
+}
+
+/// Every material the game draws with, compiled once up front and then
+/// shared by handle. Compiling a shader is far too slow to do per draw call.
+pub struct Materials {
+    materials: Vec<Material>,
+    names: HashMap<String, MaterialHandle>,
+    // which materials take lights and shadows
+    lit: Vec<bool>,
+}
+
+impl Materials {
+    /// Compiles the built-in materials. Needs a GL context.
+    pub fn new() -> Materials {
+        let mut materials = Materials {
+            materials: Vec::new(),
+            names: HashMap::new(),
+            lit: Vec::new(),
+        };
+        materials.register_surface(BUILTIN_NAMES[WORLD.0], &Surface::default());
+        materials.register(BUILTIN_NAMES[SKYBOX.0], skybox());
+        materials.register(BUILTIN_NAMES[SHADOW.0], shadow());
+        materials.register(BUILTIN_NAMES[SHADOW_DEBUG.0], shadow_debug());
+        materials
+    }
+
+    /// Adds a material under `name`. Registering a name again replaces its
+    /// material and keeps the handle, so objects pick up the new one.
+    pub fn register(&mut self, name: &str, material: Material) -> MaterialHandle {
+        self.insert(name, material, false)
+    }
+
+    /// Compiles a lit material drawing with `surface`'s color and textures,
+    /// and adds it under `name` like [`Materials::register`].
+    pub fn register_surface(&mut self, name: &str, surface: &Surface) -> MaterialHandle {
+        let material = world();
+        material.set_uniform("SurfaceColor", surface.color.to_vec());
+        material.set_uniform(
+            "SurfaceParams",
+            vec4(
+                match surface.mapping {
+                    UvMapping::Mesh => 0.,
+                    UvMapping::Triplanar => 1.,
+                },
+                1. / surface.tile_size,
+                if surface.normal_map.is_some() { 1. } else { 0. },
+                0.,
+            ),
+        );
+        // left unset, textures are white
+        if let Some(diffuse) = &surface.diffuse {
+            material.set_texture("Diffuse", diffuse.clone());
+        }
+        if let Some(normal_map) = &surface.normal_map {
+            material.set_texture("NormalMap", normal_map.clone());
+        }
+        self.insert(name, material, true)
+    }
+
+    fn insert(&mut self, name: &str, material: Material, lit: bool) -> MaterialHandle {
+        if let Some(handle) = self.names.get(name) {
+            self.materials[handle.0] = material;
+            self.lit[handle.0] = lit;
+            return *handle;
+        }
+        let handle = MaterialHandle(self.materials.len());
+        self.materials.push(material);
+        self.lit.push(lit);
+        self.names.insert(name.to_string(), handle);
+        handle
+    }
+
+    pub fn find(&self, name: &str) -> Option<MaterialHandle> {
+        self.names.get(name).copied()
+    }
+
+    pub fn get(&self, handle: MaterialHandle) -> &Material {
+        &self.materials[handle.0]
+    }
+
+    /// Passes the level's lights to every lit material. Call once a frame
+    /// before drawing the world.
+    pub fn set_lighting(&self, lighting: &Lighting, eye: Vec3) {
+        for material in self.lit_materials() {
+            lighting.apply(material, eye);
+        }
+    }
+
+    /// Passes the sun's shadow map to every lit material. Call after
+    /// [`ShadowMap::render`] and before drawing the world.
+    pub fn set_shadows(&self, shadows: &ShadowMap) {
+        for material in self.lit_materials() {
+            shadows.apply(material);
+        }
+    }
+
+    fn lit_materials(&self) -> impl Iterator<Item = &Material> {
+        self.materials
+            .iter()
+            .zip(self.lit.iter())
+            .filter(|(_, lit)| **lit)
+            .map(|(material, _)| material)
+    }
+
+    /// Makes following draws use the material.
+    pub fn apply(&self, handle: MaterialHandle) {
+        gl_use_material(self.get(handle));
+    }
+}
+
+impl Default for Materials {
+    fn default() -> Materials {
+        Materials::new()
+    }
+}
+
+/// Uploads an image as a texture that repeats and is mipmapped, for textures
+/// tiled across surfaces. Needs a GL context.
+pub fn tiling_texture(image: &Image) -> Texture2D {
+    let texture = Texture2D::from_image(image);
+    let id = texture.raw_miniquad_id();
+    let ctx = unsafe { &mut get_internal_gl().quad_context };
+    ctx.texture_set_wrap(id, TextureWrap::Repeat, TextureWrap::Repeat);
+    ctx.texture_generate_mipmaps(id);
+    ctx.texture_set_min_filter(id, FilterMode::Linear, MipmapFilterMode::Linear);
+    texture
+}
+
+// lit with Lighting, colored by vertex color times the mesh's texture times
+// the surface's; register_surface sets the surface uniforms
+fn world() -> Material {
     let pipeline_params = PipelineParams {
         depth_write: true,
         depth_test: Comparison::LessOrEqual,
         ..Default::default()
     };
-    let material = load_material(
+    let fragment = format!(
+        "#version 330 core\n#define MAX_LIGHTS {}\n{}{}",
+        MAX_LIGHTS, DEPTH_PACKING, WORLD_FRAGMENT
+    );
+    load_material(
         ShaderSource::Glsl {
-            vertex: DEFAULT_VERTEX,
-            fragment: DEFAULT_FRAGMENT,
+            vertex: WORLD_VERTEX,
+            fragment: &fragment,
         },
         MaterialParams {
-            pipeline_params: pipeline_params,
-            ..Default::default()
+            pipeline_params,
+            uniforms: vec![
+                UniformDesc::new("Ambient", UniformType::Float4),
+                UniformDesc::new("SunDirection", UniformType::Float4),
+                UniformDesc::new("SunColor", UniformType::Float4),
+                UniformDesc::new("CameraPosition", UniformType::Float4),
+                UniformDesc::new("LightPosition", UniformType::Float4).array(MAX_LIGHTS),
+                UniformDesc::new("LightColor", UniformType::Float4).array(MAX_LIGHTS),
+                UniformDesc::new("LightSpot", UniformType::Float4).array(MAX_LIGHTS),
+                UniformDesc::new("ShadowMatrix", UniformType::Mat4),
+                UniformDesc::new("ShadowParams", UniformType::Float4),
+                UniformDesc::new("SurfaceColor", UniformType::Float4),
+                UniformDesc::new("SurfaceParams", UniformType::Float4),
+            ],
+            textures: vec![
+                "ShadowMap".to_string(),
+                "Diffuse".to_string(),
+                "NormalMap".to_string(),
+            ],
         }
-    ).unwrap();
-    material
+    ).unwrap()
 }
 
-pub fn skybox() -> Material {
+// writes depth seen from the sun, packed into the color channels
+fn shadow() -> Material {
     let pipeline_params = PipelineParams {
         depth_write: true,
         depth_test: Comparison::LessOrEqual,
         ..Default::default()
     };
-    let material = load_material(
+    let fragment = format!("#version 330 core\n{}{}", DEPTH_PACKING, SHADOW_FRAGMENT);
+    load_material(
+        ShaderSource::Glsl {
+            vertex: SHADOW_VERTEX,
+            fragment: &fragment,
+        },
+        MaterialParams {
+            pipeline_params,
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
+// shows a shadow map as grayscale
+fn shadow_debug() -> Material {
+    let fragment = format!("#version 330 core\n{}{}", DEPTH_PACKING, SHADOW_DEBUG_FRAGMENT);
+    load_material(
+        ShaderSource::Glsl {
+            vertex: SHADOW_DEBUG_VERTEX,
+            fragment: &fragment,
+        },
+        MaterialParams::default(),
+    )
+    .unwrap()
+}
+
+fn skybox() -> Material {
+    // the sky is drawn at the far plane, so it only passes the depth test
+    // where nothing else has been drawn
+    let pipeline_params = PipelineParams {
+        depth_write: false,
+        depth_test: Comparison::LessOrEqual,
+        ..Default::default()
+    };
+    load_material(
         ShaderSource::Glsl {
             vertex: SKYBOX_VERTEX,
             fragment: SKYBOX_FRAGMENT,
         },
         MaterialParams {
-            pipeline_params: pipeline_params,
-            ..Default::default()
+            pipeline_params,
+            uniforms: vec![
+                UniformDesc::new("SkyViewProjection", UniformType::Mat4),
+                UniformDesc::new("Tint", UniformType::Float4),
+            ],
+            textures: vec!["Sky".to_string()],
         }
-    ).unwrap();
-    material
+    ).unwrap()
 }
 
-const DEFAULT_VERTEX: &'static str = "#version 330 core
+const WORLD_VERTEX: &str = "#version 330 core
 
-//layout (location=0) in vec3 aPos;
 in vec3 position;
 in vec2 texcoord;
+in vec4 color0;
+in vec4 normal;
 
 out vec2 uv;
+out vec4 color;
 out vec3 fragPos;
+out vec3 fragNormal;
 
 uniform mat4 Model;
 uniform mat4 Projection;
 
 void main() {
-    gl_Position = Projection * Model * vec4(position, 1.0);
+    vec4 world = Model * vec4(position, 1.0);
+    gl_Position = Projection * world;
     uv = texcoord;
-    fragPos = position;
+    color = color0 / 255.0;
+    fragPos = world.xyz;
+    // objects only move rigidly, so the model matrix turns normals correctly
+    fragNormal = mat3(Model) * normal.xyz;
 }
 ";
 
-const DEFAULT_FRAGMENT: &'static str = "#version 330 core
+// depth from 0 to 1 spread over the four 8 bit channels of a color, since
+// shadow maps are plain RGBA8 render targets
+const DEPTH_PACKING: &str = "
+vec4 packDepth(float depth) {
+    // exactly 1 would wrap around to 0
+    vec4 packed = fract(min(depth, 0.999999) * vec4(1.0, 255.0, 65025.0, 16581375.0));
+    return packed - packed.yzww * vec4(1.0 / 255.0, 1.0 / 255.0, 1.0 / 255.0, 0.0);
+}
+
+float unpackDepth(vec4 packed) {
+    return dot(packed, vec4(1.0, 1.0 / 255.0, 1.0 / 65025.0, 1.0 / 16581375.0));
+}
+";
+
+// prefixed with the version, MAX_LIGHTS and DEPTH_PACKING when compiled
+const WORLD_FRAGMENT: &str = "
 in vec2 uv;
+in vec4 color;
 in vec3 fragPos;
+in vec3 fragNormal;
+
+uniform sampler2D Texture;
+
+uniform vec4 Ambient;
+// direction the sunlight travels in
+uniform vec4 SunDirection;
+uniform vec4 SunColor;
+uniform vec4 CameraPosition;
+// xyz position, w range
+uniform vec4 LightPosition[MAX_LIGHTS];
+// rgb color, w cosine of the spot's inner angle
+uniform vec4 LightColor[MAX_LIGHTS];
+// xyz spot direction, w cosine of the spot's outer angle
+uniform vec4 LightSpot[MAX_LIGHTS];
+
+// world to shadow map clip space
+uniform mat4 ShadowMatrix;
+// x 1 if shadows are on, y texel size, z depth bias, w normal offset
+uniform vec4 ShadowParams;
+uniform sampler2D ShadowMap;
+
+uniform vec4 SurfaceColor;
+// x 1 for triplanar mapping, y texture repeats per unit, z 1 if there is a
+// normal map
+uniform vec4 SurfaceParams;
+uniform sampler2D Diffuse;
+uniform sampler2D NormalMap;
 
 out vec4 fragColor;
+
+const float SHININESS = 32.0;
+const float SPECULAR = 0.25;
+
+vec3 diffuseLight;
+vec3 specularLight;
+
+void addLight(vec3 n, vec3 toLight, vec3 toEye, vec3 light) {
+    float diffuse = max(dot(n, toLight), 0.0);
+    if (diffuse <= 0.0) {
+        return;
+    }
+    vec3 halfway = normalize(toLight + toEye);
+    diffuseLight += light * diffuse;
+    specularLight += light * pow(max(dot(n, halfway), 0.0), SHININESS) * SPECULAR;
+}
+
+vec3 triplanarWeights(vec3 n) {
+    vec3 w = pow(abs(n), vec3(4.0));
+    return w / (w.x + w.y + w.z);
+}
+
+vec4 sampleDiffuse(vec3 n) {
+    if (SurfaceParams.x == 0.0) {
+        return texture(Diffuse, uv * SurfaceParams.y);
+    }
+    vec3 p = fragPos * SurfaceParams.y;
+    vec3 w = triplanarWeights(n);
+    return texture(Diffuse, p.zy) * w.x + texture(Diffuse, p.xz) * w.y + texture(Diffuse, p.xy) * w.z;
+}
+
+vec3 sampleNormal(vec2 at) {
+    return texture(NormalMap, at).xyz * 2.0 - 1.0;
+}
+
+// surface normal with the normal map applied
+vec3 mappedNormal(vec3 n) {
+    if (SurfaceParams.z == 0.0) {
+        return n;
+    }
+    if (SurfaceParams.x == 0.0) {
+        // meshes carry no tangents, so the tangent frame comes from how
+        // position and uv change across the screen
+        vec2 st = uv * SurfaceParams.y;
+        vec3 dp1 = dFdx(fragPos);
+        vec3 dp2 = dFdy(fragPos);
+        vec2 duv1 = dFdx(st);
+        vec2 duv2 = dFdy(st);
+        vec3 dp2perp = cross(dp2, n);
+        vec3 dp1perp = cross(n, dp1);
+        vec3 t = dp2perp * duv1.x + dp1perp * duv2.x;
+        vec3 b = dp2perp * duv1.y + dp1perp * duv2.y;
+        float scale = inversesqrt(max(max(dot(t, t), dot(b, b)), 1e-12));
+        // v runs down the image, normal maps have green pointing up it
+        return normalize(mat3(t * scale, -b * scale, n) * sampleNormal(st));
+    }
+    // whiteout blend of the three projections
+    vec3 p = fragPos * SurfaceParams.y;
+    vec3 w = triplanarWeights(n);
+    vec3 nx = sampleNormal(p.zy);
+    vec3 ny = sampleNormal(p.xz);
+    vec3 nz = sampleNormal(p.xy);
+    nx = vec3(nx.xy + n.zy, abs(nx.z) * n.x);
+    ny = vec3(ny.xy + n.xz, abs(ny.z) * n.y);
+    nz = vec3(nz.xy + n.xy, abs(nz.z) * n.z);
+    return normalize(nx.zyx * w.x + ny.xzy * w.y + nz.xyz * w.z);
+}
+
+// fraction of sunlight reaching the fragment, filtered over 3x3 texels
+float sunShadow(vec3 n) {
+    if (ShadowParams.x == 0.0) {
+        return 1.0;
+    }
+    vec4 pos = ShadowMatrix * vec4(fragPos + n * ShadowParams.w, 1.0);
+    vec3 coord = pos.xyz / pos.w * 0.5 + 0.5;
+    // outside the map is never shadowed
+    if (any(lessThan(coord, vec3(0.0))) || any(greaterThan(coord, vec3(1.0)))) {
+        return 1.0;
+    }
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            float depth = unpackDepth(texture(ShadowMap, coord.xy + vec2(x, y) * ShadowParams.y));
+            lit += coord.z - ShadowParams.z > depth ? 0.0 : 1.0;
+        }
+    }
+    return lit / 9.0;
+}
+
 void main() {
+    vec3 surfaceNormal = normalize(fragNormal);
+    vec4 albedo = texture(Texture, uv) * sampleDiffuse(surfaceNormal) * SurfaceColor * color;
+    vec3 n = mappedNormal(surfaceNormal);
+    vec3 toEye = normalize(CameraPosition.xyz - fragPos);
 
-    //normal vector
-    vec3 U = dFdx(fragPos);
-    vec3 V = dFdy(fragPos);
-    vec3 norm = normalize(cross(U, V));
+    diffuseLight = Ambient.rgb;
+    specularLight = vec3(0.0);
+    addLight(n, -SunDirection.xyz, toEye, SunColor.rgb * sunShadow(surfaceNormal));
 
-    //
-    vec3 lightPos = vec3(20.,10.,10.);
-    vec3 lightDir = normalize(lightPos - fragPos);
+    for (int i = 0; i < MAX_LIGHTS; i++) {
+        vec3 toLight = LightPosition[i].xyz - fragPos;
+        float range = LightPosition[i].w;
+        float dist = length(toLight);
+        if (range <= 0.0 || dist >= range) {
+            continue;
+        }
+        toLight /= dist;
+        float falloff = 1.0 - dist / range;
+        float cone = smoothstep(LightSpot[i].w, LightColor[i].w, dot(-toLight, LightSpot[i].xyz));
+        addLight(n, toLight, toEye, LightColor[i].rgb * falloff * falloff * cone);
+    }
 
-    float dif = max((dot(norm, lightDir) + 1) / 2., 0.05);
-    fragColor = vec4(abs(norm) * dif, 1.0);
+    fragColor = vec4(albedo.rgb * diffuseLight + specularLight, albedo.a);
 }
 ";
 
-const SKYBOX_VERTEX: &'static str = "#version 330 core
+const SHADOW_VERTEX: &str = "#version 330 core
+
+in vec3 position;
+
+uniform mat4 Model;
+uniform mat4 Projection;
+
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1.0);
+}
+";
+
+const SHADOW_FRAGMENT: &str = "
+out vec4 fragColor;
+
+void main() {
+    fragColor = packDepth(gl_FragCoord.z);
+}
+";
+
+const SHADOW_DEBUG_VERTEX: &str = "#version 330 core
 
 in vec3 position;
 in vec2 texcoord;
 
 out vec2 uv;
-out vec3 fragPos;
 
 uniform mat4 Model;
 uniform mat4 Projection;
@@ -92,13 +514,10 @@ uniform mat4 Projection;
 void main() {
     gl_Position = Projection * Model * vec4(position, 1.0);
     uv = texcoord;
-    fragPos = position;
 }
 ";
 
-
-const SKYBOX_FRAGMENT: &'static str = "#version 330 core
-
+const SHADOW_DEBUG_FRAGMENT: &str = "
 in vec2 uv;
 
 uniform sampler2D Texture;
@@ -106,5 +525,37 @@ uniform sampler2D Texture;
 out vec4 fragColor;
 
 void main() {
-    fragColor = texture(Texture, uv);
+    fragColor = vec4(vec3(unpackDepth(texture(Texture, uv))), 1.0);
+}
+";
+
+const SKYBOX_VERTEX: &str = "#version 330 core
+
+in vec3 position;
+
+out vec3 direction;
+
+// camera rotation and projection, without translation
+uniform mat4 SkyViewProjection;
+
+void main() {
+    vec4 pos = SkyViewProjection * vec4(position, 1.0);
+    // z = w puts every vertex at the far plane
+    gl_Position = pos.xyww;
+    direction = position;
+}
+";
+
+const SKYBOX_FRAGMENT: &str = "#version 330 core
+
+in vec3 direction;
+
+uniform samplerCube Sky;
+uniform vec4 Tint;
+
+out vec4 fragColor;
+
+void main() {
+    // GL cubemaps are left handed
+    fragColor = texture(Sky, vec3(direction.xy, -direction.z)) * Tint;
 }";
@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::path::Path;
+
+use macroquad::prelude::*;
+use nalgebra::Point3;
+use parry3d::shape::{Shape, SharedShape, TriMeshFlags};
+
+use crate::batch::BatchBuilder;
+use crate::materials::{self, MaterialHandle, Materials, Surface};
+use crate::objects::{PhysicsObject, Transform, with_transform};
+
+// macroquad clamps any single draw above these sizes, so big meshes are split
+pub(crate) const MAX_CHUNK_VERTICES: usize = 9999;
+pub(crate) const MAX_CHUNK_INDICES: usize = 4998;
+
+/// Collision shape generated for a [`MeshObject`].
+#[derive(Clone, Copy, Debug)]
+pub enum MeshCollider {
+    /// Convex hull of all vertices. Cheap, but fills in concave parts.
+    ConvexHull,
+    /// Exact triangle mesh. Use for static geometry only. Edges shared
+    /// between triangles are marked so colliders slide over them smoothly.
+    TriMesh,
+    /// Box with the given half extents, centered on the object.
+    Cuboid(Vec3),
+    Ball(f32),
+    /// Capsule along the object's y axis.
+    Capsule { half_height: f32, radius: f32 },
+}
+
+#[derive(Debug)]
+pub enum MeshError {
+    Obj {
+        path: String,
+        error: tobj::LoadError,
+    },
+    /// The OBJ names an MTL that is missing or broken.
+    Materials {
+        path: String,
+        error: tobj::LoadError,
+    },
+    Empty {
+        path: String,
+    },
+    Collider {
+        path: String,
+        reason: String,
+    },
+    Texture {
+        path: String,
+        error: String,
+    },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::Obj { path, error } => write!(f, "{}: {}", path, error),
+            MeshError::Materials { path, error } => {
+                write!(f, "{}: could not load materials: {}", path, error)
+            }
+            MeshError::Empty { path } => write!(f, "{}: mesh has no triangles", path),
+            MeshError::Collider { path, reason } => {
+                write!(f, "{}: could not build collider: {}", path, reason)
+            }
+            MeshError::Texture { path, error } => {
+                write!(f, "failed to load texture {}: {}", path, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+struct SubMesh {
+    mesh: Mesh,
+    texture_path: Option<String>,
+    normal_map_path: Option<String>,
+    // lit material carrying the normal map, if there is one
+    material: Option<MaterialHandle>,
+}
+
+// triangle geometry in object space, with scale already applied
+struct MeshGeometry {
+    vertices: Vec<Point3<f32>>,
+    indices: Vec<[u32; 3]>,
+}
+
+/// An OBJ read from disk along with its MTL and every texture that names,
+/// so that anything wrong with the files shows up before a mesh is built.
+pub struct ObjData {
+    path: String,
+    models: Vec<tobj::Model>,
+    materials: Vec<tobj::Material>,
+    // decoded textures by path
+    images: HashMap<String, Image>,
+}
+
+impl ObjData {
+    pub fn load(path: &str) -> Result<ObjData, MeshError> {
+        let (models, materials) =
+            tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS).map_err(|error| MeshError::Obj {
+                path: path.to_string(),
+                error,
+            })?;
+        // an OBJ without an MTL loads with no materials and is drawn white
+        let materials = materials.map_err(|error| MeshError::Materials {
+            path: path.to_string(),
+            error,
+        })?;
+        if models.iter().all(|model| model.mesh.indices.len() < 3) {
+            return Err(MeshError::Empty {
+                path: path.to_string(),
+            });
+        }
+
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let mut images = HashMap::new();
+        // MTL bump maps are what exporters use for normal maps
+        for name in materials
+            .iter()
+            .flat_map(|m| [&m.diffuse_texture, &m.normal_texture])
+            .flatten()
+        {
+            let texture = dir.join(name).to_string_lossy().into_owned();
+            if let Entry::Vacant(entry) = images.entry(texture) {
+                let image = load_image_sync(entry.key())?;
+                entry.insert(image);
+            }
+        }
+
+        Ok(ObjData {
+            path: path.to_string(),
+            models,
+            materials,
+            images,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+fn load_image_sync(path: &str) -> Result<Image, MeshError> {
+    let error = |error: String| MeshError::Texture {
+        path: path.to_string(),
+        error,
+    };
+    let bytes = std::fs::read(path).map_err(|e| error(e.to_string()))?;
+    Image::from_file_with_format(&bytes, None).map_err(|e| error(e.to_string()))
+}
+
+/// A model built from an OBJ file, with diffuse colors and textures taken
+/// from its MTL.
+pub struct MeshObject {
+    transform: Transform,
+    submeshes: Vec<SubMesh>,
+    collider: SharedShape,
+    pub material: MaterialHandle,
+}
+
+impl MeshObject {
+    /// Builds a mesh and its collider from a loaded OBJ. Does not touch
+    /// the GPU; call [`MeshObject::load_textures`] before drawing.
+    pub fn new(
+        obj: &ObjData,
+        transform: Transform,
+        scale: Vec3,
+        collider: MeshCollider,
+    ) -> Result<MeshObject, MeshError> {
+        let (submeshes, geometry) = build_meshes(obj, scale);
+        let collider = build_collider(&geometry, collider).map_err(|reason| {
+            MeshError::Collider {
+                path: obj.path.clone(),
+                reason,
+            }
+        })?;
+
+        Ok(MeshObject {
+            transform,
+            submeshes,
+            collider,
+            material: materials::WORLD,
+        })
+    }
+
+    /// Uploads the diffuse textures of `obj`, the OBJ the mesh was built
+    /// from, and registers a material for every normal map since those
+    /// can't be passed along with the mesh.
+    pub fn load_textures(&mut self, obj: &ObjData, materials: &mut Materials) {
+        let mut cache: HashMap<String, Texture2D> = HashMap::new();
+        let mut load = |path: &String| {
+            cache
+                .entry(path.clone())
+                .or_insert_with(|| materials::tiling_texture(&obj.images[path]))
+                .clone()
+        };
+        for submesh in self.submeshes.iter_mut() {
+            if let Some(path) = &submesh.texture_path {
+                submesh.mesh.texture = Some(load(path));
+            }
+            if let Some(path) = &submesh.normal_map_path {
+                // named after the normal map, so meshes sharing one share the material
+                let handle = match materials.find(path) {
+                    Some(handle) => handle,
+                    None => {
+                        let surface = Surface {
+                            normal_map: Some(load(path)),
+                            ..Default::default()
+                        };
+                        materials.register_surface(path, &surface)
+                    }
+                };
+                submesh.material = Some(handle);
+            }
+        }
+    }
+}
+
+impl PhysicsObject for MeshObject {
+    fn get_transform(&self) -> Transform {
+        self.transform
+    }
+    fn get_collider(&self) -> &dyn Shape {
+        &*self.collider
+    }
+    fn draw(&self, materials: &Materials) {
+        with_transform(&self.transform, || {
+            for submesh in self.submeshes.iter() {
+                materials.apply(submesh.material.unwrap_or(self.material));
+                draw_mesh(&submesh.mesh);
+            }
+        });
+    }
+    fn draw_shape(&self) {
+        with_transform(&self.transform, || {
+            for submesh in self.submeshes.iter() {
+                draw_mesh(&submesh.mesh);
+            }
+        });
+    }
+    fn batch(&self, batch: &mut BatchBuilder) -> bool {
+        for submesh in self.submeshes.iter() {
+            let material = submesh.material.unwrap_or(self.material);
+            batch.add_mesh(Some(material), &self.transform, &submesh.mesh);
+        }
+        true
+    }
+}
+
+// for OBJs without normals: each vertex gets the area weighted average of
+// the normals of the triangles using it
+fn smooth_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut normals = vec![Vec3::ZERO; vertices.len()];
+    for t in indices.chunks_exact(3) {
+        let [a, b, c] = [t[0], t[1], t[2]].map(|i| vertices[i as usize].position);
+        // not normalized, so larger triangles weigh more
+        let normal = (b - a).cross(c - a);
+        for i in t {
+            normals[*i as usize] += normal;
+        }
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        vertex.normal = normal.normalize_or_zero().extend(0.);
+    }
+}
+
+// render meshes, and the triangles for the collider, of `obj` scaled by `scale`
+fn build_meshes(obj: &ObjData, scale: Vec3) -> (Vec<SubMesh>, MeshGeometry) {
+    let dir = Path::new(&obj.path).parent().unwrap_or(Path::new(""));
+
+    let mut submeshes = Vec::new();
+    let mut geometry = MeshGeometry {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+    };
+
+    for model in obj.models.iter() {
+        let mesh = &model.mesh;
+        let material = mesh.material_id.and_then(|id| obj.materials.get(id));
+        let color = material
+            .and_then(|m| m.diffuse)
+            .map(|[r, g, b]| Color::new(r, g, b, 1.))
+            .unwrap_or(WHITE);
+        let texture_path = material
+            .and_then(|m| m.diffuse_texture.as_ref())
+            .map(|t| dir.join(t).to_string_lossy().into_owned());
+        let normal_map_path = material
+            .and_then(|m| m.normal_texture.as_ref())
+            .map(|t| dir.join(t).to_string_lossy().into_owned());
+
+        let vertex_count = mesh.positions.len() / 3;
+        let mut vertices = Vec::with_capacity(vertex_count);
+        for i in 0..vertex_count {
+            let position = vec3(
+                mesh.positions[3 * i],
+                mesh.positions[3 * i + 1],
+                mesh.positions[3 * i + 2],
+            ) * scale;
+            let uv = if mesh.texcoords.len() >= 2 * (i + 1) {
+                // OBJ has v pointing up, textures are stored top row first
+                vec2(mesh.texcoords[2 * i], 1. - mesh.texcoords[2 * i + 1])
+            } else {
+                Vec2::ZERO
+            };
+            let normal = if mesh.normals.len() >= 3 * (i + 1) {
+                // inverse scale keeps normals perpendicular under non-uniform scaling
+                (vec3(
+                    mesh.normals[3 * i],
+                    mesh.normals[3 * i + 1],
+                    mesh.normals[3 * i + 2],
+                ) / scale)
+                    .normalize_or_zero()
+            } else {
+                // computed from the triangles below
+                Vec3::ZERO
+            };
+            vertices.push(Vertex {
+                position,
+                uv,
+                color: color.into(),
+                normal: normal.extend(0.),
+            });
+        }
+        if mesh.normals.is_empty() {
+            smooth_normals(&mut vertices, &mesh.indices);
+        }
+
+        let base = geometry.vertices.len() as u32;
+        geometry
+            .vertices
+            .extend(vertices.iter().map(|v| Point3::from(v.position.to_array())));
+        geometry.indices.extend(
+            mesh.indices
+                .chunks_exact(3)
+                .map(|t| [base + t[0], base + t[1], base + t[2]]),
+        );
+
+        for mesh in split_into_chunks(&vertices, &mesh.indices) {
+            submeshes.push(SubMesh {
+                mesh,
+                texture_path: texture_path.clone(),
+                normal_map_path: normal_map_path.clone(),
+                material: None,
+            });
+        }
+    }
+
+    (submeshes, geometry)
+}
+
+// splits a mesh into pieces small enough for one macroquad draw call and
+// addressable by u16 indices
+pub(crate) fn split_into_chunks(vertices: &[Vertex], indices: &[u32]) -> Vec<Mesh> {
+    let mut chunks = Vec::new();
+    let mut remap: HashMap<u32, u16> = HashMap::new();
+    let mut chunk = Mesh {
+        vertices: Vec::new(),
+        indices: Vec::new(),
+        texture: None,
+    };
+
+    for triangle in indices.chunks_exact(3) {
+        if chunk.vertices.len() + 3 > MAX_CHUNK_VERTICES
+            || chunk.indices.len() + 3 > MAX_CHUNK_INDICES
+        {
+            chunks.push(chunk);
+            chunk = Mesh {
+                vertices: Vec::new(),
+                indices: Vec::new(),
+                texture: None,
+            };
+            remap.clear();
+        }
+        for idx in triangle.iter() {
+            let local = *remap.entry(*idx).or_insert_with(|| {
+                chunk.vertices.push(vertices[*idx as usize]);
+                (chunk.vertices.len() - 1) as u16
+            });
+            chunk.indices.push(local);
+        }
+    }
+    if !chunk.indices.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+fn build_collider(geometry: &MeshGeometry, kind: MeshCollider) -> Result<SharedShape, String> {
+    match kind {
+        MeshCollider::ConvexHull => SharedShape::convex_hull(&geometry.vertices)
+            .ok_or_else(|| "mesh is flat or degenerate".to_string()),
+        MeshCollider::TriMesh => SharedShape::trimesh_with_flags(
+            geometry.vertices.clone(),
+            geometry.indices.clone(),
+            // ORIENTED gives the mesh an inside for point containment tests
+            TriMeshFlags::FIX_INTERNAL_EDGES
+                | TriMeshFlags::ORIENTED
+                | TriMeshFlags::DELETE_DEGENERATE_TRIANGLES,
+        )
+        .map_err(|e| format!("{:?}", e)),
+        MeshCollider::Cuboid(half_extents) => Ok(SharedShape::cuboid(
+            half_extents.x,
+            half_extents.y,
+            half_extents.z,
+        )),
+        MeshCollider::Ball(radius) => Ok(SharedShape::ball(radius)),
+        MeshCollider::Capsule {
+            half_height,
+            radius,
+        } => Ok(SharedShape::capsule_y(half_height, radius)),
+    }
+}
@@ -1,80 +1,167 @@
 use macroquad::prelude::*;
-use nalgebra::{Point3, Vector3};
+use nalgebra::{Isometry3, Point3, Quaternion, Translation3, UnitQuaternion, Vector3};
+use parry3d::bounding_volume::Aabb;
 use parry3d::shape::{ConvexPolyhedron, Cuboid, Shape};
-use tobj::load_obj;
 
-use crate::materials;
+use crate::batch::BatchBuilder;
+use crate::materials::{self, MaterialHandle, Materials};
+use crate::rigidbody::RigidBody;
+
+/// Placement of an object in the world. Scale is not part of the transform;
+/// it is baked into each object's collider and draw geometry instead, since
+/// parry3d shapes can only be moved rigidly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+impl Transform {
+    pub fn new(position: Vec3, rotation: Quat) -> Transform {
+        Transform {
+            position,
+            rotation: rotation.normalize(),
+        }
+    }
+
+    pub fn from_position(position: Vec3) -> Transform {
+        Transform::new(position, Quat::IDENTITY)
+    }
+
+    pub fn isometry(&self) -> Isometry3<f32> {
+        let r = self.rotation;
+        Isometry3::from_parts(
+            Translation3::new(self.position.x, self.position.y, self.position.z),
+            UnitQuaternion::new_unchecked(Quaternion::new(r.w, r.x, r.y, r.z)),
+        )
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_rotation_translation(self.rotation, self.position)
+    }
+}
 
 pub trait PhysicsObject {
-    fn get_position(&self) -> Vec3;
-    fn get_collider(&self) -> Box<dyn Shape>;
-    fn draw(&self);
+    fn get_transform(&self) -> Transform;
+    /// Collider in object space, placed in the world by `get_transform`.
+    fn get_collider(&self) -> &dyn Shape;
+    fn draw(&self, materials: &Materials);
+
+    /// Draws the object's triangles with whatever material is in use, for
+    /// passes like the shadow map that replace every object's material.
+    fn draw_shape(&self) {}
+
+    /// Adds the object's triangles to the static batch and returns true, if it
+    /// can be drawn that way. Batched objects are not drawn individually.
+    fn batch(&self, _batch: &mut BatchBuilder) -> bool {
+        false
+    }
+
+    /// Static objects are expected to move rarely if ever; moving one
+    /// rebuilds the static batch.
+    fn is_static(&self) -> bool {
+        true
+    }
+
+    /// World-space bounding box, used by the broad phase.
+    fn aabb(&self) -> Aabb {
+        self.get_collider().compute_aabb(&self.get_transform().isometry())
+    }
+
+    /// The object's body, if it is moved by the physics rather than fixed in
+    /// place.
+    fn rigid_body(&self) -> Option<&RigidBody> {
+        None
+    }
+
+    fn rigid_body_mut(&mut self) -> Option<&mut RigidBody> {
+        None
+    }
 }
 
+/// Base color of boxes and ramps that don't set one.
+pub const DEFAULT_COLOR: Color = Color::new(0.8, 0.8, 0.8, 1.);
+
+// draws with the object's transform applied on top of the current model matrix
+pub(crate) fn with_transform(transform: &Transform, f: impl FnOnce()) {
+    let gl = unsafe { &mut macroquad::window::get_internal_gl().quad_gl };
+    gl.push_model_matrix(transform.matrix());
+    f();
+    let gl = unsafe { &mut macroquad::window::get_internal_gl().quad_gl };
+    gl.pop_model_matrix();
+}
 
 pub struct CollisionBox {
-    position: Vec3,
-    half_extents: Vec3,
+    transform: Transform,
+    mesh: Mesh,
     pub collider: Cuboid,
+    pub material: MaterialHandle,
 }
 
 impl CollisionBox {
-    //TODO: rotation?
-    pub fn new(position: Vec3, half_extents: Vec3) -> CollisionBox { 
+    pub fn new(transform: Transform, half_extents: Vec3) -> CollisionBox { 
         CollisionBox {
-            position: position,
-            half_extents: half_extents,
+            transform,
+            mesh: box_mesh(half_extents, DEFAULT_COLOR),
             collider: Cuboid::new(
                 Vector3::new(half_extents.x, half_extents.y, half_extents.z)
-            )
+            ),
+            material: materials::WORLD,
         }
     }
+
+    /// Base color, multiplied with the texture if there is one.
+    pub fn set_color(&mut self, color: Color) {
+        set_mesh_color(&mut self.mesh, color);
+    }
+
+    pub fn set_texture(&mut self, texture: Option<Texture2D>) {
+        self.mesh.texture = texture;
+    }
 }
 
 impl PhysicsObject for CollisionBox {
-    fn get_collider(&self) -> Box<dyn Shape> {
-        Box::new(self.collider)
-    }
-    fn get_position(&self) -> Vec3 {
-        self.position
-    }
-    fn draw(&self) {
-        let material = materials::default_world();
-        gl_use_material(&material);
-        draw_cube(
-            self.position,
-            2. * self.half_extents,
-            None,
-            GRAY
-        );
-        unsafe { macroquad::window::get_internal_gl().flush(); }
+    fn get_collider(&self) -> &dyn Shape {
+        &self.collider
+    }
+    fn get_transform(&self) -> Transform {
+        self.transform
+    }
+    fn draw(&self, materials: &Materials) {
+        materials.apply(self.material);
+        self.draw_shape();
+    }
+    fn draw_shape(&self) {
+        with_transform(&self.transform, || draw_mesh(&self.mesh));
+    }
+    fn batch(&self, batch: &mut BatchBuilder) -> bool {
+        batch.add_mesh(Some(self.material), &self.transform, &self.mesh);
+        true
     }
 }
 
 pub struct CollisionRamp {
-    position: Vec3,
-    theta: f32,
-    half_extents: Vec3,
-    points: [Vec3; 6],
-    faces: [[u32; 3]; 8],
+    transform: Transform,
+    mesh: Mesh,
     pub collider: ConvexPolyhedron,
+    pub material: MaterialHandle,
 }
 impl CollisionRamp {
-    pub fn new(position: Vec3, theta: f32, half_extents: Vec3) -> CollisionRamp {
-        let transform = Mat3::from_rotation_y(theta);
+    /// Wedge rising from the -z edge of its base to the +z edge.
+    pub fn new(transform: Transform, half_extents: Vec3) -> CollisionRamp {
         let points = [
                //bottom left front
-               transform * vec3(-half_extents.x, -half_extents.y, -half_extents.z),
+               vec3(-half_extents.x, -half_extents.y, -half_extents.z),
                //bottom right front
-               transform * vec3(half_extents.x, -half_extents.y, -half_extents.z),
+               vec3(half_extents.x, -half_extents.y, -half_extents.z),
                //bottom right back
-               transform * vec3(half_extents.x, -half_extents.y, half_extents.z),
+               vec3(half_extents.x, -half_extents.y, half_extents.z),
                //bottom left back
-               transform * vec3(-half_extents.x, -half_extents.y, half_extents.z),
+               vec3(-half_extents.x, -half_extents.y, half_extents.z),
                //top left back
-               transform * vec3(-half_extents.x, half_extents.y, half_extents.z),
+               vec3(-half_extents.x, half_extents.y, half_extents.z),
                //top right back
-               transform * vec3(half_extents.x, half_extents.y, half_extents.z),
+               vec3(half_extents.x, half_extents.y, half_extents.z),
             ];
             let faces = [
                 //bottom face
@@ -92,261 +179,183 @@ impl CollisionRamp {
         let points_nalgebra = points.iter().map(|x| Point3::from(x.to_array())).collect();
 
         CollisionRamp {
-            position: position,
-            theta: theta,
-            half_extents: half_extents,
-            points: points,
-            faces: faces,
+            transform,
+            mesh: ramp_mesh(&points, &faces, DEFAULT_COLOR),
             collider: ConvexPolyhedron::from_convex_mesh(
                 points_nalgebra,
                 &faces
-            ).unwrap()
+            ).unwrap(),
+            material: materials::WORLD,
         }
     }
+
+    /// Base color, multiplied with the texture if there is one.
+    pub fn set_color(&mut self, color: Color) {
+        set_mesh_color(&mut self.mesh, color);
+    }
+
+    pub fn set_texture(&mut self, texture: Option<Texture2D>) {
+        self.mesh.texture = texture;
+    }
 }
 
 impl PhysicsObject for CollisionRamp {
-    fn get_collider(&self) -> Box<dyn Shape> {
-        Box::new(self.collider.clone())
-    }
-    fn get_position(&self) -> Vec3 {
-        self.position
-    }
-    fn draw(&self) {
-        let material = materials::default_world();
-        gl_use_material(&material);
-        //
-        let uv = [
-            vec2(0., 0.),
-            vec2(1., 0.),
-            vec2(1., 1.),
-            vec2(0., 1.),
-            vec2(0., 1.),
-            vec2(1., 1.),
-        ];
-        for face in self.faces.iter() {
-            let mut vertices: Vec<Vertex> = Vec::new();
-            for idx in face.iter() {
-                vertices.push(
-                    Vertex {
-                        position: self.points[*idx as usize] + self.position,
-                        uv: uv[*idx as usize],
-                        color: [255, 255, 255, 255],
-                        normal: Vec4::ZERO
-                    }
-                );
-            }
-            draw_mesh(
-                &Mesh {
-                    vertices: vertices,
-                    indices: vec![0, 1, 2],
-                    texture: None
-                }
-            );
+    fn get_collider(&self) -> &dyn Shape {
+        &self.collider
+    }
+    fn get_transform(&self) -> Transform {
+        self.transform
+    }
+    fn draw(&self, materials: &Materials) {
+        materials.apply(self.material);
+        self.draw_shape();
+    }
+    fn draw_shape(&self) {
+        with_transform(&self.transform, || draw_mesh(&self.mesh));
+    }
+    fn batch(&self, batch: &mut BatchBuilder) -> bool {
+        batch.add_mesh(Some(self.material), &self.transform, &self.mesh);
+        true
+    }
+}
+
+// box centered on the origin, with its own vertices per face so each face
+// gets a flat normal and its own uvs
+pub(crate) fn box_mesh(half_extents: Vec3, color: Color) -> Mesh {
+    // normal, then the two axes spanning the face
+    let faces = [
+        (Vec3::X, Vec3::NEG_Z, Vec3::Y),
+        (Vec3::NEG_X, Vec3::Z, Vec3::Y),
+        (Vec3::Y, Vec3::X, Vec3::NEG_Z),
+        (Vec3::NEG_Y, Vec3::X, Vec3::Z),
+        (Vec3::Z, Vec3::X, Vec3::Y),
+        (Vec3::NEG_Z, Vec3::NEG_X, Vec3::Y),
+    ];
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, u, v) in faces {
+        let base = vertices.len() as u16;
+        for (du, dv) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)] {
+            let position = (normal + u * du + v * dv) * half_extents;
+            vertices.push(Vertex {
+                position,
+                uv: face_uv(position, normal),
+                color: color.into(),
+                normal: normal.extend(0.),
+            });
         }
-        unsafe { macroquad::window::get_internal_gl().flush(); }
+        indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+    Mesh {
+        vertices,
+        indices,
+        texture: None,
     }
 }
 
-pub struct Skybox {
-    size: f32,
-    texture: Texture2D
+// flat shaded ramp, one set of vertices per triangle
+fn ramp_mesh(points: &[Vec3; 6], faces: &[[u32; 3]; 8], color: Color) -> Mesh {
+    let center = points.iter().sum::<Vec3>() / points.len() as f32;
+    let mut vertices = Vec::with_capacity(24);
+    for face in faces.iter() {
+        let [a, b, c] = face.map(|i| points[i as usize]);
+        let mut normal = (b - a).cross(c - a).normalize_or_zero();
+        // the face list isn't consistently wound, so point normals away from the middle
+        if normal.dot(a - center) < 0. {
+            normal = -normal;
+        }
+        for idx in face.iter() {
+            vertices.push(Vertex {
+                position: points[*idx as usize],
+                uv: face_uv(points[*idx as usize], normal),
+                color: color.into(),
+                normal: normal.extend(0.),
+            });
+        }
+    }
+    Mesh {
+        indices: (0..vertices.len() as u16).collect(),
+        vertices,
+        texture: None,
+    }
 }
 
+// upright cylinder centered on the origin, smooth around the side and flat
+// on the caps
+pub(crate) fn cylinder_mesh(radius: f32, half_height: f32, segments: u16, color: Color) -> Mesh {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let around = |i: u16| {
+        let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+        vec3(angle.cos(), 0., angle.sin())
+    };
+
+    // the seam gets its own vertices so the side's uvs can wrap
+    let circumference = std::f32::consts::TAU * radius;
+    for i in 0..=segments {
+        let normal = around(i);
+        let u = i as f32 / segments as f32 * circumference;
+        for (y, v) in [(-half_height, 2. * half_height), (half_height, 0.)] {
+            vertices.push(Vertex {
+                position: normal * radius + vec3(0., y, 0.),
+                uv: vec2(u, v),
+                color: color.into(),
+                normal: normal.extend(0.),
+            });
+        }
+    }
+    for i in 0..segments {
+        let base = 2 * i;
+        indices.extend([base, base + 1, base + 3, base, base + 3, base + 2]);
+    }
 
-// TODO: make this not suck
-impl Skybox {
-    pub fn new(size: f32, texture: Texture2D) -> Skybox {
-        Skybox {
-            size: size,
-            texture: texture
+    for normal in [Vec3::Y, Vec3::NEG_Y] {
+        let center = vertices.len() as u16;
+        let position = normal * half_height;
+        vertices.push(Vertex {
+            position,
+            uv: face_uv(position, normal),
+            color: color.into(),
+            normal: normal.extend(0.),
+        });
+        for i in 0..segments {
+            let position = around(i) * radius + normal * half_height;
+            vertices.push(Vertex {
+                position,
+                uv: face_uv(position, normal),
+                color: color.into(),
+                normal: normal.extend(0.),
+            });
+        }
+        for i in 0..segments {
+            let a = center + 1 + i;
+            let b = center + 1 + (i + 1) % segments;
+            indices.extend([center, a, b]);
         }
     }
-    pub fn draw(&self) {
-        let bottom1 = Mesh {
-            vertices: vec![
-                //front bottom left
-                Vertex::new(-self.size, -self.size, -self.size, 0.25, 1.0, WHITE),
-                //front bottom right
-                Vertex::new(self.size, -self.size, -self.size, 0.5, 1.0, WHITE),
-                //back bottom right
-                Vertex::new(self.size, -self.size, self.size, 0.5, 2./3., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-        let bottom2 = Mesh {
-            vertices: vec![
-                //front bottom left
-                Vertex::new(-self.size, -self.size, -self.size, 0.25, 1.0, WHITE),
-                //back bottom right
-                Vertex::new(self.size, -self.size, self.size, 0.5, 2./3., WHITE),
-                //back bottom left
-                Vertex::new(-self.size, -self.size, self.size, 0.25, 2./3., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-
-        let front1 = Mesh {
-            vertices: vec![
-                //front bottom left
-                Vertex::new(-self.size, -self.size, -self.size, 0.25, 2./3., WHITE),
-                //front bottom right
-                Vertex::new(self.size, -self.size, -self.size, 0.5, 2./3., WHITE),
-                //front top right
-                Vertex::new(self.size, self.size, -self.size, 0.5, 1./3., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-
-        let front2 = Mesh {
-            vertices: vec![
-                //front bottom left
-                Vertex::new(-self.size, -self.size, -self.size, 0.25, 2./3., WHITE),
-                //front top right
-                Vertex::new(self.size, self.size, -self.size, 0.5, 1./3., WHITE),
-                //front top left
-                Vertex::new(-self.size, self.size, -self.size, 0.25, 1./3., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-
-        let left1 = Mesh {
-            vertices: vec![
-                //front bottom left
-                Vertex::new(-self.size, -self.size, -self.size, 0.25, 2./3., WHITE),
-                //back bottom left
-                Vertex::new(-self.size, -self.size, self.size, 0., 2./3., WHITE),
-                //front top left
-                Vertex::new(-self.size, self.size, -self.size, 0.25, 1./3., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-
-        let left2 = Mesh {
-            vertices: vec![
-                //back bottom left
-                Vertex::new(-self.size, -self.size, self.size, 0., 2./3., WHITE),
-                //front top left
-                Vertex::new(-self.size, self.size, -self.size, 0.25, 1./3., WHITE),
-                //back top left
-                Vertex::new(-self.size, self.size, self.size, 0., 1./3., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-
-        let top1 = Mesh {
-            vertices: vec![
-                //front top left
-                Vertex::new(-self.size, self.size, -self.size, 0.25, 1./3., WHITE),
-                //front top right
-                Vertex::new(self.size, self.size, -self.size, 0.5, 1./3., WHITE),
-                //back top right
-                Vertex::new(self.size, self.size, self.size, 0.5, 0., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-
-        let top2 = Mesh {
-            vertices: vec![
-                //front top left
-                Vertex::new(-self.size, self.size, -self.size, 0.25, 1./3., WHITE),
-                //back top right
-                Vertex::new(self.size, self.size, self.size, 0.5, 0., WHITE),
-                //back top left
-                Vertex::new(-self.size, self.size, self.size, 0.25, 0., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-
-        let right1 = Mesh {
-            vertices: vec![
-                //front bottom right
-                Vertex::new(self.size, -self.size, -self.size, 0.5, 2./3., WHITE),
-                //back bottom right
-                Vertex::new(self.size, -self.size, self.size, 0.75, 2./3., WHITE),
-                //back top right
-                Vertex::new(self.size, self.size, self.size, 0.75, 1./3., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-
-        let right2 = Mesh {
-            vertices: vec![
-                //front bottom right
-                Vertex::new(self.size, -self.size, -self.size, 0.5, 2./3., WHITE),
-                //back top right
-                Vertex::new(self.size, self.size, self.size, 0.75, 1./3., WHITE),
-                //front top right
-                Vertex::new(self.size, self.size, -self.size, 0.5, 1./3., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-
-        let back1 = Mesh {
-            vertices: vec![
-                //back bottom right
-                Vertex::new(self.size, -self.size, self.size, 0.75, 2./3., WHITE),
-                //back bottom left
-                Vertex::new(-self.size, -self.size, self.size, 1., 2./3., WHITE),
-                //back top left
-                Vertex::new(-self.size, self.size, self.size, 1., 1./3., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-
-        let back2 = Mesh {
-            vertices: vec![
-                //back bottom right
-                Vertex::new(self.size, -self.size, self.size, 0.75, 2./3., WHITE),
-                //back top left
-                Vertex::new(-self.size, self.size, self.size, 1., 1./3., WHITE),
-                //back top right
-                Vertex::new(self.size, self.size, self.size, 0.75, 1./3., WHITE),
-            ],
-            indices: vec![0, 2, 1],
-            texture: Some(self.texture.clone())
-        };
-
-        let material = materials::skybox();
-        gl_use_material(&material);
-        draw_mesh(&bottom1);
-        draw_mesh(&bottom2);
-        draw_mesh(&front1);
-        draw_mesh(&front2);
-        draw_mesh(&left1);
-        draw_mesh(&left2);
-        draw_mesh(&top1);
-        draw_mesh(&top2);
-        draw_mesh(&right1);
-        draw_mesh(&right2);
-        draw_mesh(&back1);
-        draw_mesh(&back2);
-
-        unsafe { macroquad::window::get_internal_gl().flush(); }
+
+    Mesh {
+        vertices,
+        indices,
+        texture: None,
     }
 }
 
-pub struct Cat {
-    position: Vec3,
-    scale: f32,
+// texture coordinates in world units, projected onto the plane of a face, so
+// textures tile at the same density on any size of object. u runs
+// horizontally and v downwards, as in images, on walls.
+fn face_uv(position: Vec3, normal: Vec3) -> Vec2 {
+    let u = if normal.y.abs() > 0.99 {
+        Vec3::X
+    } else {
+        Vec3::Y.cross(normal).normalize()
+    };
+    let v = u.cross(normal);
+    vec2(position.dot(u), position.dot(v))
 }
 
-impl Cat {
-    pub fn new(position: Vec3, scale: f32) -> Cat {
-        Cat {
-            position,
-            scale
-        }
+pub(crate) fn set_mesh_color(mesh: &mut Mesh, color: Color) {
+    for vertex in mesh.vertices.iter_mut() {
+        vertex.color = color.into();
     }
 }
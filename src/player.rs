@@ -1,160 +1,500 @@
-use crate::game::State;
-use crate::materials::*;
+use crate::controller::CharacterController;
+use crate::game::{Spawn, State};
+use crate::health::{Damage, DamageKind, Health};
+use crate::input::InputCommand;
+use crate::projectile::Explosion;
+use crate::rewind::Hitbox;
+use crate::rigidbody::PLAYER_MASS;
 use macroquad::prelude::*;
 
 use std::f32::consts::PI;
 
-use nalgebra::{Isometry3, Vector3};
+// how far from the capsule bodies count as walked into
+const PUSH_MARGIN: f32 = 0.02;
 
-use parry3d::math::Point;
-use parry3d::shape::{Capsule, Cuboid, Shape};
-use parry3d::query::contact::{Contact, contact};
+/// Movement tuning, Quake style: speeds are the top speed the input
+/// accelerates towards, not a speed that gets applied directly. Speeds are
+/// units per second, accelerations are in multiples of the target speed per
+/// second.
+pub struct MovementConfig {
+    pub walk_speed: f32,
+    pub sprint_speed: f32,
+    pub crouch_speed: f32,
+    pub jump_speed: f32,
+    /// Units per second squared.
+    pub gravity: f32,
+    pub ground_accel: f32,
+    pub air_accel: f32,
+    /// Cap on the speed air control accelerates towards. Keeping it low is
+    /// what makes strafe jumping gain speed: turning into the strafe keeps the
+    /// cap ahead of the current velocity.
+    pub air_speed_cap: f32,
+    pub friction: f32,
+    /// Below this speed friction acts as if the player moved this fast, so
+    /// they come to a full stop instead of creeping.
+    pub stop_speed: f32,
+    /// Capsule half height while crouched.
+    pub crouch_half_height: f32,
+}
+
+impl Default for MovementConfig {
+    fn default() -> MovementConfig {
+        MovementConfig {
+            walk_speed: 6.,
+            sprint_speed: 9.,
+            crouch_speed: 3.,
+            jump_speed: 9.,
+            gravity: 28.8,
+            ground_accel: 10.,
+            air_accel: 10.,
+            air_speed_cap: 0.6,
+            friction: 6.,
+            stop_speed: 2.,
+            crouch_half_height: 0.05,
+        }
+    }
+}
 
+pub fn look_direction(theta: f32, phi: f32) -> Vec3 {
+    vec3(
+        phi.cos() * theta.cos(),
+        phi.sin(),
+        -phi.cos() * theta.sin(),
+    )
+}
 
-const MOVE_SPEED: f32 = 0.1;
-const GRAVITY: f32 = 0.008;
+/// Something that happened to a player, for the HUD, sounds or the network
+/// to react to. Collected until [`Player::take_events`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayerEvent {
+    Damaged(Damage),
+    /// The damage that did it.
+    Died(Damage),
+    Respawned,
+}
+
+/// Everything about a player that changes from tick to tick, for sending
+/// over the network or putting a player back the way they were.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayerState {
+    pub id: u32,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub theta: f32,
+    pub phi: f32,
+    pub on_ground: bool,
+    pub crouched: bool,
+    pub health: f32,
+    pub armor: f32,
+    /// Seconds since dying, while dead.
+    pub dead_for: Option<f32>,
+}
 
 pub struct Player {
+    /// Tells players apart, e.g. to know whose explosion hit whom.
+    pub id: u32,
     pub position: Vec3,
     pub theta: f32,
     pub phi: f32,
     pub target: Vec3,
-    normal: Vec3,    
+    pub controller: CharacterController,
+    pub movement: MovementConfig,
+    pub health: Health,
     velocity: Vec3,
-    new_position: Vec3,
+    // position and eye height at the start of the last tick, for render interpolation
+    prev_position: Vec3,
+    prev_eye_height: f32,
     is_on_ground: bool,
-
+    is_crouched: bool,
+    // capsule half height when standing, restored when uncrouching
+    standing_half_height: f32,
+    // seconds since dying, while dead
+    dead_for: Option<f32>,
+    // bodies walked into during the last tick, as (object, contact point,
+    // velocity into it)
+    pushes: Vec<(usize, Vec3, Vec3)>,
+    events: Vec<PlayerEvent>,
 }
 
 impl Player {
-    pub fn new() -> Player {
+    pub fn new(spawn: &Spawn) -> Player {
+        let controller = CharacterController::default();
+        let standing_half_height = controller.config.half_height;
+        let prev_eye_height = controller.height();
         Player {
-            position: vec3(0., 10., 0.),
-            theta: 0.,
-            phi: 0., //target: vec3(0.,-0.2,1.),
-            target: vec3(0., 0., -1.),
-            normal: vec3(0., 1., 0.),
+            id: 0,
+            position: spawn.position,
+            theta: spawn.theta,
+            phi: spawn.phi,
+            target: look_direction(spawn.theta, spawn.phi),
+            controller,
+            movement: MovementConfig::default(),
+            health: Health::default(),
             velocity: vec3(0., 0., 0.),
-            new_position: vec3(0., 10., 0.),
-            is_on_ground: false
+            prev_position: spawn.position,
+            prev_eye_height,
+            is_on_ground: false,
+            is_crouched: false,
+            standing_half_height,
+            dead_for: None,
+            pushes: Vec::new(),
+            events: Vec::new(),
         }
     }
 
-    pub fn handle_contact(&mut self, oc: Contact, obj_tx: &Isometry3<f32>,  obj_shape: &dyn Shape) {
-        
-        let point1 = Vector3::from(oc.point1.coords);
-        let point2 = Vector3::from(oc.point2.coords);
+    pub fn is_dead(&self) -> bool {
+        self.dead_for.is_some()
+    }
 
-        let player_point = Point::new(self.new_position.x, self.new_position.y, self.new_position.z);
-        let surface_normal = vec3(oc.normal2.x, oc.normal2.y, oc.normal2.z);
+    pub fn state(&self) -> PlayerState {
+        PlayerState {
+            id: self.id,
+            position: self.position,
+            velocity: self.velocity,
+            theta: self.theta,
+            phi: self.phi,
+            on_ground: self.is_on_ground,
+            crouched: self.is_crouched,
+            health: self.health.health(),
+            armor: self.health.armor(),
+            dead_for: self.dead_for,
+        }
+    }
 
-        
+    /// Puts the player in `state`, without raising any events. The previous
+    /// position is kept for render interpolation.
+    pub fn set_state(&mut self, state: &PlayerState) {
+        self.prev_position = self.position;
+        self.prev_eye_height = self.eye_height();
+        self.id = state.id;
+        self.position = state.position;
+        self.velocity = state.velocity;
+        self.theta = state.theta;
+        self.phi = state.phi;
+        self.target = look_direction(state.theta, state.phi);
+        self.is_on_ground = state.on_ground;
+        if state.crouched != self.is_crouched {
+            let half_height = if state.crouched {
+                self.movement.crouch_half_height
+            } else {
+                self.standing_half_height
+            };
+            self.controller.set_half_height(half_height);
+            self.is_crouched = state.crouched;
+        }
+        self.health.set(state.health, state.armor);
+        self.dead_for = state.dead_for;
+    }
 
-        // floor collisions
-        // TODO: smooth movement walking on slopes
-        if surface_normal.dot(Vec3::Y) > 0.5 {
-            self.new_position.y += surface_normal.y * -oc.dist;
-            //self.velocity.y = self.velocity.y.clamp(0., f32::MAX);
-            self.normal = surface_normal;
-            self.is_on_ground = true;
+    /// Events since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<PlayerEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Takes `damage`, dying if it runs out of health. Returns the health
+    /// lost.
+    pub fn take_damage(&mut self, damage: Damage) -> f32 {
+        if self.is_dead() {
+            return 0.;
+        }
+        let lost = self.health.apply(&damage);
+        self.events.push(PlayerEvent::Damaged(damage));
+        if self.health.is_dead() {
+            self.dead_for = Some(0.);
+            self.velocity = Vec3::ZERO;
+            self.events.push(PlayerEvent::Died(damage));
         }
-        else {
-            if oc.dist < 0. {
+        lost
+    }
 
-                println!("normal: {} {} {}", oc.normal2.x, oc.normal2.y, oc.normal2.z);
-                let wall_normal = vec3(oc.normal2.x, 0.,  oc.normal2.z).normalize();
-                let target_xz = vec3(self.target.x, 0., self.target.z).normalize();
-                println!("dot: {}", wall_normal.dot(target_xz));
-                self.new_position += wall_normal * wall_normal.dot(-target_xz) * MOVE_SPEED;
-            }
+    /// Puts the player back at `spawn` with full health.
+    pub fn respawn(&mut self, spawn: &Spawn) {
+        self.position = spawn.position;
+        self.prev_position = spawn.position;
+        self.theta = spawn.theta;
+        self.phi = spawn.phi;
+        self.target = look_direction(spawn.theta, spawn.phi);
+        self.velocity = Vec3::ZERO;
+        self.controller.set_half_height(self.standing_half_height);
+        self.is_crouched = false;
+        self.prev_eye_height = self.eye_height();
+        self.is_on_ground = false;
+        self.health.reset();
+        self.dead_for = None;
+        self.events.push(PlayerEvent::Respawned);
+    }
+
+    pub fn is_on_ground(&self) -> bool {
+        self.is_on_ground
+    }
+
+    pub fn is_crouched(&self) -> bool {
+        self.is_crouched
+    }
+
+    pub fn velocity(&self) -> Vec3 {
+        self.velocity
+    }
+
+    /// Middle of the capsule as of the last tick.
+    pub fn center(&self) -> Vec3 {
+        self.position + vec3(0., self.controller.height() / 2., 0.)
+    }
+
+    /// The player's capsule as shots see it.
+    pub fn hitbox(&self) -> Hitbox {
+        Hitbox {
+            id: self.id,
+            position: self.position,
+            radius: self.controller.config.radius,
+            half_height: self.controller.config.half_height,
         }
+    }
+
+    /// Distance along a ray from `origin` in the normalized `direction` to
+    /// where it enters the player's capsule, if it does within
+    /// `max_distance`.
+    pub fn cast_ray(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<f32> {
+        self.hitbox().cast_ray(origin, direction, max_distance)
+    }
 
-        // don't let player's feet clip through surfaces
-        if obj_shape.contains_point(obj_tx, &player_point) {
-            self.new_position.y = point2.y;
-            self.velocity.y = self.velocity.y.clamp(0., f32::MAX);
-            self.is_on_ground = true;
+    /// Shoves the rigid bodies the player walked into during the last tick,
+    /// giving them up to the player's speed depending on how heavy they are.
+    ///
+    /// This only works one way: bodies don't collide with players, so one
+    /// driven into a player overlaps them until their next move, when the
+    /// controller pushes them back out of it.
+    pub fn push_bodies(&self, state: &mut State) {
+        for &(index, point, velocity) in self.pushes.iter() {
+            let Some(body) = state.objects()[index].rigid_body() else {
+                continue;
+            };
+            let speed = velocity.length();
+            let direction = velocity / speed;
+            let missing = speed - body.velocity_at(point).dot(direction);
+            if missing <= 0. {
+                continue;
+            }
+            let mass = body.config.mass * PLAYER_MASS / (body.config.mass + PLAYER_MASS);
+            state.apply_impulse(index, point, direction * missing * mass);
         }
+    }
 
+    /// Pushes the player by `explosion`, e.g. off the ground for a rocket
+    /// jump, and hurts them by it.
+    pub fn apply_explosion(&mut self, explosion: &Explosion) {
+        if self.is_dead() {
+            return;
+        }
+        let falloff = explosion.falloff(self.center());
+        if falloff <= 0. {
+            return;
+        }
+        let mut amount = explosion.damage * falloff;
+        if explosion.owner == Some(self.id) {
+            amount *= self.health.config.self_damage_scale;
+        }
+        self.take_damage(Damage {
+            amount,
+            kind: DamageKind::Explosion,
+            source: explosion.owner,
+        });
+        if self.is_dead() {
+            return;
+        }
+        let push = explosion.knockback_at(self.center());
+        self.velocity += push;
+        if push.y > 0. {
+            // otherwise the next tick would snap us back onto the ground
+            self.is_on_ground = false;
+        }
     }
 
-    pub fn update(&mut self, state: &mut State) {
-        let delta = mouse_delta_position();
+    // the eye sits at the top of the capsule
+    fn eye_height(&self) -> f32 {
+        self.controller.height()
+    }
 
-        self.theta += delta.x;
-        self.theta = self.theta.rem_euclid(PI * 2.);
-        self.phi += delta.y;
-        self.phi = self.phi.clamp(-PI/2. + 0.001, PI/2. - 0.001);
+    /// Eye position as of the last tick.
+    pub fn eye(&self) -> Vec3 {
+        self.position + vec3(0., self.eye_height(), 0.)
+    }
 
-        self.target = vec3(
-            self.phi.cos() * self.theta.cos(),
-            self.phi.sin(),
-            -self.phi.cos() * self.theta.sin(),
-        );
+    /// Draws the player's capsule, as others see them, interpolated `alpha`
+    /// of the way from the previous tick to the current one.
+    pub fn draw(&self, alpha: f32, color: Color) {
+        let radius = self.controller.config.radius;
+        let height = self.controller.height();
+        let feet = self.prev_position.lerp(self.position, alpha);
+        draw_sphere(feet + vec3(0., radius, 0.), radius, None, color);
+        draw_cylinder(feet + vec3(0., radius, 0.), radius, radius, height - 2. * radius, None, color);
+        draw_sphere(feet + vec3(0., height - radius, 0.), radius, None, color);
+    }
 
-        let forward = vec3(self.theta.cos(), 0., self.theta.sin());
-        let right = vec3((self.theta-PI/4.).cos(), 0., (self.theta-PI/4.).sin());
+    /// Eye camera, with the position interpolated `alpha` of the way from the
+    /// previous tick to the current one. The view angles are passed in rather
+    /// than taken from the last tick so mouse look is not delayed by a tick.
+    pub fn camera(&self, alpha: f32, theta: f32, phi: f32) -> Camera3D {
+        // recoil may push the view past straight up
+        let phi = phi.clamp(-PI/2. + 0.001, PI/2. - 0.001);
+        let forward = vec3(theta.cos(), 0., theta.sin());
+        let right = vec3((theta-PI/4.).cos(), 0., (theta-PI/4.).sin());
         let camera_up = -right.cross(forward);
         //let camera_up = self.up;
 
-        set_camera(&Camera3D {
-            position: self.position + vec3(0., 1., 0.),
+        let eye_height = self.prev_eye_height + (self.eye_height() - self.prev_eye_height) * alpha;
+        let eye = self.prev_position.lerp(self.position, alpha) + vec3(0., eye_height, 0.);
+        Camera3D {
+            position: eye,
             up: camera_up,
-            target: self.position + self.target + vec3(0., 1., 0.),
+            target: eye + look_direction(theta, phi),
             ..Default::default()
-        });
+        }
+    }
+
+    /// Advances the player by one fixed tick of `dt` seconds. The dead
+    /// ignore `cmd` and respawn at the level's spawn once their delay is up.
+    pub fn update(&mut self, state: &State, cmd: &InputCommand, dt: f32) {
+        self.prev_position = self.position;
+        self.prev_eye_height = self.eye_height();
+
+        if let Some(dead_for) = self.dead_for {
+            if dead_for + dt >= self.health.config.respawn_delay {
+                self.respawn(&state.spawn);
+            } else {
+                self.dead_for = Some(dead_for + dt);
+            }
+            return;
+        }
+
+        self.theta = cmd.theta.rem_euclid(PI * 2.);
+        self.phi = cmd.phi.clamp(-PI/2. + 0.001, PI/2. - 0.001);
+        self.target = look_direction(self.theta, self.phi);
 
-        self.velocity.x = 0.;
-        self.velocity.z = 0.;
+        self.update_crouch(state, cmd.crouch);
 
-        if !self.is_on_ground {
-            self.velocity.y -= GRAVITY;
-            self.normal = vec3(0., 1., 0.);
+        let forward = vec3(self.theta.cos(), 0., -self.theta.sin());
+        let right = vec3(self.theta.sin(), 0., self.theta.cos());
+        let mut wish = Vec3::ZERO;
+        if cmd.forward { wish += forward; }
+        if cmd.back { wish -= forward; }
+        if cmd.right { wish += right; }
+        if cmd.left { wish -= right; }
+        // normalized so diagonals are no faster than straight lines
+        let wish_dir = wish.normalize_or_zero();
+        let wish_speed = if self.is_crouched {
+            self.movement.crouch_speed
+        } else if cmd.sprint {
+            self.movement.sprint_speed
         } else {
+            self.movement.walk_speed
+        };
+
+        // jumping before friction means a jump on the tick of landing keeps
+        // all speed, which is what bunny hopping relies on
+        if cmd.jump && self.is_on_ground {
+            self.velocity.y = self.movement.jump_speed;
+            self.is_on_ground = false;
         }
 
-        if is_key_down(KeyCode::W) {
-            //self.velocity = vec3(self.theta.cos(), self.velocity.y, -self.theta.sin()) * MOVE_SPEED;
-            self.velocity.x = self.theta.cos() * MOVE_SPEED;
-            self.velocity.z = -self.theta.sin() * MOVE_SPEED;
+        if self.is_on_ground {
+            self.apply_friction(dt);
+            self.accelerate(wish_dir, wish_speed, self.movement.ground_accel, dt);
+            // the controller keeps us on the ground, gravity would only slide us down slopes
+            self.velocity.y = 0.;
+        } else {
+            let air_speed = wish_speed.min(self.movement.air_speed_cap);
+            self.accelerate(wish_dir, air_speed, self.movement.air_accel, dt);
+            self.velocity.y -= self.movement.gravity * dt;
         }
-        if is_key_down(KeyCode::S) {
-            //self.velocity = -vec3(self.theta.cos(), -self.velocity.y, -self.theta.sin()) * MOVE_SPEED;
-            self.velocity.x = -self.theta.cos() * MOVE_SPEED;
-            self.velocity.z = self.theta.sin() * MOVE_SPEED;
+
+        let was_on_ground = self.is_on_ground;
+        let fall_speed = -self.velocity.y;
+        let move_velocity = self.velocity;
+        let result = self.controller.move_character(
+            state,
+            self.position,
+            self.velocity * dt,
+            self.is_on_ground,
+        );
+        if result.hit_wall {
+            // only keep the part of the velocity that went along the wall, so
+            // running into one doesn't bank speed for when it ends
+            let moved = (result.position - self.position) / dt;
+            self.velocity.x = moved.x;
+            self.velocity.z = moved.z;
         }
-        if self.is_on_ground {
-            self.velocity.y =
-                self.velocity.x * (-self.normal.x / self.normal.y) +
-                self.velocity.z * (-self.normal.z / self.normal.y);
-        }
-        if is_key_pressed(KeyCode::Space) {
-           if self.is_on_ground {
-               self.velocity.y = 0.15;
-           }
-       }
-
-        self.is_on_ground = false; //this gets checked in collision handler
-
-        self.new_position = self.position + self.velocity;
-
-        let player_shape = Capsule::new_y(0.25, 0.25);
-        let player_tx = Isometry3::translation(self.new_position.x, self.new_position.y + 0.5, self.new_position.z);
-        for obj in state.objects.iter_mut() {
-            let obj_pos = obj.get_position();
-            let obj_tx = Isometry3::translation(obj_pos.x, obj_pos.y, obj_pos.z);
-            let obj_shape = obj.get_collider();
-            
-            let obj_contact = contact(&player_tx, &player_shape, &obj_tx, &*obj_shape, 0.).unwrap();
-            if obj_contact.is_some() {
-                self.handle_contact(obj_contact.unwrap(), &obj_tx, &*obj_shape);
+        self.position = result.position;
+        self.is_on_ground = result.grounded && self.velocity.y <= 0.;
+        if self.is_on_ground || (result.hit_ceiling && self.velocity.y > 0.) {
+            self.velocity.y = 0.;
+        }
+
+        self.pushes.clear();
+        let margin = 2. * self.controller.config.skin + PUSH_MARGIN;
+        for (index, point, normal) in self.controller.touching(state, self.position, margin) {
+            // standing on a body doesn't push it
+            if state.objects()[index].rigid_body().is_none() || self.controller.is_walkable(normal) {
+                continue;
+            }
+            let speed = -move_velocity.dot(normal);
+            if speed > 0. {
+                self.pushes.push((index, point, -normal * speed));
             }
         }
 
-        // smooth out oscillations from floating point errors
-        if self.position.distance(self.new_position) > 0.005 {
+        let safe_speed = self.health.config.safe_fall_speed;
+        if self.is_on_ground && !was_on_ground && fall_speed > safe_speed {
+            self.take_damage(Damage {
+                amount: (fall_speed - safe_speed) * self.health.config.fall_damage,
+                kind: DamageKind::Fall,
+                source: None,
+            });
+        }
+        if self.position.y < state.kill_height {
+            self.take_damage(Damage {
+                amount: 0.,
+                kind: DamageKind::KillPlane,
+                source: None,
+            });
+        }
+    }
+
+    // crouching is instant; standing up waits until there is room overhead
+    fn update_crouch(&mut self, state: &State, crouch: bool) {
+        if crouch && !self.is_crouched {
+            self.controller.set_half_height(self.movement.crouch_half_height);
+            self.is_crouched = true;
+        } else if !crouch
+            && self.is_crouched
+            && self.controller.fits(state, self.position, self.standing_half_height)
+        {
+            self.controller.set_half_height(self.standing_half_height);
+            self.is_crouched = false;
+        }
+    }
+
+    fn apply_friction(&mut self, dt: f32) {
+        let speed = vec2(self.velocity.x, self.velocity.z).length();
+        if speed < 1e-4 {
+            self.velocity.x = 0.;
+            self.velocity.z = 0.;
+            return;
+        }
+        let control = speed.max(self.movement.stop_speed);
+        let new_speed = (speed - control * self.movement.friction * dt).max(0.);
+        self.velocity.x *= new_speed / speed;
+        self.velocity.z *= new_speed / speed;
+    }
 
-            self.position = self.new_position;
+    // adds speed along `wish_dir` until the velocity's component in that
+    // direction reaches `wish_speed`. speed in other directions is left alone
+    fn accelerate(&mut self, wish_dir: Vec3, wish_speed: f32, accel: f32, dt: f32) {
+        let current = self.velocity.dot(wish_dir);
+        let add = wish_speed - current;
+        if add <= 0. {
+            return;
         }
+        self.velocity += wish_dir * (accel * wish_speed * dt).min(add);
     }
 }
@@ -0,0 +1,96 @@
+use macroquad::prelude::*;
+
+use crate::input::InputCommand;
+use crate::protocol::TimedCommand;
+
+/// Ticks of input kept for replaying, a little over two seconds at the
+/// default tick rate. Round trips longer than this can't be reconciled fully.
+pub const INPUT_BUFFER_SIZE: usize = 128;
+
+/// A command the client ran ahead of the server, with where it left the
+/// predicted player.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PendingInput {
+    pub tick: u32,
+    pub command: InputCommand,
+    /// Server tick the client was showing when the command was given.
+    pub view_tick: u32,
+    /// None if there was no player to predict yet.
+    pub predicted: Option<Vec3>,
+}
+
+/// Ring buffer of the client's most recent commands, by client tick. Pushing
+/// a new tick overwrites the one `capacity` ticks before it.
+pub struct InputBuffer {
+    slots: Vec<Option<PendingInput>>,
+    latest: Option<u32>,
+}
+
+impl InputBuffer {
+    pub fn new(capacity: usize) -> InputBuffer {
+        InputBuffer {
+            slots: vec![None; capacity.max(1)],
+            latest: None,
+        }
+    }
+
+    fn slot(&self, tick: u32) -> usize {
+        tick as usize % self.slots.len()
+    }
+
+    /// Records the command for `tick`, which should follow the last one pushed.
+    pub fn push(&mut self, tick: u32, command: InputCommand, view_tick: u32, predicted: Option<Vec3>) {
+        let slot = self.slot(tick);
+        self.slots[slot] = Some(PendingInput {
+            tick,
+            command,
+            view_tick,
+            predicted,
+        });
+        self.latest = Some(tick);
+    }
+
+    /// The newest tick pushed.
+    pub fn latest(&self) -> Option<u32> {
+        self.latest
+    }
+
+    pub fn get(&self, tick: u32) -> Option<&PendingInput> {
+        self.slots[self.slot(tick)]
+            .as_ref()
+            .filter(|input| input.tick == tick)
+    }
+
+    pub fn get_mut(&mut self, tick: u32) -> Option<&mut PendingInput> {
+        let slot = self.slot(tick);
+        self.slots[slot]
+            .as_mut()
+            .filter(|input| input.tick == tick)
+    }
+
+    /// Inputs after `tick` up to the newest, oldest first: the ones the
+    /// server hadn't run when it reported `tick`. Ticks that have been
+    /// overwritten are skipped.
+    pub fn after(&self, tick: u32) -> impl Iterator<Item = PendingInput> + '_ {
+        let latest = self.latest.unwrap_or(tick);
+        let first = (tick + 1).max((latest + 1).saturating_sub(self.slots.len() as u32));
+        (first..=latest).filter_map(|tick| self.get(tick).copied())
+    }
+
+    /// The newest `count` commands, oldest first.
+    pub fn recent(&self, count: usize) -> Vec<TimedCommand> {
+        let Some(latest) = self.latest else {
+            return Vec::new();
+        };
+        let count = count.min(self.slots.len()) as u32;
+        let first = (latest + 1).saturating_sub(count);
+        (first..=latest)
+            .filter_map(|tick| {
+                self.get(tick).map(|input| TimedCommand {
+                    command: input.command,
+                    view_tick: input.view_tick,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,255 @@
+use macroquad::prelude::*;
+use nalgebra::{Isometry3, Vector3};
+use parry3d::bounding_volume::BoundingVolume;
+use parry3d::query::{ShapeCastOptions, cast_shapes};
+use parry3d::shape::{Ball, Shape};
+
+use crate::game::State;
+use crate::rewind::Hitbox;
+
+// contacts handled per tick, i.e. how many surfaces a grenade can bounce off
+// within one tick before it stops for the rest of it
+const MAX_BOUNCES: usize = 4;
+// gap kept between a projectile and whatever it touches
+const SKIN: f32 = 0.005;
+// bounces slower than this come to rest
+const REST_SPEED: f32 = 0.5;
+// projectiles without a fuse are removed after this long, so a rocket fired
+// into the sky doesn't fly forever
+const MAX_LIFETIME: f32 = 30.;
+
+/// What a projectile does when it touches something.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Impact {
+    Explode,
+    /// Bounces off, keeping `restitution` of its speed into the surface and
+    /// losing `friction` of its speed along it.
+    Bounce { restitution: f32, friction: f32 },
+}
+
+/// Tuning of a projectile. Distances are in world units, times in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProjectileConfig {
+    pub radius: f32,
+    /// Launch speed.
+    pub speed: f32,
+    /// Downward acceleration, zero for rockets.
+    pub gravity: f32,
+    /// Fraction of its velocity lost per second to the air.
+    pub drag: f32,
+    pub impact: Impact,
+    /// Explodes this long after launch if it hasn't already.
+    pub fuse: Option<f32>,
+    pub explosion_radius: f32,
+    /// Damage at the center of the explosion, falling off to none at its edge.
+    pub damage: f32,
+    /// Speed the explosion gives to a player at its center, falling off the
+    /// same way.
+    pub knockback: f32,
+    pub color: Color,
+}
+
+impl ProjectileConfig {
+    /// Flies straight and fast, exploding on contact.
+    pub fn rocket() -> ProjectileConfig {
+        ProjectileConfig {
+            radius: 0.1,
+            speed: 25.,
+            gravity: 0.,
+            drag: 0.,
+            impact: Impact::Explode,
+            fuse: None,
+            explosion_radius: 3.,
+            damage: 100.,
+            knockback: 16.,
+            color: ORANGE,
+        }
+    }
+
+    /// Lobbed, bouncing around until its fuse runs out.
+    pub fn grenade() -> ProjectileConfig {
+        ProjectileConfig {
+            radius: 0.08,
+            speed: 15.,
+            gravity: 20.,
+            drag: 0.3,
+            impact: Impact::Bounce {
+                restitution: 0.45,
+                friction: 0.3,
+            },
+            fuse: Some(2.),
+            explosion_radius: 3.5,
+            damage: 100.,
+            knockback: 12.,
+            color: DARKGREEN,
+        }
+    }
+}
+
+/// A projectile in flight, stepped by
+/// [`State::update_projectiles`](crate::game::State::update_projectiles).
+#[derive(Clone, Debug)]
+pub struct Projectile {
+    pub config: ProjectileConfig,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    /// Seconds since launch.
+    pub age: f32,
+    /// Id of the player who fired it.
+    pub owner: Option<u32>,
+}
+
+/// A projectile going off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Explosion {
+    pub position: Vec3,
+    pub radius: f32,
+    pub damage: f32,
+    pub knockback: f32,
+    pub owner: Option<u32>,
+}
+
+impl Projectile {
+    /// A projectile leaving `origin` at its configured speed along the
+    /// normalized `direction`.
+    pub fn new(config: ProjectileConfig, origin: Vec3, direction: Vec3) -> Projectile {
+        Projectile {
+            config,
+            position: origin,
+            velocity: direction * config.speed,
+            age: 0.,
+            owner: None,
+        }
+    }
+
+    fn explosion(&self) -> Explosion {
+        Explosion {
+            position: self.position,
+            radius: self.config.explosion_radius,
+            damage: self.config.damage,
+            knockback: self.config.knockback,
+            owner: self.owner,
+        }
+    }
+
+    /// Advances the projectile by `dt` seconds. Touching any of `players`
+    /// other than its owner sets it off, even a grenade that would bounce
+    /// off a wall. Returns whether it is done, and the explosion if it went
+    /// off.
+    pub(crate) fn step(&mut self, state: &State, players: &[Hitbox], dt: f32) -> (bool, Option<Explosion>) {
+        self.age += dt;
+        if let Some(fuse) = self.config.fuse {
+            if self.age >= fuse {
+                return (true, Some(self.explosion()));
+            }
+        } else if self.age >= MAX_LIFETIME {
+            return (true, None);
+        }
+
+        self.velocity.y -= self.config.gravity * dt;
+        self.velocity *= (-self.config.drag * dt).exp();
+
+        let ball = Ball::new(self.config.radius);
+        let mut remaining = dt;
+        for _ in 0..MAX_BOUNCES {
+            let translation = self.velocity * remaining;
+            if translation.length_squared() < 1e-10 {
+                break;
+            }
+            let wall = cast(state, &ball, self.position, translation);
+            let player = players
+                .iter()
+                .filter(|hitbox| Some(hitbox.id) != self.owner)
+                .filter_map(|hitbox| hitbox.cast_ball(self.position, self.config.radius, translation))
+                .reduce(f32::min);
+            if let Some(toi) = player
+                && wall.is_none_or(|(wall, _)| toi < wall)
+            {
+                self.position += translation * toi;
+                return (true, Some(self.explosion()));
+            }
+            let Some((toi, normal)) = wall else {
+                self.position += translation;
+                break;
+            };
+            self.position += translation * toi;
+            match self.config.impact {
+                Impact::Explode => return (true, Some(self.explosion())),
+                Impact::Bounce {
+                    restitution,
+                    friction,
+                } => {
+                    let into = self.velocity.dot(normal);
+                    let along = self.velocity - normal * into;
+                    self.velocity = along * (1. - friction) - normal * into * restitution;
+                    if self.velocity.length() < REST_SPEED {
+                        self.velocity = Vec3::ZERO;
+                    }
+                }
+            }
+            remaining *= 1. - toi;
+        }
+        (false, None)
+    }
+
+    /// Draws the projectile as a small sphere, unlit.
+    pub fn draw(&self) {
+        draw_sphere(self.position, self.config.radius, None, self.config.color);
+    }
+}
+
+impl Explosion {
+    /// Fraction of the explosion's strength felt at `point`, from one at the
+    /// center to zero at the edge.
+    pub fn falloff(&self, point: Vec3) -> f32 {
+        (1. - self.position.distance(point) / self.radius).max(0.)
+    }
+
+    /// Velocity the explosion adds to something centered at `point`, pushing
+    /// it straight away from the center.
+    pub fn knockback_at(&self, point: Vec3) -> Vec3 {
+        let away = (point - self.position).normalize_or(Vec3::Y);
+        away * self.knockback * self.falloff(point)
+    }
+}
+
+// sweeps `ball` from `position` by `translation`, returning the fraction of
+// the way it got and the normal of what it hit
+fn cast(state: &State, ball: &Ball, position: Vec3, translation: Vec3) -> Option<(f32, Vec3)> {
+    let start = Isometry3::translation(position.x, position.y, position.z);
+    let end = position + translation;
+    let sweep = ball
+        .compute_aabb(&start)
+        .merged(&ball.compute_aabb(&Isometry3::translation(end.x, end.y, end.z)))
+        .loosened(2. * SKIN);
+    let velocity = Vector3::new(translation.x, translation.y, translation.z);
+    let options = ShapeCastOptions {
+        max_time_of_impact: 1.,
+        target_distance: SKIN,
+        stop_at_penetration: false,
+        compute_impact_geometry_on_penetration: true,
+    };
+
+    let mut best: Option<(f32, Vec3)> = None;
+    for (_, obj) in state.objects_near(&sweep) {
+        let Ok(Some(hit)) = cast_shapes(
+            &start,
+            &velocity,
+            ball,
+            &obj.get_transform().isometry(),
+            &Vector3::zeros(),
+            obj.get_collider(),
+            options,
+        ) else {
+            continue;
+        };
+        let normal = -vec3(hit.normal1.x, hit.normal1.y, hit.normal1.z);
+        if normal.dot(translation) > -1e-6 {
+            continue;
+        }
+        if best.is_none_or(|(toi, _)| hit.time_of_impact < toi) {
+            best = Some((hit.time_of_impact.clamp(0., 1.), normal));
+        }
+    }
+    best
+}
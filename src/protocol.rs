@@ -0,0 +1,417 @@
+use std::fmt;
+
+use macroquad::prelude::*;
+
+use crate::input::InputCommand;
+
+/// First bytes of every packet, so stray datagrams are told apart from ours.
+pub const PROTOCOL_ID: u32 = 0x4650_5331;
+/// Largest packet sent, kept under common MTUs so packets aren't fragmented.
+pub const MAX_PACKET_SIZE: usize = 1200;
+/// Chat messages and player names are cut to this many bytes when sent, and
+/// longer ones are rejected when received.
+pub const MAX_CHAT_LENGTH: usize = 200;
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// The data ended in the middle of a value.
+    UnexpectedEnd,
+    /// A packet from something that doesn't speak this protocol.
+    WrongProtocol,
+    /// An enum tag that doesn't name any variant.
+    UnknownTag(u8),
+    InvalidString,
+    /// A value that can't be right, like a length longer than it could be.
+    Malformed,
+    /// A delta against a snapshot, by tick, that is no longer kept.
+    MissingBaseline(u32),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            DecodeError::WrongProtocol => write!(f, "not a packet of this protocol"),
+            DecodeError::UnknownTag(tag) => write!(f, "unknown tag {}", tag),
+            DecodeError::InvalidString => write!(f, "string is not valid utf-8"),
+            DecodeError::Malformed => write!(f, "malformed data"),
+            DecodeError::MissingBaseline(tick) => write!(f, "no snapshot of tick {} to apply a delta to", tick),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Appends values to a byte buffer, little endian.
+#[derive(Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn vec3(&mut self, value: Vec3) {
+        self.f32(value.x);
+        self.f32(value.y);
+        self.f32(value.z);
+    }
+
+    pub fn quat(&mut self, value: Quat) {
+        self.f32(value.x);
+        self.f32(value.y);
+        self.f32(value.z);
+        self.f32(value.w);
+    }
+
+    /// Length prefixed, at most 65535 bytes.
+    pub fn bytes(&mut self, value: &[u8]) {
+        let len = value.len().min(u16::MAX as usize);
+        self.u16(len as u16);
+        self.bytes.extend(&value[..len]);
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    /// Appends raw bytes without a length, e.g. a payload running to the
+    /// end of the packet.
+    pub fn raw(&mut self, value: &[u8]) {
+        self.bytes.extend(value);
+    }
+}
+
+/// Reads back what a [`Writer`] wrote.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, position: 0 }
+    }
+
+    /// Everything not read yet.
+    pub fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.position..]
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.position + count;
+        let taken = self.bytes.get(self.position..end).ok_or(DecodeError::UnexpectedEnd)?;
+        self.position = end;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, DecodeError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, DecodeError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn vec3(&mut self) -> Result<Vec3, DecodeError> {
+        Ok(vec3(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    pub fn quat(&mut self) -> Result<Quat, DecodeError> {
+        Ok(Quat::from_xyzw(self.f32()?, self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    pub fn string(&mut self) -> Result<String, DecodeError> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidString)
+    }
+}
+
+/// Sent reliably from a client to the server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    Join { name: String },
+    Chat(String),
+    Leave,
+}
+
+/// Sent reliably from the server to a client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMessage {
+    /// The join was accepted; the client plays as `player_id` in `level`.
+    Welcome { player_id: u32, tick_rate: u32, level: String },
+    /// The join was turned down, e.g. because the server is full.
+    Refused(String),
+    PlayerJoined { id: u32, name: String },
+    PlayerLeft { id: u32 },
+    Chat { from: u32, text: String },
+    /// The server moved on to `level`; everyone starts over there.
+    ChangeLevel { level: String },
+    /// The server is going away, for the given reason.
+    Shutdown(String),
+}
+
+impl ClientMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        match self {
+            ClientMessage::Join { name } => {
+                w.u8(0);
+                w.str(truncate(name, MAX_CHAT_LENGTH));
+            }
+            ClientMessage::Chat(text) => {
+                w.u8(1);
+                w.str(truncate(text, MAX_CHAT_LENGTH));
+            }
+            ClientMessage::Leave => w.u8(2),
+        }
+        w.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<ClientMessage, DecodeError> {
+        let mut r = Reader::new(bytes);
+        Ok(match r.u8()? {
+            0 => ClientMessage::Join {
+                name: chat_string(&mut r)?,
+            },
+            1 => ClientMessage::Chat(chat_string(&mut r)?),
+            2 => ClientMessage::Leave,
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        })
+    }
+}
+
+impl ServerMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        match self {
+            ServerMessage::Welcome {
+                player_id,
+                tick_rate,
+                level,
+            } => {
+                w.u8(0);
+                w.u32(*player_id);
+                w.u32(*tick_rate);
+                w.str(level);
+            }
+            ServerMessage::Refused(reason) => {
+                w.u8(1);
+                w.str(reason);
+            }
+            ServerMessage::PlayerJoined { id, name } => {
+                w.u8(2);
+                w.u32(*id);
+                w.str(name);
+            }
+            ServerMessage::PlayerLeft { id } => {
+                w.u8(3);
+                w.u32(*id);
+            }
+            ServerMessage::Chat { from, text } => {
+                w.u8(4);
+                w.u32(*from);
+                w.str(text);
+            }
+            ServerMessage::ChangeLevel { level } => {
+                w.u8(5);
+                w.str(level);
+            }
+            ServerMessage::Shutdown(reason) => {
+                w.u8(6);
+                w.str(reason);
+            }
+        }
+        w.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<ServerMessage, DecodeError> {
+        let mut r = Reader::new(bytes);
+        Ok(match r.u8()? {
+            0 => ServerMessage::Welcome {
+                player_id: r.u32()?,
+                tick_rate: r.u32()?,
+                level: r.string()?,
+            },
+            1 => ServerMessage::Refused(r.string()?),
+            2 => ServerMessage::PlayerJoined {
+                id: r.u32()?,
+                name: r.string()?,
+            },
+            3 => ServerMessage::PlayerLeft { id: r.u32()? },
+            4 => ServerMessage::Chat {
+                from: r.u32()?,
+                text: r.string()?,
+            },
+            5 => ServerMessage::ChangeLevel { level: r.string()? },
+            6 => ServerMessage::Shutdown(r.string()?),
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        })
+    }
+}
+
+/// A command along with the server tick of the snapshot the client was
+/// showing when it was given, so shots can be judged against what the
+/// player saw.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimedCommand {
+    pub command: InputCommand,
+    pub view_tick: u32,
+}
+
+/// The client's latest commands, sent unreliably every tick. Each packet
+/// repeats the last few commands so one lost packet loses no input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputPacket {
+    /// Client tick of the last command; the ones before it are for the
+    /// ticks before that.
+    pub tick: u32,
+    /// Oldest first.
+    pub commands: Vec<TimedCommand>,
+}
+
+impl InputPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u32(self.tick);
+        w.u8(self.commands.len().min(u8::MAX as usize) as u8);
+        for timed in self.commands.iter().take(u8::MAX as usize) {
+            write_command(&mut w, &timed.command);
+            w.u32(timed.view_tick);
+        }
+        w.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<InputPacket, DecodeError> {
+        let mut r = Reader::new(bytes);
+        let tick = r.u32()?;
+        let count = r.u8()?;
+        let commands = (0..count)
+            .map(|_| {
+                Ok(TimedCommand {
+                    command: read_command(&mut r)?,
+                    view_tick: r.u32()?,
+                })
+            })
+            .collect::<Result<_, DecodeError>>()?;
+        Ok(InputPacket { tick, commands })
+    }
+}
+
+fn write_command(w: &mut Writer, cmd: &InputCommand) {
+    w.f32(cmd.theta);
+    w.f32(cmd.phi);
+    let flags = [
+        cmd.forward,
+        cmd.back,
+        cmd.left,
+        cmd.right,
+        cmd.jump,
+        cmd.sprint,
+        cmd.crouch,
+        cmd.fire,
+        cmd.reload,
+        cmd.next_weapon,
+        cmd.interact,
+    ];
+    let bits = flags
+        .iter()
+        .enumerate()
+        .fold(0u16, |bits, (i, set)| bits | ((*set as u16) << i));
+    w.u16(bits);
+}
+
+fn read_command(r: &mut Reader) -> Result<InputCommand, DecodeError> {
+    let theta = r.f32()?;
+    let phi = r.f32()?;
+    let bits = r.u16()?;
+    let bit = |i: u16| bits & (1 << i) != 0;
+    Ok(InputCommand {
+        theta,
+        phi,
+        forward: bit(0),
+        back: bit(1),
+        left: bit(2),
+        right: bit(3),
+        jump: bit(4),
+        sprint: bit(5),
+        crouch: bit(6),
+        fire: bit(7),
+        reload: bit(8),
+        next_weapon: bit(9),
+        interact: bit(10),
+    })
+}
+
+// cut at a character boundary
+// text from a client, which is relayed to everyone and so has to stay short
+// enough to fit in a packet
+fn chat_string(r: &mut Reader) -> Result<String, DecodeError> {
+    let text = r.string()?;
+    if text.len() > MAX_CHAT_LENGTH {
+        return Err(DecodeError::Malformed);
+    }
+    Ok(text)
+}
+
+fn truncate(text: &str, max_len: usize) -> &str {
+    if text.len() <= max_len {
+        return text;
+    }
+    let mut end = max_len;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
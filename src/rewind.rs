@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+
+use macroquad::prelude::*;
+use nalgebra::{Isometry3, Point3, Vector3};
+use parry3d::query::{Ray, RayCast, ShapeCastOptions, cast_shapes};
+use parry3d::shape::{Ball, Capsule};
+
+/// Longest the server looks back when judging a shot, in seconds, unless
+/// configured otherwise.
+pub const DEFAULT_MAX_REWIND: f32 = 0.3;
+
+/// A player's capsule at some tick, as far as shots are concerned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hitbox {
+    pub id: u32,
+    /// Feet, like [`crate::player::Player::position`].
+    pub position: Vec3,
+    pub radius: f32,
+    /// Half the length of the capsule's straight part.
+    pub half_height: f32,
+}
+
+impl Hitbox {
+    /// Distance along a ray from `origin` in the normalized `direction` to
+    /// where it enters the capsule, if it does within `max_distance`.
+    pub fn cast_ray(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<f32> {
+        let ray = Ray::new(
+            Point3::from(origin.to_array()),
+            Vector3::from(direction.to_array()),
+        );
+        Capsule::new_y(self.half_height, self.radius).cast_ray(&self.isometry(), &ray, max_distance, true)
+    }
+
+    /// Fraction of `translation` a ball of `radius` at `position` gets
+    /// through before touching the capsule, if it does.
+    pub fn cast_ball(&self, position: Vec3, radius: f32, translation: Vec3) -> Option<f32> {
+        let options = ShapeCastOptions {
+            max_time_of_impact: 1.,
+            ..Default::default()
+        };
+        let hit = cast_shapes(
+            &Isometry3::translation(position.x, position.y, position.z),
+            &Vector3::from(translation.to_array()),
+            &Ball::new(radius),
+            &self.isometry(),
+            &Vector3::zeros(),
+            &Capsule::new_y(self.half_height, self.radius),
+            options,
+        )
+        .ok()??;
+        Some(hit.time_of_impact)
+    }
+
+    fn isometry(&self) -> Isometry3<f32> {
+        let center = self.position + vec3(0., self.half_height + self.radius, 0.);
+        Isometry3::translation(center.x, center.y, center.z)
+    }
+}
+
+/// Where every living player's hitbox was over the last few ticks, so the
+/// server can judge a shot against the world the shooter was looking at
+/// rather than the one it has moved on to.
+pub struct HitboxHistory {
+    // oldest first, one per tick
+    ticks: VecDeque<(u32, Vec<Hitbox>)>,
+    max_rewind: u32,
+}
+
+impl HitboxHistory {
+    /// Keeps `max_rewind` ticks back from the newest. Zero turns rewinding off.
+    pub fn new(max_rewind: u32) -> HitboxHistory {
+        HitboxHistory {
+            ticks: VecDeque::new(),
+            max_rewind,
+        }
+    }
+
+    /// How many ticks back shots may be judged.
+    pub fn max_rewind(&self) -> u32 {
+        self.max_rewind
+    }
+
+    /// Records the hitboxes as of `tick`, forgetting ticks too old to rewind to.
+    pub fn record(&mut self, tick: u32, hitboxes: Vec<Hitbox>) {
+        self.ticks.push_back((tick, hitboxes));
+        let oldest = tick.saturating_sub(self.max_rewind);
+        while self.ticks.front().is_some_and(|(kept, _)| *kept < oldest) {
+            self.ticks.pop_front();
+        }
+    }
+
+    /// Hitboxes as they were at `tick`. A tick further back than is kept
+    /// gets the oldest one kept instead. None if nothing has been recorded.
+    pub fn at(&self, tick: u32) -> Option<&[Hitbox]> {
+        let (_, hitboxes) = self
+            .ticks
+            .iter()
+            .rev()
+            .find(|(kept, _)| *kept <= tick)
+            .or(self.ticks.front())?;
+        Some(hitboxes)
+    }
+}
@@ -0,0 +1,486 @@
+use macroquad::prelude::*;
+use nalgebra::Vector3;
+use parry3d::bounding_volume::BoundingVolume;
+use parry3d::query::{ContactManifold, DefaultQueryDispatcher, PersistentQueryDispatcher};
+use parry3d::shape::{Cuboid, Cylinder, Shape, SharedShape};
+
+use crate::batch::BatchBuilder;
+use crate::game::State;
+use crate::materials::{self, MaterialHandle, Materials};
+use crate::objects::{
+    DEFAULT_COLOR, PhysicsObject, Transform, box_mesh, cylinder_mesh, set_mesh_color,
+    with_transform,
+};
+
+/// Downward acceleration of bodies, in units per second squared.
+pub const GRAVITY: f32 = 20.;
+/// Mass players count as when pushing bodies or being pushed by the same
+/// explosions, in the same units as [`BodyConfig::mass`].
+pub const PLAYER_MASS: f32 = 80.;
+
+// velocity passes over each tick's contacts
+const ITERATIONS: usize = 10;
+// contacts are picked up this far before touching, so resting bodies keep
+// theirs from tick to tick
+const CONTACT_MARGIN: f32 = 0.02;
+// overlap left alone, so resting contacts don't jitter in and out
+const SLOP: f32 = 0.005;
+// fraction of the overlap pushed out per tick
+const BAUMGARTE: f32 = 0.2;
+// impacts slower than this don't bounce, so bodies can come to rest
+const BOUNCE_SPEED: f32 = 1.;
+// bodies slower than these for SLEEP_TIME seconds stop being simulated
+const SLEEP_SPEED: f32 = 0.08;
+const SLEEP_ANGULAR_SPEED: f32 = 0.1;
+const SLEEP_TIME: f32 = 0.5;
+// a sleeping body hit faster than this wakes up, as does one touching a
+// body that isn't still
+const WAKE_SPEED: f32 = 0.3;
+const CYLINDER_SEGMENTS: u16 = 16;
+
+/// Collision shape of a rigid body, centered on its position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BodyShape {
+    Box(Vec3),
+    /// Upright cylinder, e.g. a barrel.
+    Cylinder { radius: f32, half_height: f32 },
+}
+
+/// Physical properties of a rigid body.
+#[derive(Clone, Debug)]
+pub struct BodyConfig {
+    pub mass: f32,
+    /// Coulomb friction coefficient against whatever the body touches.
+    pub friction: f32,
+    /// Fraction of the impact speed kept when bouncing, from 0 to 1.
+    pub restitution: f32,
+    /// Fraction of the velocity lost per second.
+    pub linear_damping: f32,
+    pub angular_damping: f32,
+}
+
+impl Default for BodyConfig {
+    /// A wooden crate.
+    fn default() -> BodyConfig {
+        BodyConfig {
+            mass: 20.,
+            friction: 0.6,
+            restitution: 0.2,
+            linear_damping: 0.05,
+            angular_damping: 0.1,
+        }
+    }
+}
+
+/// A box or cylinder moved by the physics: it falls, tumbles, slides and
+/// gets pushed by players, shots and explosions. Bodies that come to rest
+/// fall asleep and cost nothing until something touches them.
+pub struct RigidBody {
+    transform: Transform,
+    mesh: Mesh,
+    collider: SharedShape,
+    pub material: MaterialHandle,
+    pub config: BodyConfig,
+    pub velocity: Vec3,
+    /// Radians per second about each world axis.
+    pub angular_velocity: Vec3,
+    // inverse of the inertia tensor's diagonal, in body space
+    inverse_inertia: Vec3,
+    still_for: f32,
+    asleep: bool,
+}
+
+impl RigidBody {
+    pub fn new(transform: Transform, shape: BodyShape, config: BodyConfig) -> RigidBody {
+        let mass = config.mass;
+        let (mesh, collider, inertia) = match shape {
+            BodyShape::Box(half_extents) => {
+                let size = 2. * half_extents;
+                let squared = size * size;
+                (
+                    box_mesh(half_extents, DEFAULT_COLOR),
+                    SharedShape::new(Cuboid::new(Vector3::new(
+                        half_extents.x,
+                        half_extents.y,
+                        half_extents.z,
+                    ))),
+                    vec3(
+                        squared.y + squared.z,
+                        squared.x + squared.z,
+                        squared.x + squared.y,
+                    ) * mass
+                        / 12.,
+                )
+            }
+            BodyShape::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let height = 2. * half_height;
+                let side = mass * (3. * radius * radius + height * height) / 12.;
+                (
+                    cylinder_mesh(radius, half_height, CYLINDER_SEGMENTS, DEFAULT_COLOR),
+                    SharedShape::new(Cylinder::new(half_height, radius)),
+                    vec3(side, mass * radius * radius / 2., side),
+                )
+            }
+        };
+        RigidBody {
+            transform,
+            mesh,
+            collider,
+            material: materials::WORLD,
+            config,
+            velocity: Vec3::ZERO,
+            angular_velocity: Vec3::ZERO,
+            inverse_inertia: inertia.recip(),
+            still_for: 0.,
+            asleep: false,
+        }
+    }
+
+    /// Base color, multiplied with the texture if there is one.
+    pub fn set_color(&mut self, color: Color) {
+        set_mesh_color(&mut self.mesh, color);
+    }
+
+    pub fn set_texture(&mut self, texture: Option<Texture2D>) {
+        self.mesh.texture = texture;
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
+
+    pub fn wake(&mut self) {
+        self.asleep = false;
+        self.still_for = 0.;
+    }
+
+    /// Moves the body outright, e.g. to where the server says it is. Call
+    /// [`State::object_moved`] afterwards.
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    /// Velocity of the point of the body at world position `point`.
+    pub fn velocity_at(&self, point: Vec3) -> Vec3 {
+        self.velocity + self.angular_velocity.cross(point - self.transform.position)
+    }
+
+    /// Changes the body's momentum by `impulse`, applied at world position
+    /// `point`, waking it up.
+    pub fn apply_impulse(&mut self, point: Vec3, impulse: Vec3) {
+        self.wake();
+        self.velocity += impulse / self.config.mass;
+        let torque = (point - self.transform.position).cross(impulse);
+        self.angular_velocity += self.inverse_inertia_world() * torque;
+    }
+
+    // inverse inertia tensor in world space
+    fn inverse_inertia_world(&self) -> Mat3 {
+        let rotation = Mat3::from_quat(self.transform.rotation);
+        rotation * Mat3::from_diagonal(self.inverse_inertia) * rotation.transpose()
+    }
+}
+
+impl PhysicsObject for RigidBody {
+    fn get_collider(&self) -> &dyn Shape {
+        self.collider.as_ref()
+    }
+    fn get_transform(&self) -> Transform {
+        self.transform
+    }
+    fn draw(&self, materials: &Materials) {
+        materials.apply(self.material);
+        self.draw_shape();
+    }
+    fn draw_shape(&self) {
+        with_transform(&self.transform, || draw_mesh(&self.mesh));
+    }
+    fn batch(&self, _batch: &mut BatchBuilder) -> bool {
+        false
+    }
+    fn is_static(&self) -> bool {
+        false
+    }
+    fn rigid_body(&self) -> Option<&RigidBody> {
+        Some(self)
+    }
+    fn rigid_body_mut(&mut self) -> Option<&mut RigidBody> {
+        Some(self)
+    }
+}
+
+// a body's state while solving, copied out so two bodies can be changed at
+// once
+struct SolverBody {
+    object: usize,
+    position: Vec3,
+    velocity: Vec3,
+    angular_velocity: Vec3,
+    inverse_mass: f32,
+    inverse_inertia: Mat3,
+    friction: f32,
+    restitution: f32,
+}
+
+impl SolverBody {
+    fn velocity_at(&self, point: Vec3) -> Vec3 {
+        self.velocity + self.angular_velocity.cross(point - self.position)
+    }
+
+    fn apply_impulse(&mut self, point: Vec3, impulse: Vec3) {
+        self.velocity += impulse * self.inverse_mass;
+        self.angular_velocity += self.inverse_inertia * (point - self.position).cross(impulse);
+    }
+
+    // how much an impulse along `direction` at `point` resists changing the
+    // velocity there
+    fn inverse_mass_along(&self, point: Vec3, direction: Vec3) -> f32 {
+        let r = point - self.position;
+        self.inverse_mass + direction.dot((self.inverse_inertia * r.cross(direction)).cross(r))
+    }
+}
+
+// a body touching something; `other` is None for anything that doesn't move
+struct Contact {
+    body: usize,
+    other: Option<usize>,
+    point: Vec3,
+    // pushes `body` away from the other
+    normal: Vec3,
+    // negative while overlapping
+    distance: f32,
+    friction: f32,
+    // normal velocity to reach, from bouncing or pushing out of an overlap
+    target: f32,
+    normal_impulse: f32,
+    friction_impulse: Vec3,
+}
+
+/// Advances every awake body in `state` by `dt` seconds.
+pub(crate) fn step(state: &mut State, dt: f32) {
+    let mut bodies: Vec<SolverBody> = Vec::new();
+    // index into `bodies` by object index, for bodies being simulated
+    let mut slots: Vec<Option<usize>> = vec![None; state.objects().len()];
+    for index in state.bodies().to_vec() {
+        let Some(body) = state.rigid_body_mut(index) else {
+            continue;
+        };
+        if body.asleep {
+            continue;
+        }
+        body.velocity.y -= GRAVITY * dt;
+        body.velocity *= (-body.config.linear_damping * dt).exp();
+        body.angular_velocity *= (-body.config.angular_damping * dt).exp();
+        slots[index] = Some(bodies.len());
+        bodies.push(SolverBody {
+            object: index,
+            position: body.transform.position,
+            velocity: body.velocity,
+            angular_velocity: body.angular_velocity,
+            inverse_mass: 1. / body.config.mass,
+            inverse_inertia: body.inverse_inertia_world(),
+            friction: body.config.friction,
+            restitution: body.config.restitution,
+        });
+    }
+    if bodies.is_empty() {
+        return;
+    }
+
+    let mut contacts = Vec::new();
+    let mut woken = Vec::new();
+    // sleeping bodies touching an awake one, by slot and object index
+    let mut sleepers = Vec::new();
+    for (slot, body) in bodies.iter().enumerate() {
+        let obj = &*state.objects()[body.object];
+        let aabb = obj.aabb().loosened(CONTACT_MARGIN);
+        for (index, other) in state.objects_near(&aabb) {
+            if index == body.object {
+                continue;
+            }
+            let other_slot = slots[index];
+            // pairs of awake bodies are handled once, from the first of them
+            if other_slot.is_some_and(|other_slot| other_slot < slot) {
+                continue;
+            }
+            let (friction, restitution) = match other.rigid_body() {
+                Some(other) => (
+                    (body.friction * other.config.friction).sqrt(),
+                    body.restitution.max(other.config.restitution),
+                ),
+                None => (body.friction, body.restitution),
+            };
+            let first = contacts.len();
+            find_contacts(obj, other, slot, other_slot, friction, &mut contacts);
+            for contact in contacts[first..].iter_mut() {
+                let relative = body.velocity_at(contact.point)
+                    - other_slot.map_or(Vec3::ZERO, |s| bodies[s].velocity_at(contact.point));
+                let approach = relative.dot(contact.normal);
+                let bounce = if approach < -BOUNCE_SPEED { -restitution * approach } else { 0. };
+                contact.target = if contact.distance > 0. {
+                    // not touching yet: only stop it from closing the gap
+                    // within this tick, bouncing if it would
+                    let reaches = contact.distance + approach * dt < 0.;
+                    (-contact.distance / dt).max(if reaches { bounce } else { f32::MIN })
+                } else {
+                    bounce.max(BAUMGARTE * (-contact.distance - SLOP).max(0.) / dt)
+                };
+                // sleeping bodies act as if fixed, unless hit hard enough
+                if other.rigid_body().is_some_and(|b| b.asleep) && -approach > WAKE_SPEED {
+                    woken.push(index);
+                }
+            }
+            if contacts.len() > first && other.rigid_body().is_some_and(|b| b.asleep) {
+                sleepers.push((slot, index));
+            }
+        }
+    }
+
+    for _ in 0..ITERATIONS {
+        for contact in contacts.iter_mut() {
+            solve(&mut bodies, contact);
+        }
+    }
+    // whatever rests on or against a body that moves off can't stay put
+    for &(slot, index) in sleepers.iter() {
+        let body = &bodies[slot];
+        if !is_still(body.velocity, body.angular_velocity) {
+            woken.push(index);
+        }
+    }
+
+    for body in bodies.iter() {
+        let index = body.object;
+        let Some(rigid_body) = state.rigid_body_mut(index) else {
+            continue;
+        };
+        rigid_body.velocity = body.velocity;
+        rigid_body.angular_velocity = body.angular_velocity;
+        rigid_body.transform.position += body.velocity * dt;
+        rigid_body.transform.rotation = (Quat::from_scaled_axis(body.angular_velocity * dt)
+            * rigid_body.transform.rotation)
+            .normalize();
+
+        if is_still(body.velocity, body.angular_velocity) {
+            rigid_body.still_for += dt;
+            if rigid_body.still_for >= SLEEP_TIME {
+                rigid_body.asleep = true;
+                rigid_body.velocity = Vec3::ZERO;
+                rigid_body.angular_velocity = Vec3::ZERO;
+            }
+        } else {
+            rigid_body.still_for = 0.;
+        }
+        state.object_moved(index);
+    }
+
+    for index in woken {
+        if let Some(body) = state.rigid_body_mut(index) {
+            body.wake();
+        }
+    }
+}
+
+// slow enough to fall asleep
+fn is_still(velocity: Vec3, angular_velocity: Vec3) -> bool {
+    velocity.length() < SLEEP_SPEED && angular_velocity.length() < SLEEP_ANGULAR_SPEED
+}
+
+// contact points between the body in `slot` and `other`, from parry's
+// contact manifolds
+fn find_contacts(
+    obj: &dyn PhysicsObject,
+    other: &dyn PhysicsObject,
+    slot: usize,
+    other_slot: Option<usize>,
+    friction: f32,
+    contacts: &mut Vec<Contact>,
+) {
+    let tx1 = obj.get_transform().isometry();
+    let tx2 = other.get_transform().isometry();
+    let pos12 = tx1.inv_mul(&tx2);
+    let mut manifolds: Vec<ContactManifold<(), ()>> = Vec::new();
+    if DefaultQueryDispatcher
+        .contact_manifolds(
+            &pos12,
+            obj.get_collider(),
+            other.get_collider(),
+            CONTACT_MARGIN,
+            &mut manifolds,
+            &mut None,
+        )
+        .is_err()
+    {
+        return;
+    }
+
+    for manifold in manifolds.iter() {
+        let sub1 = tx1 * manifold.subshape_pos1.unwrap_or_default();
+        let sub2 = tx2 * manifold.subshape_pos2.unwrap_or_default();
+        // the manifold's normal points out of the body, towards the other
+        let n = sub1 * manifold.local_n1;
+        let normal = -vec3(n.x, n.y, n.z);
+        for point in manifold.points.iter() {
+            if point.dist > CONTACT_MARGIN {
+                continue;
+            }
+            let p1 = sub1 * point.local_p1;
+            let p2 = sub2 * point.local_p2;
+            contacts.push(Contact {
+                body: slot,
+                other: other_slot,
+                point: vec3(p1.x + p2.x, p1.y + p2.y, p1.z + p2.z) / 2.,
+                normal,
+                distance: point.dist,
+                friction,
+                target: 0.,
+                normal_impulse: 0.,
+                friction_impulse: Vec3::ZERO,
+            });
+        }
+    }
+}
+
+// one pass of sequential impulses over a contact: stop the bodies moving
+// into each other, then apply friction up to what the normal impulse allows
+fn solve(bodies: &mut [SolverBody], contact: &mut Contact) {
+    let relative_velocity = |bodies: &[SolverBody]| {
+        bodies[contact.body].velocity_at(contact.point)
+            - contact.other.map_or(Vec3::ZERO, |o| bodies[o].velocity_at(contact.point))
+    };
+    let inverse_mass = |bodies: &[SolverBody], direction: Vec3| {
+        bodies[contact.body].inverse_mass_along(contact.point, direction)
+            + contact.other.map_or(0., |o| bodies[o].inverse_mass_along(contact.point, direction))
+    };
+    let apply = |bodies: &mut [SolverBody], impulse: Vec3| {
+        bodies[contact.body].apply_impulse(contact.point, impulse);
+        if let Some(other) = contact.other {
+            bodies[other].apply_impulse(contact.point, -impulse);
+        }
+    };
+
+    let normal = contact.normal;
+    let speed = relative_velocity(bodies).dot(normal);
+    let lambda = (contact.target - speed) / inverse_mass(bodies, normal);
+    let total = (contact.normal_impulse + lambda).max(0.);
+    let change = total - contact.normal_impulse;
+    contact.normal_impulse = total;
+    apply(bodies, normal * change);
+
+    let velocity = relative_velocity(bodies);
+    let sliding = velocity - normal * velocity.dot(normal);
+    let slide_speed = sliding.length();
+    if slide_speed < 1e-6 {
+        return;
+    }
+    let tangent = sliding / slide_speed;
+    let lambda = -slide_speed / inverse_mass(bodies, tangent);
+    let total = (contact.friction_impulse + tangent * lambda)
+        .clamp_length_max(contact.friction * contact.normal_impulse);
+    let change = total - contact.friction_impulse;
+    contact.friction_impulse = total;
+    apply(bodies, change);
+}
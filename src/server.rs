@@ -0,0 +1,571 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+
+use macroquad::prelude::*;
+
+use crate::connection::{Connection, DEFAULT_TIMEOUT, HEADER_SIZE};
+use crate::game::State;
+use crate::health::Damage;
+use crate::input::InputCommand;
+use crate::player::{PlayerEvent, PlayerState};
+use crate::protocol::{ClientMessage, InputPacket, MAX_PACKET_SIZE, ServerMessage, TimedCommand};
+use crate::rewind::{DEFAULT_MAX_REWIND, HitboxHistory};
+use crate::snapshot::{BodyState, ProjectileState, SNAPSHOT_HISTORY, Snapshot};
+use crate::timestep::{DEFAULT_TICK_RATE, FixedTimestep};
+use crate::transport::Transport;
+use crate::world::World;
+
+/// Port a server listens on unless told otherwise.
+pub const DEFAULT_PORT: u16 = 27960;
+
+// commands queued beyond this are dropped, oldest first, so a client that
+// runs fast can't build up lag
+const MAX_QUEUED_COMMANDS: usize = 8;
+
+// room kept in every packet for reliable messages, so welcomes, chat and
+// level changes get through however much there is to snapshot
+const RESERVED_FOR_MESSAGES: usize = 256;
+const SNAPSHOT_BUDGET: usize = MAX_PACKET_SIZE - HEADER_SIZE - RESERVED_FOR_MESSAGES;
+
+/// How far from a player, in units, other things still go in its snapshots
+/// unless configured otherwise.
+pub const DEFAULT_RELEVANCE_DISTANCE: f32 = 100.;
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    pub max_players: usize,
+    /// Seconds without a packet before a client is dropped.
+    pub timeout: f64,
+    pub tick_rate: u32,
+    /// Longest a hitscan shot is rewound, in seconds, to judge it against
+    /// where targets were on the shooter's screen. Zero judges every shot
+    /// against the present.
+    pub max_rewind: f32,
+    /// Players, bodies and projectiles further than this from a player are
+    /// left out of its snapshots, as are other players it has no line of
+    /// sight to.
+    pub relevance_distance: f32,
+    /// Path of the level, told to clients so they load the same one.
+    pub level: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            max_players: 16,
+            timeout: DEFAULT_TIMEOUT,
+            tick_rate: DEFAULT_TICK_RATE,
+            max_rewind: DEFAULT_MAX_REWIND,
+            relevance_distance: DEFAULT_RELEVANCE_DISTANCE,
+            level: "levels/test.ron".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaveReason {
+    Quit,
+    TimedOut,
+}
+
+/// Something that happened on the server, for logging.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerEvent {
+    Joined { id: u32, name: String, addr: SocketAddr },
+    Left { id: u32, name: String, reason: LeaveReason },
+    Chat { from: u32, text: String },
+    Died { id: u32, damage: Damage },
+    /// Packets to a client stopped going out. Said once until they go out
+    /// again.
+    SendFailed { addr: SocketAddr, error: String },
+}
+
+struct RemoteClient {
+    connection: Connection,
+    name: String,
+    /// None until joined, and for clients that were refused.
+    player: Option<u32>,
+    refused: bool,
+    // client tick of each command waiting to run
+    commands: VecDeque<(u32, TimedCommand)>,
+    // client ticks start at 1, so 0 until the first command is queued
+    last_queued: u32,
+    // server tick when `last_queued` was queued
+    queued_at: u32,
+    last_run: u32,
+    last_command: TimedCommand,
+    // snapshots sent and the sequence of the packet each went out in,
+    // oldest first, kept as baselines for deltas until a newer one is acked
+    sent_snapshots: VecDeque<(u16, Snapshot)>,
+    // how urgently each relevant body, by object index, needs sending
+    body_priority: HashMap<u32, f32>,
+    // whether the last packet to it failed to send
+    send_failing: bool,
+}
+
+/// The authoritative simulation. Owns the [`World`] and every player in it;
+/// clients only send their commands and are told what happened.
+pub struct Server<T: Transport> {
+    transport: T,
+    pub world: World,
+    pub config: ServerConfig,
+    clients: Vec<RemoteClient>,
+    time: f64,
+    timestep: FixedTimestep,
+    events: Vec<ServerEvent>,
+}
+
+impl<T: Transport> Server<T> {
+    pub fn new(transport: T, state: State, config: ServerConfig) -> Server<T> {
+        let mut world = World::new(state);
+        let max_rewind = (config.max_rewind.max(0.) * config.tick_rate as f32).round() as u32;
+        world.history = HitboxHistory::new(max_rewind);
+        Server {
+            transport,
+            world,
+            timestep: FixedTimestep::new(config.tick_rate),
+            config,
+            clients: Vec::new(),
+            time: 0.,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.transport.local_addr()
+    }
+
+    /// Id and name of every player in the game.
+    pub fn players(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.clients
+            .iter()
+            .filter_map(|client| Some((client.player?, client.name.as_str())))
+    }
+
+    /// Handles everything received, then runs as many ticks as `frame_time`
+    /// seconds call for, sending a snapshot after each.
+    pub fn update(&mut self, frame_time: f32) -> io::Result<()> {
+        self.time += frame_time as f64;
+        self.receive()?;
+        for _ in 0..self.timestep.advance(frame_time) {
+            self.tick();
+        }
+        self.drop_timed_out();
+        Ok(())
+    }
+
+    /// Events since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<ServerEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Handles every packet waiting.
+    pub fn receive(&mut self) -> io::Result<()> {
+        while let Some((from, packet)) = self.transport.receive()? {
+            let index = match self.clients.iter().position(|client| client.connection.addr == from) {
+                Some(index) => index,
+                None => {
+                    // only a packet that starts with a join makes a new client
+                    let mut connection = Connection::new(from, self.time);
+                    let Ok(Some(_)) = connection.receive(self.time, &packet) else {
+                        continue;
+                    };
+                    let Some(Ok(ClientMessage::Join { name })) =
+                        connection.receive_message().map(|data| ClientMessage::decode(&data))
+                    else {
+                        continue;
+                    };
+                    self.clients.push(RemoteClient {
+                        connection,
+                        name: String::new(),
+                        player: None,
+                        refused: false,
+                        commands: VecDeque::new(),
+                        last_queued: 0,
+                        queued_at: self.world.tick,
+                        last_run: 0,
+                        last_command: TimedCommand::default(),
+                        sent_snapshots: VecDeque::new(),
+                        body_priority: HashMap::new(),
+                        send_failing: false,
+                    });
+                    let index = self.clients.len() - 1;
+                    self.join(index, name);
+                    self.handle_messages(index);
+                    continue;
+                }
+            };
+
+            let tick = self.world.tick;
+            let client = &mut self.clients[index];
+            // garbage and duplicates are dropped
+            let Ok(Some(payload)) = client.connection.receive(self.time, &packet) else {
+                continue;
+            };
+            if client.player.is_some()
+                && !payload.is_empty()
+                && let Ok(input) = InputPacket::decode(&payload)
+            {
+                queue_commands(client, &input, tick);
+            }
+            self.handle_messages(index);
+        }
+        Ok(())
+    }
+
+    /// Runs one tick with each client's next command and sends everyone a
+    /// snapshot of the result.
+    pub fn tick(&mut self) {
+        let world = &mut self.world;
+        let commands: Vec<(u32, InputCommand)> = self
+            .clients
+            .iter_mut()
+            .filter_map(|client| {
+                let player = client.player?;
+                match client.commands.pop_front() {
+                    Some((tick, cmd)) => {
+                        client.last_run = tick;
+                        client.last_command = cmd;
+                    }
+                    // nothing arrived in time, so keep doing the same, but
+                    // without repeating one-shot presses
+                    None => {
+                        client.last_command.command.jump = false;
+                        client.last_command.command.next_weapon = false;
+                    }
+                }
+                if let Some(avatar) = world.avatar_mut(player) {
+                    avatar.view_tick = Some(client.last_command.view_tick);
+                }
+                Some((player, client.last_command.command))
+            })
+            .collect();
+        self.world.tick(&commands, self.timestep.dt());
+
+        for avatar in self.world.avatars.iter_mut() {
+            for event in avatar.player.take_events() {
+                if let PlayerEvent::Died(damage) = event {
+                    self.events.push(ServerEvent::Died {
+                        id: avatar.player.id,
+                        damage,
+                    });
+                }
+            }
+        }
+
+        let snapshot = self.snapshot();
+        for index in 0..self.clients.len() {
+            let payload = match self.clients[index].player {
+                Some(player) => {
+                    let mut relevant = self.relevant_to(&snapshot, player);
+                    let eye = self.world.avatar(player).map_or(Vec3::ZERO, |avatar| avatar.player.eye());
+                    let client = &mut self.clients[index];
+                    relevant.last_input = client.last_run;
+                    relevant.precise = Some(player);
+                    prioritize(client, &mut relevant.bodies, eye, self.config.relevance_distance);
+                    let (payload, sent) =
+                        relevant.encode_within(baseline(client, relevant.tick), SNAPSHOT_BUDGET);
+                    for body in sent.bodies.iter() {
+                        client.body_priority.insert(body.object, 0.);
+                    }
+                    let sequence = client.connection.next_sequence();
+                    client.sent_snapshots.push_back((sequence, sent));
+                    payload
+                }
+                None => Vec::new(),
+            };
+            let client = &mut self.clients[index];
+            let packet = client.connection.build_packet(self.time, &payload);
+            // one client that can't be reached mustn't cost the others their
+            // snapshots; it times out like any other if it stays that way
+            match self.transport.send(client.connection.addr, &packet) {
+                Ok(()) => client.send_failing = false,
+                Err(e) => {
+                    if !client.send_failing {
+                        self.events.push(ServerEvent::SendFailed {
+                            addr: client.connection.addr,
+                            error: e.to_string(),
+                        });
+                    }
+                    client.send_failing = true;
+                }
+            }
+        }
+        // refused clients stay only until they've heard why
+        self.clients
+            .retain(|client| !client.refused || !client.connection.all_acked());
+    }
+
+    /// The world as it is now, for no client in particular.
+    pub fn snapshot(&self) -> Snapshot {
+        let state = &self.world.state;
+        Snapshot {
+            tick: self.world.tick,
+            last_input: 0,
+            precise: None,
+            players: self
+                .world
+                .avatars
+                .iter()
+                .map(|avatar| avatar.player.state())
+                .collect(),
+            bodies: state
+                .bodies()
+                .iter()
+                .map(|&index| {
+                    let transform = state.objects()[index].get_transform();
+                    BodyState {
+                        object: index as u32,
+                        position: transform.position,
+                        rotation: transform.rotation,
+                    }
+                })
+                .collect(),
+            projectiles: state
+                .projectiles
+                .iter()
+                .map(|projectile| {
+                    let color = projectile.config.color;
+                    ProjectileState {
+                        position: projectile.position,
+                        velocity: projectile.velocity,
+                        radius: projectile.config.radius,
+                        color: [color.r, color.g, color.b].map(|c| (c * 255.).round() as u8),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// What of `snapshot` concerns the player `viewer`: itself, other
+    /// players it can see and anything else near enough. Players come
+    /// viewer first and projectiles nearest first, in case not all of them
+    /// fit.
+    pub fn relevant_to(&self, snapshot: &Snapshot, viewer: u32) -> Snapshot {
+        let state = &self.world.state;
+        let Some(eye) = self.world.avatar(viewer).map(|avatar| avatar.player.eye()) else {
+            return snapshot.clone();
+        };
+        let near = |position: Vec3| position.distance(eye) <= self.config.relevance_distance;
+        let mut players: Vec<PlayerState> = snapshot
+            .players
+            .iter()
+            .filter(|player| {
+                if player.id == viewer {
+                    return true;
+                }
+                let Some(avatar) = self.world.avatar(player.id) else {
+                    return false;
+                };
+                let other = &avatar.player;
+                near(other.center())
+                    && (in_sight(state, eye, other.eye()) || in_sight(state, eye, other.center()))
+            })
+            .copied()
+            .collect();
+        players.sort_by(|a, b| {
+            (b.id == viewer)
+                .cmp(&(a.id == viewer))
+                .then(a.position.distance(eye).total_cmp(&b.position.distance(eye)))
+        });
+        let mut projectiles: Vec<ProjectileState> = snapshot
+            .projectiles
+            .iter()
+            .filter(|projectile| near(projectile.position))
+            .copied()
+            .collect();
+        projectiles.sort_by(|a, b| a.position.distance(eye).total_cmp(&b.position.distance(eye)));
+        Snapshot {
+            players,
+            bodies: snapshot.bodies.iter().filter(|body| near(body.position)).copied().collect(),
+            projectiles,
+            ..*snapshot
+        }
+    }
+
+    /// Moves the game on to the level in `state`, `level` being its path for
+    /// clients to load. Everyone starts over at its spawn.
+    pub fn change_level(&mut self, state: State, level: String) {
+        let mut world = World::new(state);
+        // ticks keep counting, so clients can still tell old snapshots apart
+        world.tick = self.world.tick;
+        world.history = HitboxHistory::new(self.world.history.max_rewind());
+        for id in self.clients.iter().filter_map(|client| client.player) {
+            world.insert_player(id);
+        }
+        self.world = world;
+        self.config.level = level.clone();
+        self.broadcast(&ServerMessage::ChangeLevel { level });
+    }
+
+    /// Tells everyone the server is going away. Keep calling
+    /// [`Server::update`] until [`Server::all_acked`] to be sure they heard.
+    pub fn shut_down(&mut self, reason: &str) {
+        self.broadcast(&ServerMessage::Shutdown(reason.to_string()));
+    }
+
+    /// Whether every client has acked every message sent to it.
+    pub fn all_acked(&self) -> bool {
+        self.clients.iter().all(|client| client.connection.all_acked())
+    }
+
+    /// Sends `message` reliably to everyone in the game.
+    pub fn broadcast(&mut self, message: &ServerMessage) {
+        let data = message.encode();
+        for client in self.clients.iter_mut().filter(|client| client.player.is_some()) {
+            client.connection.send_message(data.clone());
+        }
+    }
+
+    fn join(&mut self, index: usize, name: String) {
+        let joined = self.clients.iter().filter(|client| client.player.is_some()).count();
+        if joined >= self.config.max_players {
+            let client = &mut self.clients[index];
+            client.refused = true;
+            let refusal = ServerMessage::Refused("server is full".to_string());
+            client.connection.send_message(refusal.encode());
+            return;
+        }
+
+        let id = self.world.add_player();
+        let others: Vec<ServerMessage> = self
+            .players()
+            .map(|(id, name)| ServerMessage::PlayerJoined {
+                id,
+                name: name.to_string(),
+            })
+            .collect();
+        let client = &mut self.clients[index];
+        let welcome = ServerMessage::Welcome {
+            player_id: id,
+            tick_rate: self.config.tick_rate,
+            level: self.config.level.clone(),
+        };
+        client.connection.send_message(welcome.encode());
+        for message in others {
+            client.connection.send_message(message.encode());
+        }
+        client.player = Some(id);
+        client.name = name.clone();
+        let addr = client.connection.addr;
+        // everyone, the new player included, hears who joined
+        self.broadcast(&ServerMessage::PlayerJoined {
+            id,
+            name: name.clone(),
+        });
+        self.events.push(ServerEvent::Joined { id, name, addr });
+    }
+
+    fn handle_messages(&mut self, index: usize) {
+        while let Some(data) = self.clients[index].connection.receive_message() {
+            let Ok(message) = ClientMessage::decode(&data) else {
+                continue;
+            };
+            let client = &self.clients[index];
+            match (message, client.player) {
+                // a second join from the same address is a resend
+                (ClientMessage::Join { .. }, _) => {}
+                (ClientMessage::Chat(text), Some(from)) => {
+                    self.broadcast(&ServerMessage::Chat {
+                        from,
+                        text: text.clone(),
+                    });
+                    self.events.push(ServerEvent::Chat { from, text });
+                }
+                (ClientMessage::Chat(_), None) => {}
+                (ClientMessage::Leave, _) => {
+                    self.remove_client(index, LeaveReason::Quit);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn drop_timed_out(&mut self) {
+        while let Some(index) = self
+            .clients
+            .iter()
+            .position(|client| client.connection.timed_out(self.time, self.config.timeout))
+        {
+            self.remove_client(index, LeaveReason::TimedOut);
+        }
+    }
+
+    fn remove_client(&mut self, index: usize, reason: LeaveReason) {
+        let client = self.clients.remove(index);
+        if let Some(id) = client.player {
+            self.world.remove_player(id);
+            self.broadcast(&ServerMessage::PlayerLeft { id });
+            self.events.push(ServerEvent::Left {
+                id,
+                name: client.name,
+                reason,
+            });
+        }
+    }
+}
+
+// the newest snapshot `client` is known to have, forgetting the ones before
+// it and any too old to still be kept at both ends
+fn baseline(client: &mut RemoteClient, tick: u32) -> Option<&Snapshot> {
+    let sent = &mut client.sent_snapshots;
+    while sent.front().is_some_and(|(_, snapshot)| tick - snapshot.tick >= SNAPSHOT_HISTORY) {
+        sent.pop_front();
+    }
+    let acked = client.connection.newest_acked()?;
+    let index = sent.iter().position(|(sequence, _)| *sequence == acked)?;
+    sent.drain(..index);
+    sent.front().map(|(_, snapshot)| snapshot)
+}
+
+// bodies go by how urgently they need sending, which grows every tick by
+// more the nearer they are and starts over once they're sent, so when there
+// are too many for one snapshot the far ones still get their turn
+fn prioritize(client: &mut RemoteClient, bodies: &mut [BodyState], eye: Vec3, range: f32) {
+    let priority: HashMap<u32, f32> = bodies
+        .iter()
+        .map(|body| {
+            let waited = client.body_priority.get(&body.object).copied().unwrap_or(0.);
+            (body.object, waited + range / (body.position.distance(eye) + 1.))
+        })
+        .collect();
+    bodies.sort_by(|a, b| priority[&b.object].total_cmp(&priority[&a.object]));
+    client.body_priority = priority;
+}
+
+// whether nothing in the level stands between `from` and `to`
+fn in_sight(state: &State, from: Vec3, to: Vec3) -> bool {
+    let offset = to - from;
+    let distance = offset.length();
+    distance <= f32::EPSILON || state.cast_ray(from, offset / distance, distance).is_none()
+}
+
+// a client runs a tick per server tick, so a packet more than a queue's
+// worth of ticks ahead of the last command queued, plus the server ticks
+// since, is one no honest client could send and is dropped
+fn queue_commands(client: &mut RemoteClient, input: &InputPacket, server_tick: u32) {
+    let since = server_tick.saturating_sub(client.queued_at);
+    let newest_allowed = client
+        .last_queued
+        .saturating_add(MAX_QUEUED_COMMANDS as u32)
+        .saturating_add(since);
+    if input.tick > newest_allowed {
+        return;
+    }
+    let count = input.commands.len() as u32;
+    for (i, cmd) in input.commands.iter().enumerate() {
+        // the last command is for `input.tick`, those before for the ticks before
+        let Some(tick) = input.tick.checked_sub(count - 1 - i as u32) else {
+            continue;
+        };
+        if tick > client.last_queued {
+            client.commands.push_back((tick, *cmd));
+            client.last_queued = tick;
+            client.queued_at = server_tick;
+        }
+    }
+    while client.commands.len() > MAX_QUEUED_COMMANDS {
+        client.commands.pop_front();
+    }
+}
@@ -0,0 +1,177 @@
+use macroquad::prelude::*;
+
+use crate::batch::StaticBatch;
+use crate::game::State;
+use crate::materials::{self, Materials};
+
+pub const DEFAULT_RESOLUTION: u32 = 2048;
+
+/// Depth map of the level as seen from the sun, used by the world shader to
+/// darken whatever the sun can't reach. Covers a square area around a focus
+/// point, normally the camera, that moves along with it.
+pub struct ShadowMap {
+    /// Turns sun shadows off without freeing the map.
+    pub enabled: bool,
+    /// Half the side of the square area that gets shadows, in world units.
+    pub extent: f32,
+    /// How far in front of and behind the focus point casters are drawn,
+    /// along the sun's direction.
+    pub depth: f32,
+    target: RenderTarget,
+    // world to shadow map clip space, from the last render
+    matrix: Mat4,
+    // whether the last render produced a usable map
+    active: bool,
+}
+
+// renders from the sun's point of view into the shadow map
+struct LightCamera {
+    matrix: Mat4,
+    render_pass: RenderPass,
+}
+
+impl Camera for LightCamera {
+    fn matrix(&self) -> Mat4 {
+        self.matrix
+    }
+    fn depth_enabled(&self) -> bool {
+        true
+    }
+    fn render_pass(&self) -> Option<RenderPass> {
+        Some(self.render_pass.clone())
+    }
+    fn viewport(&self) -> Option<(i32, i32, i32, i32)> {
+        None
+    }
+}
+
+impl ShadowMap {
+    /// Creates a `resolution` by `resolution` map. Needs a GL context.
+    pub fn new(resolution: u32) -> ShadowMap {
+        ShadowMap {
+            enabled: true,
+            extent: 25.,
+            depth: 100.,
+            target: shadow_target(resolution),
+            matrix: Mat4::IDENTITY,
+            active: false,
+        }
+    }
+
+    pub fn resolution(&self) -> u32 {
+        self.target.texture.width() as u32
+    }
+
+    /// Reallocates the map at a new size, if it differs from the current one.
+    pub fn set_resolution(&mut self, resolution: u32) {
+        if resolution != self.resolution() {
+            self.target = shadow_target(resolution);
+        }
+    }
+
+    /// Draws every object of `state` into the map from the sun's direction,
+    /// covering the area around `focus`. Leaves the light's camera set, so
+    /// set the view camera again afterwards.
+    pub fn render(&mut self, state: &State, batch: &StaticBatch, focus: Vec3, materials: &Materials) {
+        self.active = false;
+        let Some(sun) = &state.lighting.sun else {
+            return;
+        };
+        if !self.enabled {
+            return;
+        }
+
+        let up = if sun.direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+        let view = Mat4::look_at_rh(Vec3::ZERO, sun.direction, up);
+        // move the area in whole texels, otherwise shadow edges crawl as the
+        // camera moves
+        let texel = 2. * self.extent / self.resolution() as f32;
+        let center = view.transform_point3(focus);
+        let center = vec3(
+            (center.x / texel).round() * texel,
+            (center.y / texel).round() * texel,
+            center.z,
+        );
+        // the view looks down -z
+        let projection = Mat4::orthographic_rh_gl(
+            center.x - self.extent,
+            center.x + self.extent,
+            center.y - self.extent,
+            center.y + self.extent,
+            -center.z - self.depth,
+            -center.z + self.depth,
+        );
+        self.matrix = projection * view;
+
+        set_camera(&LightCamera {
+            matrix: self.matrix,
+            render_pass: self.target.render_pass.clone(),
+        });
+        // white unpacks to the far plane, i.e. nothing in the way
+        clear_background(WHITE);
+        materials.apply(materials::SHADOW);
+        batch.draw_shapes();
+        for (index, obj) in state.objects().iter().enumerate() {
+            if !batch.contains(index) {
+                obj.draw_shape();
+            }
+        }
+        gl_use_default_material();
+        self.active = true;
+    }
+
+    /// Sets the shadow uniforms of a lit material.
+    pub fn apply(&self, material: &Material) {
+        let texel = 1. / self.resolution() as f32;
+        let world_texel = 2. * self.extent * texel;
+        material.set_uniform("ShadowMatrix", self.matrix);
+        material.set_uniform(
+            "ShadowParams",
+            vec4(
+                if self.active { 1. } else { 0. },
+                texel,
+                // a texel's worth of depth bias, in the map's 0 to 1 range
+                world_texel / (2. * self.depth),
+                // surfaces are looked up a little off along their normal,
+                // which stops them shadowing themselves
+                1.5 * world_texel,
+            ),
+        );
+        material.set_texture("ShadowMap", self.target.texture.clone());
+    }
+
+    /// Draws the map in screen space, nearer depths darker. For debugging.
+    pub fn draw_debug(&self, materials: &Materials, x: f32, y: f32, size: f32) {
+        set_default_camera();
+        materials.apply(materials::SHADOW_DEBUG);
+        draw_texture_ex(
+            &self.target.texture,
+            x,
+            y,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(size, size)),
+                // render targets are stored bottom row first
+                flip_y: true,
+                ..Default::default()
+            },
+        );
+        gl_use_default_material();
+    }
+}
+
+fn shadow_target(resolution: u32) -> RenderTarget {
+    let resolution = resolution.max(1);
+    let target = render_target_ex(
+        resolution,
+        resolution,
+        RenderTargetParams {
+            sample_count: 1,
+            depth: true,
+        },
+    );
+    // depth is packed into the color channels, blending texels would mix
+    // unrelated bits
+    target.texture.set_filter(FilterMode::Nearest);
+    target
+}
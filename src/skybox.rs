@@ -0,0 +1,179 @@
+use std::fmt;
+
+use macroquad::miniquad::{
+    FilterMode, TextureAccess, TextureFormat, TextureKind, TextureParams, TextureSource,
+    TextureWrap,
+};
+use macroquad::prelude::*;
+
+use crate::materials::{self, Materials};
+
+/// Faces in the order GL stores cubemap layers: +x, -x, +y, -y, +z, -z. The
+/// skybox shader flips z when sampling (GL cubemaps are left handed), so +z
+/// is the face seen looking down the world's -z axis.
+pub const FACE_NAMES: [&str; 6] = ["right", "left", "top", "bottom", "front", "back"];
+
+// cell of each face in a horizontal cross, 4 faces wide and 3 tall:
+//
+//          top
+//    left  front  right  back
+//          bottom
+const HORIZONTAL_CROSS: [(u32, u32); 6] = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+// and in a vertical cross, 3 wide and 4 tall, where back is upside down
+// below bottom
+const VERTICAL_CROSS: [(u32, u32); 6] = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (1, 3)];
+
+#[derive(Debug)]
+pub enum SkyboxError {
+    /// The image is not a 4x3 or 3x4 grid of square faces.
+    NotACross { width: u16, height: u16 },
+    /// Cube faces have to be square and all the same size.
+    FaceSize { face: &'static str, width: u16, height: u16 },
+}
+
+impl fmt::Display for SkyboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkyboxError::NotACross { width, height } => write!(
+                f,
+                "{}x{} is not a cross layout, expected 4:3 or 3:4",
+                width, height
+            ),
+            SkyboxError::FaceSize {
+                face,
+                width,
+                height,
+            } => write!(
+                f,
+                "{} face is {}x{}, faces must be square and the same size",
+                face, width, height
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SkyboxError {}
+
+/// Sky drawn from a cubemap behind everything else. It follows the camera's
+/// rotation but not its position, so it always looks infinitely far away.
+pub struct Skybox {
+    pub rotation: Quat,
+    /// Multiplied with the sky's colors.
+    pub tint: Color,
+    cubemap: Texture2D,
+    cube: Mesh,
+}
+
+impl Skybox {
+    /// Cuts the six faces out of a cross-layout image.
+    pub fn from_cross(image: &Image) -> Result<Skybox, SkyboxError> {
+        let (width, height) = (image.width, image.height);
+        let (cells, size) = if width / 4 == height / 3 && width % 4 == 0 && height % 3 == 0 {
+            (HORIZONTAL_CROSS, width / 4)
+        } else if width / 3 == height / 4 && width % 3 == 0 && height % 4 == 0 {
+            (VERTICAL_CROSS, width / 3)
+        } else {
+            return Err(SkyboxError::NotACross { width, height });
+        };
+
+        let mut faces = cells.map(|(x, y)| {
+            let size = size as f32;
+            image.sub_image(Rect::new(x as f32 * size, y as f32 * size, size, size))
+        });
+        if height > width {
+            // the back face of a vertical cross is stored upside down
+            faces[5].get_image_data_mut().reverse();
+        }
+        Skybox::from_faces(&faces)
+    }
+
+    /// Builds the cubemap from six square images, ordered as [`FACE_NAMES`].
+    pub fn from_faces(faces: &[Image; 6]) -> Result<Skybox, SkyboxError> {
+        let size = faces[0].width;
+        for (face, image) in faces.iter().enumerate() {
+            if image.width != size || image.height != size {
+                return Err(SkyboxError::FaceSize {
+                    face: FACE_NAMES[face],
+                    width: image.width,
+                    height: image.height,
+                });
+            }
+        }
+
+        let layers: Vec<[&[u8]; 1]> = faces.iter().map(|face| [&face.bytes[..]]).collect();
+        let layers: Vec<&[&[u8]]> = layers.iter().map(|layer| &layer[..]).collect();
+        let id = unsafe {
+            get_internal_gl().quad_context.new_texture(
+                TextureAccess::Static,
+                TextureSource::Array(&layers),
+                TextureParams {
+                    kind: TextureKind::CubeMap,
+                    format: TextureFormat::RGBA8,
+                    // clamped, so edges don't blend with the opposite side of a face
+                    wrap: TextureWrap::Clamp,
+                    min_filter: FilterMode::Linear,
+                    mag_filter: FilterMode::Linear,
+                    width: size as u32,
+                    height: size as u32,
+                    ..Default::default()
+                },
+            )
+        };
+
+        Ok(Skybox {
+            rotation: Quat::IDENTITY,
+            tint: WHITE,
+            cubemap: Texture2D::from_miniquad_texture(id),
+            cube: unit_cube(),
+        })
+    }
+
+    /// Draws the sky as seen from `camera`. Call after opaque geometry so
+    /// hidden sky pixels are rejected by the depth test.
+    pub fn draw(&self, camera: &Camera3D, materials: &Materials) {
+        let aspect = camera.aspect.unwrap_or(screen_width() / screen_height());
+        let projection = Mat4::perspective_rh_gl(camera.fovy, aspect, camera.z_near, camera.z_far);
+        // looking from the origin drops the camera's translation
+        let view = Mat4::look_at_rh(Vec3::ZERO, camera.target - camera.position, camera.up);
+
+        let material = materials.get(materials::SKYBOX);
+        material.set_uniform(
+            "SkyViewProjection",
+            projection * view * Mat4::from_quat(self.rotation),
+        );
+        material.set_uniform("Tint", self.tint.to_vec());
+        material.set_texture("Sky", self.cubemap.clone());
+        gl_use_material(material);
+        draw_mesh(&self.cube);
+        gl_use_default_material();
+    }
+}
+
+fn unit_cube() -> Mesh {
+    let corners = [
+        vec3(-1., -1., -1.),
+        vec3(1., -1., -1.),
+        vec3(1., 1., -1.),
+        vec3(-1., 1., -1.),
+        vec3(-1., -1., 1.),
+        vec3(1., -1., 1.),
+        vec3(1., 1., 1.),
+        vec3(-1., 1., 1.),
+    ];
+    Mesh {
+        vertices: corners
+            .iter()
+            .map(|c| Vertex::new(c.x, c.y, c.z, 0., 0., WHITE))
+            .collect(),
+        // face culling is off for the sky, so winding doesn't matter
+        indices: vec![
+            0, 1, 2, 0, 2, 3, // -z
+            4, 5, 6, 4, 6, 7, // +z
+            0, 1, 5, 0, 5, 4, // -y
+            3, 2, 6, 3, 6, 7, // +y
+            0, 3, 7, 0, 7, 4, // -x
+            1, 2, 6, 1, 6, 5, // +x
+        ],
+        texture: None,
+    }
+}
@@ -0,0 +1,481 @@
+use std::f32::consts::{FRAC_PI_2, PI, SQRT_2, TAU};
+
+use macroquad::prelude::*;
+
+use crate::bits::{BitReader, BitWriter, dequantize, quantize};
+use crate::player::PlayerState;
+use crate::protocol::DecodeError;
+
+/// How many ticks back a snapshot may still serve as the baseline of a
+/// delta. Both ends keep their snapshots at least this long.
+pub const SNAPSHOT_HISTORY: u32 = 64;
+
+// every quantized value is a signed number of steps of 1 / scale:
+
+// positions to 1/512 of a unit, out to 16384 units from the origin
+const POSITION_SCALE: f32 = 512.;
+const POSITION_BITS: u32 = 24;
+// velocities to 1/64 unit per second, up to 512 units per second
+const VELOCITY_SCALE: f32 = 64.;
+const VELOCITY_BITS: u32 = 16;
+// theta, wrapped into a half turn either way
+const THETA_BITS: u32 = 16;
+const THETA_SCALE: f32 = 32768. / PI;
+// phi, a quarter turn up or down
+const PHI_BITS: u32 = 15;
+const PHI_SCALE: f32 = 16384. / FRAC_PI_2;
+// health and armor to 1/64 of a point
+const VITALS_SCALE: f32 = 64.;
+const VITALS_BITS: u32 = 16;
+// time dead to 1/256 of a second, up to two minutes
+const DEAD_SCALE: f32 = 256.;
+const DEAD_BITS: u32 = 16;
+// all but the largest component of a unit quaternion, which are within
+// ±1/√2
+const ROTATION_BITS: u32 = 15;
+const ROTATION_SCALE: f32 = 16383. * SQRT_2;
+const RADIUS_SCALE: f32 = 256.;
+const RADIUS_BITS: u32 = 12;
+// how far back a baseline may be, in ticks
+const BASELINE_BITS: u32 = 16;
+// a quantized value that moved less than this many steps either way since
+// the baseline is sent as the difference
+const DELTA_BITS: u32 = 11;
+
+/// Where a rigid body is, by its index in `State::objects`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BodyState {
+    pub object: u32,
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+/// A projectile as far as drawing it goes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProjectileState {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub radius: f32,
+    pub color: [u8; 3],
+}
+
+/// The part of the world a client gets to know about after a tick, sent
+/// unreliably to it every tick.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    /// Server tick it was taken after.
+    pub tick: u32,
+    /// Client tick of the last command of the receiving client the server
+    /// has run, so the client knows which of its commands are accounted for.
+    pub last_input: u32,
+    /// The player sent at full precision, the receiving client's own, whose
+    /// prediction has to start from exactly what the server has.
+    pub precise: Option<u32>,
+    pub players: Vec<PlayerState>,
+    pub bodies: Vec<BodyState>,
+    pub projectiles: Vec<ProjectileState>,
+}
+
+impl Snapshot {
+    /// The snapshot as it comes out the other end: every value but those of
+    /// the precise player rounded to what the wire can carry.
+    pub fn quantized(&self) -> Snapshot {
+        let players = self
+            .players
+            .iter()
+            .map(|player| {
+                if Some(player.id) == self.precise {
+                    return *player;
+                }
+                PlayerState {
+                    position: grid_vec3(player.position, POSITION_SCALE, POSITION_BITS),
+                    velocity: grid_vec3(player.velocity, VELOCITY_SCALE, VELOCITY_BITS),
+                    theta: grid(wrap_angle(player.theta), THETA_SCALE, THETA_BITS),
+                    phi: grid(player.phi, PHI_SCALE, PHI_BITS),
+                    health: grid(player.health, VITALS_SCALE, VITALS_BITS),
+                    armor: grid(player.armor, VITALS_SCALE, VITALS_BITS),
+                    dead_for: player.dead_for.map(|t| grid(t, DEAD_SCALE, DEAD_BITS)),
+                    ..*player
+                }
+            })
+            .collect();
+        let bodies = self
+            .bodies
+            .iter()
+            .map(|body| BodyState {
+                object: body.object,
+                position: grid_vec3(body.position, POSITION_SCALE, POSITION_BITS),
+                rotation: rotation_from_steps(rotation_steps(body.rotation)),
+            })
+            .collect();
+        let projectiles = self
+            .projectiles
+            .iter()
+            .map(|projectile| ProjectileState {
+                position: grid_vec3(projectile.position, POSITION_SCALE, POSITION_BITS),
+                velocity: grid_vec3(projectile.velocity, VELOCITY_SCALE, VELOCITY_BITS),
+                radius: grid(projectile.radius, RADIUS_SCALE, RADIUS_BITS),
+                color: projectile.color,
+            })
+            .collect();
+        Snapshot {
+            players,
+            bodies,
+            projectiles,
+            ..*self
+        }
+    }
+
+    /// Bit-packs the snapshot, leaving out what hasn't changed since
+    /// `baseline`, an earlier snapshot the receiver is known to have.
+    /// Decodes to [`Snapshot::quantized`].
+    pub fn encode(&self, baseline: Option<&Snapshot>) -> Vec<u8> {
+        self.encode_within(baseline, usize::MAX).0
+    }
+
+    /// Like [`Snapshot::encode`], but in at most `max_bytes`: players, then
+    /// projectiles, then bodies are added in the order they're listed until
+    /// the next one wouldn't fit. Returns the bytes and the snapshot they
+    /// decode to, with only what fit.
+    pub fn encode_within(&self, baseline: Option<&Snapshot>, max_bytes: usize) -> (Vec<u8>, Snapshot) {
+        let mut snapshot = self.quantized();
+        let mut w = BitWriter::new();
+        w.write(snapshot.tick, 32);
+        w.write(snapshot.last_input, 32);
+        w.bool(snapshot.precise.is_some());
+        if let Some(id) = snapshot.precise {
+            w.uint(id);
+        }
+        let baseline = baseline.filter(|baseline| {
+            baseline.tick < snapshot.tick && snapshot.tick - baseline.tick < 1 << BASELINE_BITS
+        });
+        w.bool(baseline.is_some());
+        if let Some(baseline) = baseline {
+            w.write(snapshot.tick - baseline.tick, BASELINE_BITS);
+        }
+
+        let max_bits = max_bytes.saturating_mul(8);
+        let precise = snapshot.precise;
+        let sent = write_list(&mut w, &snapshot.players, max_bits, 2, |w, player| {
+            let base = baseline.and_then(|b| b.players.iter().find(|p| p.id == player.id));
+            write_player(w, player, Some(player.id) == precise, base);
+        });
+        snapshot.players.truncate(sent);
+        // projectiles are few and short-lived, so they're always sent whole
+        let sent = write_list(&mut w, &snapshot.projectiles, max_bits, 1, |w, projectile| {
+            write_vec3(w, projectile.position, None, false, POSITION_SCALE, POSITION_BITS);
+            write_vec3(w, projectile.velocity, None, false, VELOCITY_SCALE, VELOCITY_BITS);
+            write_scalar(w, projectile.radius, None, false, RADIUS_SCALE, RADIUS_BITS);
+            for channel in projectile.color {
+                w.write(channel as u32, 8);
+            }
+        });
+        snapshot.projectiles.truncate(sent);
+        let sent = write_list(&mut w, &snapshot.bodies, max_bits, 0, |w, body| {
+            w.uint(body.object);
+            let base = baseline.and_then(|b| b.bodies.iter().find(|o| o.object == body.object));
+            if changed(w, base.map(|base| base == body)) {
+                let base = base.map(|base| base.position);
+                write_vec3(w, body.position, base, false, POSITION_SCALE, POSITION_BITS);
+                let (largest, steps) = rotation_steps(body.rotation);
+                w.write(largest, 2);
+                for step in steps {
+                    w.signed(step, ROTATION_BITS);
+                }
+            }
+        });
+        snapshot.bodies.truncate(sent);
+        (w.into_bytes(), snapshot)
+    }
+
+    /// Reads a snapshot back, looking up the baseline it was encoded
+    /// against, if any, by tick.
+    pub fn decode<'a>(
+        bytes: &[u8],
+        baseline: impl FnOnce(u32) -> Option<&'a Snapshot>,
+    ) -> Result<Snapshot, DecodeError> {
+        let mut r = BitReader::new(bytes);
+        let tick = r.read(32)?;
+        let last_input = r.read(32)?;
+        let precise = if r.bool()? { Some(r.uint()?) } else { None };
+        let baseline = if r.bool()? {
+            let base_tick = tick.wrapping_sub(r.read(BASELINE_BITS)?);
+            Some(baseline(base_tick).ok_or(DecodeError::MissingBaseline(base_tick))?)
+        } else {
+            None
+        };
+
+        let players = read_list(&mut r, |r| read_player(r, precise, baseline))?;
+        let projectiles = read_list(&mut r, |r| {
+            Ok(ProjectileState {
+                position: read_vec3(r, None, false, POSITION_SCALE, POSITION_BITS)?,
+                velocity: read_vec3(r, None, false, VELOCITY_SCALE, VELOCITY_BITS)?,
+                radius: read_scalar(r, None, false, RADIUS_SCALE, RADIUS_BITS)?,
+                color: [r.read(8)? as u8, r.read(8)? as u8, r.read(8)? as u8],
+            })
+        })?;
+        let bodies = read_list(&mut r, |r| {
+            let object = r.uint()?;
+            let base = baseline.and_then(|b| b.bodies.iter().find(|o| o.object == object));
+            if let Some(base) = base
+                && !r.bool()?
+            {
+                return Ok(*base);
+            }
+            let base = base.map(|base| base.position);
+            let position = read_vec3(r, base, false, POSITION_SCALE, POSITION_BITS)?;
+            let largest = r.read(2)?;
+            let steps = [
+                r.signed(ROTATION_BITS)?,
+                r.signed(ROTATION_BITS)?,
+                r.signed(ROTATION_BITS)?,
+            ];
+            Ok(BodyState {
+                object,
+                position,
+                rotation: rotation_from_steps((largest, steps)),
+            })
+        })?;
+        Ok(Snapshot {
+            tick,
+            last_input,
+            precise,
+            players,
+            bodies,
+            projectiles,
+        })
+    }
+}
+
+// each item behind a set bit and a clear bit after the last, stopping at
+// the first item that would leave less than `max_bits` room for the clear
+// bits of this list and the `lists_after` still to come; returns how many
+// went in
+fn write_list<T>(
+    w: &mut BitWriter,
+    items: &[T],
+    max_bits: usize,
+    lists_after: usize,
+    mut write: impl FnMut(&mut BitWriter, &T),
+) -> usize {
+    let mut sent = 0;
+    for item in items {
+        let start = w.bit_len();
+        w.bool(true);
+        write(w, item);
+        if w.bit_len() + 1 + lists_after > max_bits {
+            w.truncate(start);
+            break;
+        }
+        sent += 1;
+    }
+    w.bool(false);
+    sent
+}
+
+fn read_list<T>(
+    r: &mut BitReader,
+    mut read: impl FnMut(&mut BitReader) -> Result<T, DecodeError>,
+) -> Result<Vec<T>, DecodeError> {
+    let mut items = Vec::new();
+    while r.bool()? {
+        items.push(read(r)?);
+    }
+    Ok(items)
+}
+
+// players go in groups of fields that tend to change together, each behind
+// a bit saying whether it differs from the baseline
+fn write_player(w: &mut BitWriter, player: &PlayerState, precise: bool, base: Option<&PlayerState>) {
+    w.uint(player.id);
+    w.bool(player.on_ground);
+    w.bool(player.crouched);
+    if changed(w, base.map(|base| base.position == player.position)) {
+        let base = base.map(|base| base.position);
+        write_vec3(w, player.position, base, precise, POSITION_SCALE, POSITION_BITS);
+    }
+    if changed(w, base.map(|base| base.velocity == player.velocity)) {
+        let base = base.map(|base| base.velocity);
+        write_vec3(w, player.velocity, base, precise, VELOCITY_SCALE, VELOCITY_BITS);
+    }
+    if changed(w, base.map(|base| (base.theta, base.phi) == (player.theta, player.phi))) {
+        write_scalar(w, player.theta, base.map(|base| base.theta), precise, THETA_SCALE, THETA_BITS);
+        write_scalar(w, player.phi, base.map(|base| base.phi), precise, PHI_SCALE, PHI_BITS);
+    }
+    if changed(w, base.map(|base| (base.health, base.armor) == (player.health, player.armor))) {
+        write_scalar(w, player.health, base.map(|base| base.health), precise, VITALS_SCALE, VITALS_BITS);
+        write_scalar(w, player.armor, base.map(|base| base.armor), precise, VITALS_SCALE, VITALS_BITS);
+    }
+    if changed(w, base.map(|base| base.dead_for == player.dead_for)) {
+        w.bool(player.dead_for.is_some());
+        if let Some(dead_for) = player.dead_for {
+            let base = base.and_then(|base| base.dead_for);
+            write_scalar(w, dead_for, base, precise, DEAD_SCALE, DEAD_BITS);
+        }
+    }
+}
+
+fn read_player(
+    r: &mut BitReader,
+    precise: Option<u32>,
+    baseline: Option<&Snapshot>,
+) -> Result<PlayerState, DecodeError> {
+    let id = r.uint()?;
+    let on_ground = r.bool()?;
+    let crouched = r.bool()?;
+    let precise = precise == Some(id);
+    let base = baseline.and_then(|b| b.players.iter().find(|p| p.id == id));
+    let position = match base {
+        Some(base) if !r.bool()? => base.position,
+        _ => read_vec3(r, base.map(|base| base.position), precise, POSITION_SCALE, POSITION_BITS)?,
+    };
+    let velocity = match base {
+        Some(base) if !r.bool()? => base.velocity,
+        _ => read_vec3(r, base.map(|base| base.velocity), precise, VELOCITY_SCALE, VELOCITY_BITS)?,
+    };
+    let (theta, phi) = match base {
+        Some(base) if !r.bool()? => (base.theta, base.phi),
+        _ => (
+            read_scalar(r, base.map(|base| base.theta), precise, THETA_SCALE, THETA_BITS)?,
+            read_scalar(r, base.map(|base| base.phi), precise, PHI_SCALE, PHI_BITS)?,
+        ),
+    };
+    let (health, armor) = match base {
+        Some(base) if !r.bool()? => (base.health, base.armor),
+        _ => (
+            read_scalar(r, base.map(|base| base.health), precise, VITALS_SCALE, VITALS_BITS)?,
+            read_scalar(r, base.map(|base| base.armor), precise, VITALS_SCALE, VITALS_BITS)?,
+        ),
+    };
+    let dead_for = match base {
+        Some(base) if !r.bool()? => base.dead_for,
+        _ if r.bool()? => {
+            let base = base.and_then(|base| base.dead_for);
+            Some(read_scalar(r, base, precise, DEAD_SCALE, DEAD_BITS)?)
+        }
+        _ => None,
+    };
+    Ok(PlayerState {
+        id,
+        position,
+        velocity,
+        theta,
+        phi,
+        on_ground,
+        crouched,
+        health,
+        armor,
+        dead_for,
+    })
+}
+
+// writes whether a value needs sending: always without a baseline,
+// otherwise only if it differs from the baseline's
+fn changed(w: &mut BitWriter, same_as_base: Option<bool>) -> bool {
+    match same_as_base {
+        Some(same) => {
+            w.bool(!same);
+            !same
+        }
+        None => true,
+    }
+}
+
+// raw when `precise`; otherwise quantized, and as the difference from
+// `base` when that's small
+fn write_scalar(w: &mut BitWriter, value: f32, base: Option<f32>, precise: bool, scale: f32, bits: u32) {
+    if precise {
+        w.f32(value);
+        return;
+    }
+    let steps = quantize(value, scale, bits);
+    if let Some(base) = base {
+        let delta = steps - quantize(base, scale, bits);
+        let small = delta.abs() < 1 << (DELTA_BITS - 1);
+        w.bool(small);
+        if small {
+            w.signed(delta, DELTA_BITS);
+            return;
+        }
+    }
+    w.signed(steps, bits);
+}
+
+fn read_scalar(
+    r: &mut BitReader,
+    base: Option<f32>,
+    precise: bool,
+    scale: f32,
+    bits: u32,
+) -> Result<f32, DecodeError> {
+    if precise {
+        return r.f32();
+    }
+    let steps = match base {
+        Some(base) if r.bool()? => quantize(base, scale, bits) + r.signed(DELTA_BITS)?,
+        _ => r.signed(bits)?,
+    };
+    Ok(dequantize(steps, scale))
+}
+
+fn write_vec3(w: &mut BitWriter, value: Vec3, base: Option<Vec3>, precise: bool, scale: f32, bits: u32) {
+    for i in 0..3 {
+        write_scalar(w, value[i], base.map(|base| base[i]), precise, scale, bits);
+    }
+}
+
+fn read_vec3(
+    r: &mut BitReader,
+    base: Option<Vec3>,
+    precise: bool,
+    scale: f32,
+    bits: u32,
+) -> Result<Vec3, DecodeError> {
+    Ok(vec3(
+        read_scalar(r, base.map(|base| base.x), precise, scale, bits)?,
+        read_scalar(r, base.map(|base| base.y), precise, scale, bits)?,
+        read_scalar(r, base.map(|base| base.z), precise, scale, bits)?,
+    ))
+}
+
+// the nearest value the wire can carry
+fn grid(value: f32, scale: f32, bits: u32) -> f32 {
+    dequantize(quantize(value, scale, bits), scale)
+}
+
+fn grid_vec3(value: Vec3, scale: f32, bits: u32) -> Vec3 {
+    value.to_array().map(|component| grid(component, scale, bits)).into()
+}
+
+// the same direction, between -π and π
+fn wrap_angle(angle: f32) -> f32 {
+    let angle = angle.rem_euclid(TAU);
+    if angle >= PI { angle - TAU } else { angle }
+}
+
+// a unit quaternion as the index of its largest component and the other
+// three; the largest follows from them, and flipping the sign so it's
+// positive gives the same rotation
+fn rotation_steps(rotation: Quat) -> (u32, [i32; 3]) {
+    let components = rotation.normalize().to_array();
+    let largest = (0..4)
+        .max_by(|&a, &b| components[a].abs().total_cmp(&components[b].abs()))
+        .unwrap_or(3);
+    let sign = components[largest].signum();
+    let mut steps = [0; 3];
+    for (step, i) in steps.iter_mut().zip((0..4).filter(|&i| i != largest)) {
+        *step = quantize(components[i] * sign, ROTATION_SCALE, ROTATION_BITS);
+    }
+    (largest as u32, steps)
+}
+
+fn rotation_from_steps((largest, steps): (u32, [i32; 3])) -> Quat {
+    let others = steps.map(|step| dequantize(step, ROTATION_SCALE));
+    let mut components = [0.; 4];
+    for (&value, i) in others.iter().zip((0..4).filter(|&i| i != largest as usize)) {
+        components[i] = value;
+    }
+    let rest: f32 = others.iter().map(|x| x * x).sum();
+    components[largest as usize] = (1. - rest).max(0.).sqrt();
+    Quat::from_array(components)
+}
@@ -0,0 +1,45 @@
+pub const DEFAULT_TICK_RATE: u32 = 60;
+
+// never simulate more than this much time in one frame, otherwise a long
+// stall (window drag, breakpoint) makes every following frame slower still
+const MAX_FRAME_TIME: f32 = 0.25;
+
+/// Accumulates real frame time and hands it out as fixed-size simulation
+/// ticks, so physics runs at `tick_rate` regardless of the display refresh.
+pub struct FixedTimestep {
+    tick_rate: u32,
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: u32) -> FixedTimestep {
+        FixedTimestep {
+            tick_rate: tick_rate.max(1),
+            accumulator: 0.,
+        }
+    }
+
+    /// Length of one tick in seconds.
+    pub fn dt(&self) -> f32 {
+        1. / self.tick_rate as f32
+    }
+
+    /// Adds `frame_time` seconds and returns how many ticks should be run.
+    pub fn advance(&mut self, frame_time: f32) -> u32 {
+        let dt = self.dt();
+        self.accumulator += frame_time.clamp(0., MAX_FRAME_TIME);
+
+        let mut ticks = 0;
+        while self.accumulator >= dt {
+            self.accumulator -= dt;
+            ticks += 1;
+        }
+        ticks
+    }
+
+    /// How far the leftover time is into the next tick, in `[0, 1)`. Used to
+    /// interpolate rendered positions between the last two ticks.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.dt()
+    }
+}
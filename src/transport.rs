@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+use crate::protocol::MAX_PACKET_SIZE;
+
+/// Sends and receives datagrams. Delivery is best effort: packets may be
+/// lost, duplicated or reordered, and [`crate::connection::Connection`]
+/// copes with that on top.
+pub trait Transport {
+    fn send(&mut self, to: SocketAddr, packet: &[u8]) -> io::Result<()>;
+    /// Next datagram waiting, with its sender, or None if there isn't one.
+    /// Never blocks.
+    fn receive(&mut self) -> io::Result<Option<(SocketAddr, Vec<u8>)>>;
+    fn local_addr(&self) -> SocketAddr;
+}
+
+/// A non-blocking UDP socket.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl UdpTransport {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<UdpTransport> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpTransport {
+            socket,
+            // room for oversized datagrams, so they are read whole and
+            // rejected rather than cut short
+            buffer: vec![0; 2 * MAX_PACKET_SIZE],
+        })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, to: SocketAddr, packet: &[u8]) -> io::Result<()> {
+        match self.socket.send_to(packet, to) {
+            Ok(_) => Ok(()),
+            // a full send buffer is just another lost packet
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn receive(&mut self) -> io::Result<Option<(SocketAddr, Vec<u8>)>> {
+        loop {
+            match self.socket.recv_from(&mut self.buffer) {
+                Ok((len, from)) => return Ok(Some((from, self.buffer[..len].to_vec()))),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                // some platforms report an earlier send to a closed port on
+                // the next receive; that says nothing about this socket
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.socket
+            .local_addr()
+            .unwrap_or_else(|_| SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+    }
+}
+
+/// How a [`LoopbackNetwork`] mistreats packets, to see how the game holds
+/// up on a bad connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkConditions {
+    /// Seconds each packet takes to arrive.
+    pub latency: f32,
+    /// Up to this many more seconds, picked at random per packet, so packets
+    /// also arrive out of order.
+    pub jitter: f32,
+    /// Fraction of packets that never arrive.
+    pub loss: f32,
+}
+
+struct Queued {
+    deliver_at: f64,
+    from: SocketAddr,
+    packet: Vec<u8>,
+}
+
+struct Network {
+    inboxes: HashMap<SocketAddr, Vec<Queued>>,
+    next_port: u16,
+    conditions: LinkConditions,
+    time: f64,
+    // xorshift state, seeded the same every run so tests are repeatable
+    rng: u64,
+}
+
+impl Network {
+    // uniform in [0, 1)
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// An in-memory network for running servers and clients in one process,
+/// e.g. in tests. Each [`LoopbackNetwork::endpoint`] gets its own address;
+/// packets sent to it are queued until it receives them, and packets to
+/// addresses nobody has are dropped.
+///
+/// Packets are delivered at once unless [`LinkConditions`] say otherwise,
+/// in which case the network keeps its own clock, moved on with
+/// [`LoopbackNetwork::advance`].
+#[derive(Clone)]
+pub struct LoopbackNetwork {
+    network: Arc<Mutex<Network>>,
+}
+
+pub struct LoopbackTransport {
+    addr: SocketAddr,
+    network: LoopbackNetwork,
+}
+
+impl LoopbackNetwork {
+    pub fn new() -> LoopbackNetwork {
+        LoopbackNetwork {
+            network: Arc::new(Mutex::new(Network {
+                inboxes: HashMap::new(),
+                next_port: 1,
+                conditions: LinkConditions::default(),
+                time: 0.,
+                rng: 0x9e37_79b9_7f4a_7c15,
+            })),
+        }
+    }
+
+    /// A transport with a fresh address on this network.
+    pub fn endpoint(&self) -> LoopbackTransport {
+        let mut network = self.network.lock().unwrap();
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, network.next_port));
+        network.next_port += 1;
+        network.inboxes.insert(addr, Vec::new());
+        LoopbackTransport {
+            addr,
+            network: self.clone(),
+        }
+    }
+
+    /// Applies to every packet sent from now on, in either direction.
+    pub fn set_conditions(&self, conditions: LinkConditions) {
+        self.network.lock().unwrap().conditions = conditions;
+    }
+
+    /// Moves the network's clock on by `dt` seconds, letting packets whose
+    /// time has come arrive.
+    pub fn advance(&self, dt: f32) {
+        self.network.lock().unwrap().time += dt as f64;
+    }
+}
+
+impl Default for LoopbackNetwork {
+    fn default() -> LoopbackNetwork {
+        LoopbackNetwork::new()
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&mut self, to: SocketAddr, packet: &[u8]) -> io::Result<()> {
+        let mut network = self.network.network.lock().unwrap();
+        let conditions = network.conditions;
+        if conditions.loss > 0. && network.random() < conditions.loss {
+            return Ok(());
+        }
+        let delay = conditions.latency + conditions.jitter * network.random();
+        let deliver_at = network.time + delay as f64;
+        if let Some(inbox) = network.inboxes.get_mut(&to) {
+            inbox.push(Queued {
+                deliver_at,
+                from: self.addr,
+                packet: packet.to_vec(),
+            });
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<(SocketAddr, Vec<u8>)>> {
+        let mut network = self.network.network.lock().unwrap();
+        let time = network.time;
+        let Some(inbox) = network.inboxes.get_mut(&self.addr) else {
+            return Ok(None);
+        };
+        // the first to arrive, and of those arriving together the first sent
+        let next = inbox
+            .iter()
+            .enumerate()
+            .filter(|(_, queued)| queued.deliver_at <= time)
+            .min_by(|(_, a), (_, b)| a.deliver_at.total_cmp(&b.deliver_at))
+            .map(|(index, _)| index);
+        Ok(next.map(|index| {
+            let queued = inbox.remove(index);
+            (queued.from, queued.packet)
+        }))
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for LoopbackTransport {
+    fn drop(&mut self) {
+        if let Ok(mut network) = self.network.network.lock() {
+            network.inboxes.remove(&self.addr);
+        }
+    }
+}
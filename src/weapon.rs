@@ -0,0 +1,349 @@
+use std::f32::consts::PI;
+
+use macroquad::prelude::*;
+
+use crate::game::{RayHit, State};
+use crate::health::{Damage, DamageKind};
+use crate::input::InputCommand;
+use crate::player::{Player, look_direction};
+use crate::projectile::{Projectile, ProjectileConfig};
+
+/// Tuning of a weapon. Angles are in radians, times in seconds.
+#[derive(Clone, Debug)]
+pub struct WeaponConfig {
+    /// Time between shots.
+    pub fire_interval: f32,
+    /// Keeps firing while the trigger is held, otherwise fires once per press.
+    pub automatic: bool,
+    /// Rays or projectiles per shot, more than one for shotguns.
+    pub pellets: u32,
+    /// Largest angle a ray strays from where the player aims.
+    pub spread: f32,
+    /// Extra spread while in the air, on top of `spread`.
+    pub air_spread: f32,
+    /// Upward kick of the aim per shot.
+    pub recoil: f32,
+    /// Largest sideways kick per shot, to either side.
+    pub recoil_yaw: f32,
+    /// How fast the kick wears off, in radians per second.
+    pub recoil_recovery: f32,
+    pub damage: f32,
+    /// Push each ray gives a rigid body it hits.
+    pub impulse: f32,
+    pub range: f32,
+    pub magazine_size: u32,
+    /// Ammo carried besides the magazine, at the start.
+    pub reserve_ammo: u32,
+    pub reload_time: f32,
+    /// Launches projectiles instead of casting rays. `damage` and `range`
+    /// then go unused, the projectile has its own.
+    pub projectile: Option<ProjectileConfig>,
+}
+
+impl WeaponConfig {
+    pub fn rocket_launcher() -> WeaponConfig {
+        WeaponConfig {
+            fire_interval: 0.8,
+            automatic: true,
+            spread: 0.,
+            air_spread: 0.,
+            recoil: 0.03,
+            recoil_yaw: 0.,
+            recoil_recovery: 0.2,
+            magazine_size: 4,
+            reserve_ammo: 20,
+            reload_time: 2.,
+            projectile: Some(ProjectileConfig::rocket()),
+            ..WeaponConfig::default()
+        }
+    }
+
+    pub fn grenade_launcher() -> WeaponConfig {
+        WeaponConfig {
+            fire_interval: 0.6,
+            automatic: false,
+            spread: 0.,
+            air_spread: 0.,
+            recoil: 0.02,
+            recoil_yaw: 0.,
+            recoil_recovery: 0.2,
+            magazine_size: 6,
+            reserve_ammo: 24,
+            reload_time: 2.5,
+            projectile: Some(ProjectileConfig::grenade()),
+            ..WeaponConfig::default()
+        }
+    }
+}
+
+impl Default for WeaponConfig {
+    /// An automatic rifle.
+    fn default() -> WeaponConfig {
+        WeaponConfig {
+            fire_interval: 0.1,
+            automatic: true,
+            pellets: 1,
+            spread: 0.004,
+            air_spread: 0.03,
+            recoil: 0.012,
+            recoil_yaw: 0.004,
+            recoil_recovery: 0.15,
+            damage: 20.,
+            impulse: 8.,
+            range: 200.,
+            magazine_size: 30,
+            reserve_ammo: 90,
+            reload_time: 1.5,
+            projectile: None,
+        }
+    }
+}
+
+/// One ray of a shot.
+#[derive(Clone, Copy, Debug)]
+pub struct Shot {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub hit: Option<RayHit>,
+    /// What the ray does to a player it hits.
+    pub damage: Damage,
+    /// Push it gives a rigid body it hits.
+    pub impulse: Vec3,
+    /// Id of the player it hit, if it hit one before any object. `hit` is
+    /// None then.
+    pub hit_player: Option<u32>,
+}
+
+/// A weapon held by a player. Stepped with the simulation's fixed
+/// ticks; spread is drawn from the weapon's own seeded generator, so the
+/// same commands always give the same shots.
+pub struct Weapon {
+    pub config: WeaponConfig,
+    ammo: u32,
+    reserve: u32,
+    // time until the next shot may fire
+    cooldown: f32,
+    // time left of a reload in progress
+    reloading: Option<f32>,
+    // current kick as (pitch, yaw)
+    recoil: Vec2,
+    trigger_held: bool,
+    rng: u32,
+}
+
+impl Weapon {
+    pub fn new(config: WeaponConfig) -> Weapon {
+        Weapon {
+            ammo: config.magazine_size,
+            reserve: config.reserve_ammo,
+            config,
+            cooldown: 0.,
+            reloading: None,
+            recoil: Vec2::ZERO,
+            trigger_held: false,
+            rng: 0x9e37_79b9,
+        }
+    }
+
+    /// Reseeds the spread and recoil pattern. Weapons seeded differently,
+    /// e.g. by their owner's id, don't scatter in lockstep.
+    pub fn seed(&mut self, seed: u32) {
+        // xorshift never leaves zero
+        self.rng = (0x9e37_79b9 ^ seed.wrapping_mul(0x85eb_ca6b)).max(1);
+    }
+
+    /// Rounds left in the magazine.
+    pub fn ammo(&self) -> u32 {
+        self.ammo
+    }
+
+    pub fn reserve(&self) -> u32 {
+        self.reserve
+    }
+
+    pub fn is_reloading(&self) -> bool {
+        self.reloading.is_some()
+    }
+
+    /// Current recoil kick as (pitch, yaw), to add to the view angles.
+    pub fn recoil(&self) -> Vec2 {
+        self.recoil
+    }
+
+    /// Advances the weapon by one tick, firing from the eye of `player` if
+    /// `cmd` asks to and the weapon is ready. Returns the rays fired, empty
+    /// when it didn't fire; projectile weapons add their projectiles to
+    /// `state` instead.
+    pub fn update(
+        &mut self,
+        state: &mut State,
+        cmd: &InputCommand,
+        player: &Player,
+        dt: f32,
+    ) -> Vec<Shot> {
+        self.cooldown -= dt;
+        self.recoil = self.recoil.move_towards(Vec2::ZERO, self.config.recoil_recovery * dt);
+
+        if let Some(remaining) = self.reloading {
+            if remaining > dt {
+                self.reloading = Some(remaining - dt);
+            } else {
+                let loaded = (self.config.magazine_size - self.ammo).min(self.reserve);
+                self.ammo += loaded;
+                self.reserve -= loaded;
+                self.reloading = None;
+            }
+        }
+
+        let pressed = cmd.fire && !self.trigger_held;
+        self.trigger_held = cmd.fire;
+        let wants_reload = cmd.reload || (cmd.fire && self.ammo == 0);
+        if wants_reload {
+            self.start_reload();
+        }
+
+        let trigger = if self.config.automatic { cmd.fire } else { pressed };
+        if !trigger || self.cooldown > 0. || self.reloading.is_some() || self.ammo == 0 {
+            // time left over from the last tick only counts while firing
+            // continuously, so an idle weapon doesn't bank shots
+            self.cooldown = self.cooldown.max(0.);
+            return Vec::new();
+        }
+        self.ammo -= 1;
+        self.cooldown += self.config.fire_interval;
+
+        // aim where the player looks, thrown off by the kick so far
+        let theta = player.theta + self.recoil.y;
+        let phi = (player.phi + self.recoil.x).clamp(-PI / 2. + 0.001, PI / 2. - 0.001);
+        let aim = look_direction(theta, phi);
+        let mut spread = self.config.spread;
+        if !player.is_on_ground() {
+            spread += self.config.air_spread;
+        }
+
+        let origin = player.eye();
+        let mut shots = Vec::new();
+        for _ in 0..self.config.pellets.max(1) {
+            let direction = self.scatter(aim, spread);
+            match self.config.projectile {
+                Some(projectile) => state.projectiles.push(Projectile {
+                    owner: Some(player.id),
+                    ..Projectile::new(projectile, origin, direction)
+                }),
+                None => shots.push(Shot {
+                    origin,
+                    direction,
+                    hit: state.cast_ray(origin, direction, self.config.range),
+                    damage: Damage {
+                        amount: self.config.damage,
+                        kind: DamageKind::Bullet,
+                        source: Some(player.id),
+                    },
+                    impulse: direction * self.config.impulse,
+                    hit_player: None,
+                }),
+            }
+        }
+
+        let side = self.random() * 2. - 1.;
+        self.recoil += vec2(self.config.recoil, side * self.config.recoil_yaw);
+        shots
+    }
+
+    /// Starts reloading, unless the magazine is full, there is no ammo left
+    /// to load or a reload is already under way.
+    pub fn start_reload(&mut self) {
+        if self.reloading.is_none() && self.ammo < self.config.magazine_size && self.reserve > 0 {
+            self.reloading = Some(self.config.reload_time);
+        }
+    }
+
+    // random direction within `spread` of `aim`, evenly over the cone's cap
+    fn scatter(&mut self, aim: Vec3, spread: f32) -> Vec3 {
+        if spread <= 0. {
+            return aim;
+        }
+        let cos = 1. - self.random() * (1. - spread.cos());
+        let sin = (1. - cos * cos).sqrt();
+        let angle = self.random() * 2. * PI;
+        let (x, y) = aim.any_orthonormal_pair();
+        (aim * cos + (x * angle.cos() + y * angle.sin()) * sin).normalize()
+    }
+
+    // xorshift, uniform in 0..1
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+impl Default for Weapon {
+    fn default() -> Weapon {
+        Weapon::new(WeaponConfig::default())
+    }
+}
+
+/// The weapons a player carries and which of them is in hand.
+pub struct Loadout {
+    pub weapons: Vec<Weapon>,
+    current: usize,
+}
+
+impl Loadout {
+    pub fn new(weapons: Vec<Weapon>) -> Loadout {
+        Loadout {
+            weapons,
+            current: 0,
+        }
+    }
+
+    /// The default loadout, with every weapon seeded by the `id` of the
+    /// player carrying it.
+    pub fn for_player(id: u32) -> Loadout {
+        let mut loadout = Loadout::default();
+        for weapon in &mut loadout.weapons {
+            weapon.seed(id);
+        }
+        loadout
+    }
+
+    /// Index of the weapon in hand.
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    pub fn current(&self) -> Option<&Weapon> {
+        self.weapons.get(self.current)
+    }
+
+    /// Advances the weapon in hand by one tick, after switching to the next
+    /// one if `cmd` asks to. See [`Weapon::update`].
+    pub fn update(
+        &mut self,
+        state: &mut State,
+        cmd: &InputCommand,
+        player: &Player,
+        dt: f32,
+    ) -> Vec<Shot> {
+        if self.weapons.is_empty() {
+            return Vec::new();
+        }
+        if cmd.next_weapon {
+            self.current = (self.current + 1) % self.weapons.len();
+        }
+        self.weapons[self.current].update(state, cmd, player, dt)
+    }
+}
+
+impl Default for Loadout {
+    /// A rifle, a rocket launcher and a grenade launcher.
+    fn default() -> Loadout {
+        Loadout::new(vec![
+            Weapon::default(),
+            Weapon::new(WeaponConfig::rocket_launcher()),
+            Weapon::new(WeaponConfig::grenade_launcher()),
+        ])
+    }
+}
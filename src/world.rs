@@ -0,0 +1,198 @@
+use crate::game::State;
+use crate::input::InputCommand;
+use crate::player::Player;
+use crate::projectile::Explosion;
+use crate::rewind::{Hitbox, HitboxHistory};
+use crate::weapon::{Loadout, Shot};
+
+/// A player in the world with the weapons they carry.
+pub struct Avatar {
+    pub player: Player,
+    pub loadout: Loadout,
+    /// Tick of the world the player was looking at when they gave their
+    /// current command, if it lags behind. Their shots are judged against
+    /// where others were then, as far back as `World::history` goes.
+    pub view_tick: Option<u32>,
+}
+
+/// What happened during a [`World::tick`], for effects like decals.
+#[derive(Default)]
+pub struct TickEvents {
+    /// Rays fired, with the id of the player who fired each.
+    pub shots: Vec<(u32, Shot)>,
+    pub explosions: Vec<Explosion>,
+}
+
+/// The level together with everyone playing in it, stepped one fixed tick
+/// at a time. The same simulation runs in single player, on the server, and
+/// on clients predicting their own player.
+pub struct World {
+    pub state: State,
+    pub avatars: Vec<Avatar>,
+    /// Ticks simulated so far.
+    pub tick: u32,
+    /// Recent hitboxes, for judging shots from players who see the world
+    /// late. Keeps nothing unless made with a max rewind.
+    pub history: HitboxHistory,
+    next_id: u32,
+}
+
+impl World {
+    pub fn new(state: State) -> World {
+        World {
+            state,
+            avatars: Vec::new(),
+            tick: 0,
+            history: HitboxHistory::new(0),
+            next_id: 0,
+        }
+    }
+
+    /// Adds a player at the level's spawn and returns their id.
+    pub fn add_player(&mut self) -> u32 {
+        let id = self.next_id;
+        self.insert_player(id);
+        id
+    }
+
+    /// Adds a player with a given id, e.g. one the server picked. Replaces
+    /// any player that already has it.
+    pub fn insert_player(&mut self, id: u32) -> &mut Avatar {
+        self.remove_player(id);
+        self.next_id = self.next_id.max(id + 1);
+        let mut player = Player::new(&self.state.spawn);
+        player.id = id;
+        self.avatars.push(Avatar {
+            player,
+            loadout: Loadout::for_player(id),
+            view_tick: None,
+        });
+        self.avatars.last_mut().unwrap()
+    }
+
+    /// Returns whether there was a player with that id.
+    pub fn remove_player(&mut self, id: u32) -> bool {
+        let count = self.avatars.len();
+        self.avatars.retain(|avatar| avatar.player.id != id);
+        self.avatars.len() != count
+    }
+
+    pub fn avatar(&self, id: u32) -> Option<&Avatar> {
+        self.avatars.iter().find(|avatar| avatar.player.id == id)
+    }
+
+    pub fn avatar_mut(&mut self, id: u32) -> Option<&mut Avatar> {
+        self.avatars.iter_mut().find(|avatar| avatar.player.id == id)
+    }
+
+    /// Advances everything by one tick of `dt` seconds. Each player follows
+    /// their command in `commands`; players without one stand still.
+    pub fn tick(&mut self, commands: &[(u32, InputCommand)], dt: f32) -> TickEvents {
+        let mut events = TickEvents::default();
+        let commands: Vec<InputCommand> = self
+            .avatars
+            .iter()
+            .map(|avatar| {
+                let id = avatar.player.id;
+                commands
+                    .iter()
+                    .find(|(player, _)| *player == id)
+                    .map_or_else(|| idle(&avatar.player), |(_, cmd)| *cmd)
+            })
+            .collect();
+
+        for (avatar, cmd) in self.avatars.iter_mut().zip(commands.iter()) {
+            let was_dead = avatar.player.is_dead();
+            avatar.player.update(&self.state, cmd, dt);
+            if was_dead && !avatar.player.is_dead() {
+                // respawned, with a fresh set of weapons
+                avatar.loadout = Loadout::for_player(avatar.player.id);
+            }
+            avatar.player.push_bodies(&mut self.state);
+        }
+
+        for (index, cmd) in commands.iter().enumerate() {
+            let Avatar {
+                player,
+                loadout,
+                view_tick,
+            } = &mut self.avatars[index];
+            if player.is_dead() {
+                continue;
+            }
+            let id = player.id;
+            let shots = loadout.update(&mut self.state, cmd, player, dt);
+            if shots.is_empty() {
+                continue;
+            }
+            // others as the shooter saw them, or as they are now
+            let rewound = view_tick
+                .filter(|tick| *tick < self.tick)
+                .and_then(|tick| self.history.at(tick));
+            let targets: Vec<Hitbox> = match rewound {
+                Some(hitboxes) => hitboxes.iter().filter(|hitbox| hitbox.id != id).copied().collect(),
+                None => self
+                    .avatars
+                    .iter()
+                    .filter(|avatar| avatar.player.id != id && !avatar.player.is_dead())
+                    .map(|avatar| avatar.player.hitbox())
+                    .collect(),
+            };
+            for mut shot in shots {
+                let max_distance = shot.hit.map_or(f32::MAX, |hit| hit.distance);
+                let target = targets
+                    .iter()
+                    .filter_map(|hitbox| {
+                        let distance = hitbox.cast_ray(shot.origin, shot.direction, max_distance)?;
+                        Some((hitbox.id, distance))
+                    })
+                    .min_by(|a, b| a.1.total_cmp(&b.1));
+                if let Some((target, _)) = target {
+                    shot.hit = None;
+                    shot.hit_player = Some(target);
+                    if let Some(avatar) = self.avatar_mut(target) {
+                        avatar.player.take_damage(shot.damage);
+                    }
+                } else if let Some(hit) = shot.hit {
+                    self.state.apply_impulse(hit.object, hit.point, shot.impulse);
+                }
+                events.shots.push((id, shot));
+            }
+        }
+
+        let players: Vec<Hitbox> = self
+            .avatars
+            .iter()
+            .filter(|avatar| !avatar.player.is_dead())
+            .map(|avatar| avatar.player.hitbox())
+            .collect();
+        for explosion in self.state.update_projectiles(&players, dt) {
+            for avatar in self.avatars.iter_mut() {
+                avatar.player.apply_explosion(&explosion);
+            }
+            self.state.apply_explosion(&explosion);
+            events.explosions.push(explosion);
+        }
+        self.state.update_bodies(dt);
+        self.tick += 1;
+        if self.history.max_rewind() > 0 {
+            let hitboxes = self
+                .avatars
+                .iter()
+                .filter(|avatar| !avatar.player.is_dead())
+                .map(|avatar| avatar.player.hitbox())
+                .collect();
+            self.history.record(self.tick, hitboxes);
+        }
+        events
+    }
+}
+
+// a command that keeps a player looking where they were and does nothing
+fn idle(player: &Player) -> InputCommand {
+    InputCommand {
+        theta: player.theta,
+        phi: player.phi,
+        ..Default::default()
+    }
+}
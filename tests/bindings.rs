@@ -0,0 +1,29 @@
+// Bindings files: every macroquad key can be bound by name, and a name that
+// isn't one is an error pointing at it.
+
+use macroquad::prelude::*;
+
+use fps_engine::bindings::{Action, Binding, Bindings, KeyName};
+
+#[test]
+fn function_keys_and_the_numpad_can_be_bound() {
+    let source = r#"Bindings(actions: { Reload: [Key("F5"), Key("Kp0"), Key("PageUp")] })"#;
+    let bindings = Bindings::parse("input.ron", source).unwrap();
+    assert_eq!(
+        bindings.get(Action::Reload),
+        [
+            Binding::Key(KeyName(KeyCode::F5)),
+            Binding::Key(KeyName(KeyCode::Kp0)),
+            Binding::Key(KeyName(KeyCode::PageUp)),
+        ]
+    );
+    assert!(bindings.get(Action::Fire).is_empty());
+}
+
+#[test]
+fn an_unknown_key_is_an_error_at_its_name() {
+    let source = "Bindings(\n    actions: { Jump: [Key(\"Spacebar\")] },\n)";
+    let error = Bindings::parse("input.ron", source).err().unwrap().to_string();
+    assert!(error.starts_with("input.ron:2:"), "{}", error);
+    assert!(error.contains("unknown key: Spacebar"), "{}", error);
+}
@@ -0,0 +1,140 @@
+// A server and its clients talking over the in-memory loopback network, all
+// stepped by hand in one thread.
+
+#![allow(dead_code)]
+
+use macroquad::prelude::*;
+
+use fps_engine::client::{Client, ClientEvent};
+use fps_engine::game::{Spawn, State};
+use fps_engine::input::InputCommand;
+use fps_engine::level::Level;
+use fps_engine::objects::{CollisionBox, Transform};
+use fps_engine::server::{Server, ServerConfig, ServerEvent};
+use fps_engine::transport::{LinkConditions, LoopbackNetwork, LoopbackTransport};
+
+pub const DT: f32 = 1. / 60.;
+pub const LEVEL: &str = "levels/test.ron";
+
+pub fn test_level() -> State {
+    Level::from_file(LEVEL).unwrap().build_state().unwrap()
+}
+
+// a wide floor with the spawn in the middle, looking down +x, so there is
+// nothing to run into. much wider and shape casts against it lose enough
+// precision for a hard landing to fall through
+pub fn flat_level() -> State {
+    let mut state = State::new();
+    state.add_object(Box::new(CollisionBox::new(
+        Transform::from_position(vec3(0., -0.5, 0.)),
+        vec3(50., 0.5, 50.),
+    )));
+    state.spawn = Spawn {
+        position: Vec3::ZERO,
+        theta: 0.,
+        phi: 0.,
+    };
+    state
+}
+
+pub struct Game {
+    pub network: LoopbackNetwork,
+    pub server: Server<LoopbackTransport>,
+    pub clients: Vec<Client<LoopbackTransport>>,
+    // everything each client has been told, oldest first
+    pub events: Vec<Vec<ClientEvent>>,
+    pub server_events: Vec<ServerEvent>,
+    // what clients attach when told to load a level
+    pub level: fn() -> State,
+}
+
+impl Game {
+    pub fn new(config: ServerConfig) -> Game {
+        Game::with_level(test_level, config)
+    }
+
+    // clients are given `level` to attach when they connect
+    pub fn with_level(level: fn() -> State, config: ServerConfig) -> Game {
+        let network = LoopbackNetwork::new();
+        let config = ServerConfig {
+            level: LEVEL.to_string(),
+            ..config
+        };
+        let server = Server::new(network.endpoint(), level(), config);
+        Game {
+            network,
+            server,
+            clients: Vec::new(),
+            events: Vec::new(),
+            server_events: Vec::new(),
+            level,
+        }
+    }
+
+    pub fn set_conditions(&self, conditions: LinkConditions) {
+        self.network.set_conditions(conditions);
+    }
+
+    pub fn connect(&mut self, name: &str) -> usize {
+        let client = Client::connect(self.network.endpoint(), self.server.local_addr(), name);
+        self.clients.push(client);
+        self.events.push(Vec::new());
+        self.clients.len() - 1
+    }
+
+    // one frame: each client sends its command, then everyone handles what
+    // arrived
+    pub fn step(&mut self, commands: impl Fn(usize) -> Option<InputCommand>) {
+        self.network.advance(DT);
+        for (i, client) in self.clients.iter_mut().enumerate() {
+            if let Some(cmd) = commands(i) {
+                client.send_command(cmd).unwrap();
+            }
+        }
+        self.server.update(DT).unwrap();
+        self.server_events.extend(self.server.take_events());
+        for (i, client) in self.clients.iter_mut().enumerate() {
+            client.update(DT).unwrap();
+            for event in client.take_events() {
+                if let ClientEvent::Connected { .. } | ClientEvent::LevelChanged { .. } = &event {
+                    client.attach((self.level)());
+                }
+                self.events[i].push(event);
+            }
+        }
+    }
+
+    pub fn run(&mut self, frames: u32) {
+        for _ in 0..frames {
+            self.step(|_| Some(InputCommand::default()));
+        }
+    }
+
+    // frames until every client has joined and has a snapshot
+    pub fn wait_for_snapshots(&mut self) {
+        for _ in 0..600 {
+            if self.clients.iter().all(|client| {
+                client
+                    .player_id()
+                    .and_then(|id| client.world()?.avatar(id))
+                    .is_some()
+            }) {
+                return;
+            }
+            self.run(1);
+        }
+        panic!("clients never got a snapshot");
+    }
+
+    pub fn server_position(&self, client: usize) -> Vec3 {
+        let id = self.clients[client].player_id().unwrap();
+        self.server.world.avatar(id).unwrap().player.position
+    }
+
+    // where the client thinks its own player is
+    pub fn predicted_position(&self, client: usize) -> Vec3 {
+        let client = &self.clients[client];
+        let id = client.player_id().unwrap();
+        client.world().unwrap().avatar(id).unwrap().player.position
+    }
+}
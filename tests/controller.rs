@@ -0,0 +1,105 @@
+// The character controller on its own: walking up steps it can take,
+// stopping at ones it can't, sliding off steep slopes and staying on the
+// ground over small drops.
+
+mod common;
+
+use macroquad::prelude::*;
+
+use fps_engine::controller::{CharacterController, ControllerConfig};
+use fps_engine::game::State;
+use fps_engine::objects::{CollisionBox, Transform};
+
+use common::{DT, flat_level};
+
+const SPEED: f32 = 5.;
+const GRAVITY: f32 = 28.8;
+
+fn add_box(state: &mut State, transform: Transform, half_extents: Vec3) {
+    state.add_object(Box::new(CollisionBox::new(transform, half_extents)));
+}
+
+// a ledge `height` tall across the way, from x = 1 on
+fn step_of(height: f32) -> State {
+    let mut state = flat_level();
+    add_box(
+        &mut state,
+        Transform::from_position(vec3(5., height / 2., 0.)),
+        vec3(4., height / 2., 10.),
+    );
+    state
+}
+
+// moves along `direction` at walking speed for `ticks` ticks, falling when
+// not on the ground; returns the position and whether it was grounded
+// after each tick
+fn walk(state: &State, start: Vec3, direction: Vec3, ticks: u32) -> Vec<(Vec3, bool)> {
+    let controller = CharacterController::new(ControllerConfig::default());
+    let mut position = start;
+    let mut grounded = true;
+    let mut fall_speed = 0.;
+    (0..ticks)
+        .map(|_| {
+            let translation = direction * SPEED * DT - vec3(0., fall_speed * DT, 0.);
+            let result = controller.move_character(state, position, translation, grounded);
+            position = result.position;
+            grounded = result.grounded;
+            fall_speed = if grounded { 0. } else { fall_speed + GRAVITY * DT };
+            (position, grounded)
+        })
+        .collect()
+}
+
+#[test]
+fn walks_up_a_step_lower_than_the_step_height() {
+    let path = walk(&step_of(0.3), Vec3::ZERO, Vec3::X, 60);
+    let (end, grounded) = *path.last().unwrap();
+    assert!(end.x > 3., "stopped at {}", end);
+    assert!((end.y - 0.3).abs() < 0.02 && grounded, "ended at {}", end);
+}
+
+#[test]
+fn stops_at_a_step_higher_than_the_step_height() {
+    let path = walk(&step_of(0.5), Vec3::ZERO, Vec3::X, 60);
+    let (end, grounded) = *path.last().unwrap();
+    let radius = ControllerConfig::default().radius;
+    assert!(end.x < 1. - radius + 0.01, "went through to {}", end);
+    assert!(end.y.abs() < 0.02 && grounded, "ended at {}", end);
+}
+
+// a plane through the origin rising towards +x at `degrees`
+fn slope(degrees: f32) -> State {
+    let mut state = State::new();
+    let rotation = Quat::from_rotation_z(degrees.to_radians());
+    add_box(&mut state, Transform::new(rotation * vec3(0., -0.1, 0.), rotation), vec3(10., 0.1, 10.));
+    state
+}
+
+#[test]
+fn slides_down_a_slope_steeper_than_the_max_slope() {
+    // dropped onto it, standing still
+    let path = walk(&slope(60.), vec3(0., 0.5, 0.), Vec3::ZERO, 60);
+    let (end, _) = *path.last().unwrap();
+    assert!(end.x < -0.5 && end.y < -0.5, "stayed at {}", end);
+    assert!(path.iter().all(|(_, grounded)| !grounded), "stood on it");
+
+    // while a gentle one holds
+    let path = walk(&slope(30.), vec3(0., 0.5, 0.), Vec3::ZERO, 60);
+    let (end, grounded) = *path.last().unwrap();
+    assert!(grounded && end.xz().length() < 0.1, "slid to {}", end);
+}
+
+#[test]
+fn snaps_down_a_small_ledge_instead_of_falling() {
+    let mut state = flat_level();
+    add_box(
+        &mut state,
+        Transform::from_position(vec3(0., 0.125, 0.)),
+        vec3(2., 0.125, 10.),
+    );
+    // off the top of a 0.25 ledge at x = 2 and on along the floor
+    let path = walk(&state, vec3(0., 0.25, 0.), Vec3::X, 40);
+    assert!(path.iter().all(|(_, grounded)| *grounded), "left the ground");
+    let (end, _) = *path.last().unwrap();
+    assert!(end.x > 2.5 && end.y.abs() < 0.02, "ended at {}", end);
+}
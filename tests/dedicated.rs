@@ -0,0 +1,171 @@
+// What the dedicated server binary is built from: its settings file, match
+// rules, map rotation, and level changes and shutdown on a running server.
+
+mod common;
+
+use macroquad::prelude::*;
+
+use fps_engine::client::ClientEvent;
+use fps_engine::dedicated::{
+    DEFAULT_SERVER_CONFIG_PATH, GameMode, Match, MapRotation, ServerSettings, SettingsError,
+};
+use fps_engine::health::{Damage, DamageKind};
+use fps_engine::input::InputCommand;
+use fps_engine::server::{LeaveReason, ServerConfig, ServerEvent};
+
+use common::{Game, flat_level};
+
+fn died(id: u32, source: Option<u32>) -> ServerEvent {
+    ServerEvent::Died {
+        id,
+        damage: Damage {
+            amount: 100.,
+            kind: DamageKind::Bullet,
+            source,
+        },
+    }
+}
+
+#[test]
+fn the_shipped_settings_parse() {
+    let settings = ServerSettings::from_file(DEFAULT_SERVER_CONFIG_PATH).unwrap();
+    assert!(!settings.maps.is_empty());
+    let config = settings.server_config(&settings.maps[0]);
+    assert_eq!(config.level, settings.maps[0]);
+    assert_eq!(config.tick_rate, settings.tick_rate);
+}
+
+#[test]
+fn settings_left_out_keep_their_defaults() {
+    let settings = ServerSettings::parse(
+        "test",
+        r#"ServerSettings(port: 1234, maps: ["a.ron", "b.ron"], mode: Sandbox)"#,
+    )
+    .unwrap();
+    let defaults = ServerSettings::default();
+    assert_eq!(settings.port, 1234);
+    assert_eq!(settings.maps, ["a.ron", "b.ron"]);
+    assert_eq!(settings.mode, GameMode::Sandbox);
+    assert_eq!(settings.max_players, defaults.max_players);
+    assert_eq!(settings.tick_rate, defaults.tick_rate);
+
+    assert!(matches!(
+        ServerSettings::parse("test", "ServerSettings(maps: [])"),
+        Err(SettingsError::NoMaps { .. })
+    ));
+    assert!(matches!(
+        ServerSettings::parse("test", "ServerSettings(mpas: [])"),
+        Err(SettingsError::Parse { .. })
+    ));
+}
+
+#[test]
+fn maps_rotate_in_order_and_wrap() {
+    let mut rotation = MapRotation::new(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+    assert_eq!(rotation.current(), "a");
+    assert_eq!(rotation.advance(), "b");
+    assert_eq!(rotation.advance(), "c");
+    assert_eq!(rotation.advance(), "a");
+}
+
+#[test]
+fn deathmatch_ends_at_the_frag_limit() {
+    let mut game = Match::new(GameMode::Deathmatch {
+        frag_limit: 2,
+        time_limit: 0.,
+    });
+    game.record(&died(1, Some(0)));
+    // dying by your own hand costs a frag
+    game.record(&died(0, Some(0)));
+    game.record(&died(1, None));
+    game.record(&died(1, Some(0)));
+    assert_eq!(game.scores(), [(0, 1), (1, -1)]);
+    assert!(!game.is_over());
+
+    game.record(&died(1, Some(0)));
+    assert!(game.is_over());
+    assert_eq!(game.scores()[0], (0, 2));
+
+    // a leaver's score goes with them
+    game.record(&ServerEvent::Left {
+        id: 1,
+        name: "bob".to_string(),
+        reason: LeaveReason::Quit,
+    });
+    assert_eq!(game.scores(), [(0, 2)]);
+}
+
+#[test]
+fn deathmatch_ends_at_the_time_limit_and_sandbox_never_does() {
+    let mut game = Match::new(GameMode::Deathmatch {
+        frag_limit: 0,
+        time_limit: 60.,
+    });
+    game.advance(59.);
+    assert!(!game.is_over());
+    game.advance(1.);
+    assert!(game.is_over());
+
+    let mut sandbox = Match::new(GameMode::Sandbox);
+    sandbox.advance(1e6);
+    sandbox.record(&died(1, Some(0)));
+    assert!(!sandbox.is_over());
+}
+
+#[test]
+fn everyone_moves_to_the_new_level() {
+    let mut game = Game::new(ServerConfig::default());
+    game.connect("alice");
+    game.connect("bob");
+    game.wait_for_snapshots();
+    for _ in 0..30 {
+        game.step(|_| {
+            Some(InputCommand {
+                forward: true,
+                ..Default::default()
+            })
+        });
+    }
+    let ids: Vec<u32> = game.clients.iter().map(|client| client.player_id().unwrap()).collect();
+    let tick = game.server.world.tick;
+
+    game.level = flat_level;
+    game.server.change_level(flat_level(), "levels/flat.ron".to_string());
+    game.run(30);
+
+    assert_eq!(game.server.config.level, "levels/flat.ron");
+    assert!(game.server.world.tick > tick);
+    for (i, client) in game.clients.iter().enumerate() {
+        assert!(game.events[i].contains(&ClientEvent::LevelChanged {
+            level: "levels/flat.ron".to_string()
+        }));
+        let world = client.world().expect("attached the new level");
+        for &id in ids.iter() {
+            // back at the new level's spawn, keeping their ids
+            let server = game.server.world.avatar(id).unwrap().player.position;
+            assert!(server.distance(Vec3::ZERO) < 0.1, "{} is at {}", id, server);
+            let seen = world.avatar(id).unwrap().player.position;
+            assert!(seen.distance(server) < 0.01);
+        }
+    }
+
+    // newcomers are sent to the new level
+    let carol = game.connect("carol");
+    game.wait_for_snapshots();
+    assert!(game.events[carol].iter().any(
+        |event| matches!(event, ClientEvent::Connected { level, .. } if level == "levels/flat.ron")
+    ));
+}
+
+#[test]
+fn clients_hear_the_server_shut_down() {
+    let mut game = Game::new(ServerConfig::default());
+    game.connect("alice");
+    game.wait_for_snapshots();
+
+    game.server.shut_down("maintenance");
+    assert!(!game.server.all_acked());
+    game.run(10);
+    assert!(game.server.all_acked());
+    assert!(game.events[0].contains(&ClientEvent::ServerClosed("maintenance".to_string())));
+}
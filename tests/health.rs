@@ -0,0 +1,108 @@
+// Players hurt by falls and the kill plane, dying and coming back after the
+// respawn delay.
+
+mod common;
+
+use macroquad::prelude::*;
+
+use fps_engine::game::State;
+use fps_engine::health::DamageKind;
+use fps_engine::input::InputCommand;
+use fps_engine::player::{Player, PlayerEvent};
+
+use common::{DT, flat_level};
+
+// a floor at y = 0 with the spawn `height` above it
+fn drop_from(height: f32) -> (State, Player) {
+    let mut state = flat_level();
+    state.spawn.position = vec3(0., height, 0.);
+    let player = Player::new(&state.spawn);
+    (state, player)
+}
+
+// every event over `seconds` of standing still
+fn run(state: &State, player: &mut Player, seconds: f32) -> Vec<PlayerEvent> {
+    let mut events = Vec::new();
+    for _ in 0..(seconds / DT).round() as u32 {
+        player.update(state, &InputCommand::default(), DT);
+        events.extend(player.take_events());
+    }
+    events
+}
+
+fn fall_damage(events: &[PlayerEvent]) -> f32 {
+    events
+        .iter()
+        .map(|event| match event {
+            PlayerEvent::Damaged(damage) if damage.kind == DamageKind::Fall => damage.amount,
+            _ => 0.,
+        })
+        .sum()
+}
+
+#[test]
+fn only_long_falls_hurt() {
+    let (state, mut player) = drop_from(1.);
+    assert_eq!(fall_damage(&run(&state, &mut player, 2.)), 0.);
+
+    // landing at about 24 units per second, 10 over the safe speed
+    let (state, mut player) = drop_from(10.);
+    let damage = fall_damage(&run(&state, &mut player, 2.));
+    assert!((damage - 50.).abs() < 5., "took {}", damage);
+    assert!((player.health.health() - (100. - damage)).abs() < 0.01);
+    assert!(!player.is_dead());
+}
+
+#[test]
+fn a_long_enough_fall_kills() {
+    let (state, mut player) = drop_from(40.);
+    let events = run(&state, &mut player, 3.);
+    assert!(player.is_dead());
+    assert!(events.iter().any(|event| matches!(
+        event,
+        PlayerEvent::Died(damage) if damage.kind == DamageKind::Fall
+    )));
+}
+
+#[test]
+fn falling_below_the_kill_plane_is_fatal_with_armor_on() {
+    let (mut state, mut player) = drop_from(1.);
+    // spawn out past the edge of the floor
+    state.spawn.position = vec3(60., 1., 0.);
+    state.kill_height = -10.;
+    player.respawn(&state.spawn);
+    player.health.add_armor(100.);
+
+    let events = run(&state, &mut player, 1.);
+    assert!(player.is_dead());
+    assert!(player.position.y < -10.);
+    assert!(events.iter().any(|event| matches!(
+        event,
+        PlayerEvent::Died(damage) if damage.kind == DamageKind::KillPlane
+    )));
+}
+
+#[test]
+fn the_dead_respawn_after_the_delay() {
+    let (mut state, mut player) = drop_from(40.);
+    for _ in 0..300 {
+        if player.is_dead() {
+            break;
+        }
+        run(&state, &mut player, DT);
+    }
+    assert!(player.is_dead());
+    let body = player.position;
+    state.spawn.position = vec3(5., 0., 5.);
+
+    // lying where they fell until the delay is up
+    let delay = player.health.config.respawn_delay;
+    assert!(!run(&state, &mut player, delay - 0.1).contains(&PlayerEvent::Respawned));
+    assert!(player.is_dead());
+    assert_eq!(player.position, body);
+
+    assert!(run(&state, &mut player, 0.2).contains(&PlayerEvent::Respawned));
+    assert!(!player.is_dead());
+    assert_eq!(player.health.health(), player.health.config.max_health);
+    assert!(player.position.distance(state.spawn.position) < 0.1);
+}
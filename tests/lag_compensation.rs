@@ -0,0 +1,97 @@
+// Hitscan shots judged on the server against where the target was on the
+// shooter's screen, over a loopback network with latency.
+
+mod common;
+
+use macroquad::prelude::*;
+
+use fps_engine::input::InputCommand;
+use fps_engine::server::ServerConfig;
+use fps_engine::transport::LinkConditions;
+
+use common::{Game, flat_level};
+
+const SHOOTER: usize = 0;
+const TARGET: usize = 1;
+
+// view angles that look from `from` towards `to`
+fn aim(from: Vec3, to: Vec3) -> (f32, f32) {
+    let direction = (to - from).normalize();
+    (f32::atan2(-direction.z, direction.x), direction.y.asin())
+}
+
+// one shot at a target running sideways across the shooter's view, aimed
+// at the middle of the target as the shooter's client shows it. Returns
+// whether the server counted it as a hit.
+fn shoot(latency: f32, max_rewind: f32, strafe: bool) -> bool {
+    let mut game = Game::with_level(
+        flat_level,
+        ServerConfig {
+            max_rewind,
+            ..Default::default()
+        },
+    );
+    game.set_conditions(LinkConditions {
+        latency,
+        ..Default::default()
+    });
+    game.connect("shooter");
+    game.connect("target");
+    game.wait_for_snapshots();
+
+    // the target walks out in front of the shooter, then strafes
+    for frame in 0..90 {
+        game.step(|i| {
+            Some(InputCommand {
+                forward: i == TARGET && frame < 60,
+                right: i == TARGET && frame >= 60 && strafe,
+                ..Default::default()
+            })
+        });
+    }
+
+    let shooter = &game.clients[SHOOTER];
+    let world = shooter.world().unwrap();
+    let eye = world.avatar(shooter.player_id().unwrap()).unwrap().player.eye();
+    let target_id = game.clients[TARGET].player_id().unwrap();
+    let seen = world.avatar(target_id).unwrap().player.center();
+    let (theta, phi) = aim(eye, seen);
+    for frame in 0..60 {
+        game.step(|i| {
+            Some(InputCommand {
+                theta: if i == SHOOTER { theta } else { 0. },
+                phi: if i == SHOOTER { phi } else { 0. },
+                fire: i == SHOOTER && frame == 0,
+                right: i == TARGET && strafe,
+                ..Default::default()
+            })
+        });
+    }
+
+    let target = &game.server.world.avatar(target_id).unwrap().player;
+    target.health.health() < target.health.config.max_health
+}
+
+#[test]
+fn shots_hit_where_the_shooter_saw_the_target() {
+    assert!(shoot(0.1, 0.3, true));
+}
+
+#[test]
+fn without_rewinding_the_same_shot_misses() {
+    // by the time the shot arrives the target has run on by more than its
+    // own width
+    assert!(!shoot(0.1, 0., true));
+}
+
+#[test]
+fn rewinding_stops_at_the_max_rewind() {
+    assert!(!shoot(0.25, 0.1, true));
+    assert!(shoot(0.25, 1., true));
+}
+
+#[test]
+fn a_standing_target_is_hit_either_way() {
+    assert!(shoot(0.1, 0.3, false));
+    assert!(shoot(0.1, 0., false));
+}
@@ -0,0 +1,130 @@
+// Level files parsed and built without a window, and the errors a broken
+// one gives.
+
+use fps_engine::level::{Level, LevelError};
+
+fn parse_error(source: &str) -> String {
+    match Level::parse("levels/broken.ron", source) {
+        Err(error @ LevelError::Parse { .. }) => error.to_string(),
+        Err(error) => panic!("not a parse error: {}", error),
+        Ok(_) => panic!("parsed"),
+    }
+}
+
+#[test]
+fn parse_errors_point_at_the_path_line_and_column() {
+    let error = parse_error(
+        "Level(
+    spawn: (position: (0, 1, 0)),
+    objects: [
+        Box(position: (0, -0.5, 0), half_extents: (10, 0.5)),
+    ],
+)",
+    );
+    assert!(error.starts_with("levels/broken.ron:4:"), "{}", error);
+
+    let error = parse_error("Level(\n    spwan: (position: (0, 1, 0)),\n)");
+    assert!(error.starts_with("levels/broken.ron:2:5: "), "{}", error);
+    assert!(error.contains("spwan"), "{}", error);
+}
+
+#[test]
+fn models_are_built_into_the_level() {
+    let level = Level::parse(
+        "test",
+        r#"Level(models: [(path: "models/arch/arch.obj", position: (0, 0, 0), collider: Box((1, 1, 1)))])"#,
+    )
+    .unwrap();
+    assert_eq!(level.build_state().unwrap().objects().len(), 1);
+
+    // a model that isn't there is an error, not a gap in the level
+    let error = parse_error(r#"Level(models: [(path: "models/missing.obj", position: (0, 0, 0))])"#);
+    assert!(error.starts_with("levels/broken.ron:1:24: "), "{}", error);
+    assert!(error.contains("models/missing.obj"), "{}", error);
+}
+
+#[test]
+fn model_colliders_and_scales_must_be_positive() {
+    let model = |fields: &str| {
+        format!(r#"Level(models: [(path: "models/arch/arch.obj", position: (0, 0, 0), {})])"#, fields)
+    };
+    for fields in [
+        "collider: Ball(0)",
+        "collider: Capsule(half_height: 1, radius: -0.5)",
+        "collider: Capsule(half_height: -1, radius: 0.5)",
+        "scale: (1, 0, 1)",
+        "scale: (-1, 1, 1)",
+    ] {
+        let error = parse_error(&model(fields));
+        assert!(error.contains("positive"), "{}: {}", fields, error);
+    }
+
+    let level = Level::parse("test", &model("collider: Ball(1), scale: (2, 2, 2)")).unwrap();
+    assert_eq!(level.build_state().unwrap().objects().len(), 1);
+}
+
+// a scratch directory of its own for each `test` holding `files`, removed
+// again after it runs
+fn with_files(test: &str, files: &[(&str, &[u8])], run: impl FnOnce(&std::path::Path)) {
+    let dir = std::env::temp_dir().join(format!("fps-engine-{}-{}", test, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, contents) in files {
+        std::fs::write(dir.join(name), contents).unwrap();
+    }
+    run(&dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_model_whose_materials_are_missing_is_an_error_where_it_is_named() {
+    let obj = b"mtllib gone.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n";
+    with_files("missing-mtl", &[("triangle.obj", obj)], |dir| {
+        let source = format!(
+            "Level(\n    models: [(path: \"{}\", position: (0, 0, 0))],\n)",
+            dir.join("triangle.obj").display()
+        );
+        let error = parse_error(&source);
+        assert!(error.starts_with("levels/broken.ron:2:"), "{}", error);
+        assert!(error.contains("could not load materials"), "{}", error);
+    });
+}
+
+#[test]
+fn a_broken_texture_is_an_error_where_it_is_named() {
+    with_files("broken-texture", &[("brick.png", b"not a png")], |dir| {
+        let source = format!(
+            "Level(\n    objects: [\n        Box(position: (0, 0, 0), half_extents: (1, 1, 1), texture: Some(\"{}\")),\n    ],\n)",
+            dir.join("brick.png").display()
+        );
+        let error = parse_error(&source);
+        assert!(error.starts_with("levels/broken.ron:3:"), "{}", error);
+        assert!(error.contains("brick.png"), "{}", error);
+    });
+}
+
+#[test]
+fn unknown_materials_are_errors_where_they_are_named() {
+    let error = parse_error(
+        "Level(
+    objects: [
+        Box(position: (0, 0, 0), half_extents: (1, 1, 1), material: Some(\"brick\")),
+    ],
+)",
+    );
+    assert!(error.starts_with("levels/broken.ron:3:"), "{}", error);
+    assert!(error.contains("unknown material: brick"), "{}", error);
+
+    // defined by the level, even further down than where it's used, or built in
+    let level = Level::parse(
+        "test",
+        "Level(
+    objects: [
+        Box(position: (0, 0, 0), half_extents: (1, 1, 1), material: Some(\"brick\")),
+        Box(position: (0, 3, 0), half_extents: (1, 1, 1), material: Some(\"world\")),
+    ],
+    materials: [(name: \"brick\")],
+)",
+    )
+    .unwrap();
+    assert_eq!(level.build_state().unwrap().objects().len(), 2);
+}
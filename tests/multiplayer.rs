@@ -0,0 +1,316 @@
+// A server and two clients joining, moving, chatting and leaving.
+
+mod common;
+
+use std::io;
+use std::net::SocketAddr;
+
+use fps_engine::client::{Client, ClientEvent};
+use fps_engine::connection::Connection;
+use fps_engine::input::InputCommand;
+use fps_engine::protocol::{ClientMessage, InputPacket, TimedCommand, Writer};
+use fps_engine::server::{LeaveReason, Server, ServerConfig, ServerEvent};
+use fps_engine::transport::{LoopbackNetwork, LoopbackTransport, Transport};
+
+use common::{DT, Game, LEVEL, test_level};
+
+fn two_players() -> Game {
+    let mut game = Game::new(ServerConfig::default());
+    game.connect("alice");
+    game.connect("bob");
+    game.run(30);
+    game
+}
+
+#[test]
+fn clients_join_and_see_each_other() {
+    let game = two_players();
+    let alice = game.clients[0].player_id().expect("alice joined");
+    let bob = game.clients[1].player_id().expect("bob joined");
+    assert_ne!(alice, bob);
+    assert_eq!(game.server.world.avatars.len(), 2);
+    for events in game.events.iter() {
+        let level = events.iter().find_map(|event| match event {
+            ClientEvent::Connected { level, .. } => Some(level),
+            _ => None,
+        });
+        assert_eq!(level.map(String::as_str), Some(LEVEL));
+    }
+    assert!(
+        game.server_events
+            .iter()
+            .any(|e| matches!(e, ServerEvent::Joined { id, name, .. } if *id == bob && name == "bob"))
+    );
+
+    for client in game.clients.iter() {
+        let world = client.world().expect("attached");
+        assert!(world.avatar(alice).is_some());
+        assert!(world.avatar(bob).is_some());
+        assert_eq!(client.name(alice), Some("alice"));
+        assert_eq!(client.name(bob), Some("bob"));
+    }
+    assert!(game.events[0].contains(&ClientEvent::PlayerJoined {
+        id: bob,
+        name: "bob".to_string()
+    }));
+}
+
+#[test]
+fn movement_is_replicated() {
+    let mut game = two_players();
+    let alice = game.clients[0].player_id().unwrap();
+    let start = game.server.world.avatar(alice).unwrap().player.position;
+
+    for _ in 0..60 {
+        game.step(|i| {
+            Some(InputCommand {
+                forward: i == 0,
+                ..Default::default()
+            })
+        });
+    }
+    // let the last commands and snapshots land
+    game.run(5);
+
+    let moved = game.server.world.avatar(alice).unwrap().player.position;
+    assert!(moved.distance(start) > 2., "alice moved from {} to {}", start, moved);
+    for client in game.clients.iter() {
+        let seen = client.world().unwrap().avatar(alice).unwrap().player.position;
+        assert!(seen.distance(moved) < 0.01, "seen at {}, server has {}", seen, moved);
+    }
+}
+
+#[test]
+fn chat_reaches_everyone() {
+    let mut game = two_players();
+    let alice = game.clients[0].player_id().unwrap();
+    game.clients[0].chat("hello");
+    game.run(10);
+
+    let chat = ClientEvent::Chat {
+        from: alice,
+        text: "hello".to_string(),
+    };
+    assert!(game.events[0].contains(&chat));
+    assert!(game.events[1].contains(&chat));
+    assert!(game.server_events.contains(&ServerEvent::Chat {
+        from: alice,
+        text: "hello".to_string()
+    }));
+}
+
+#[test]
+fn leaving_removes_the_player() {
+    let mut game = two_players();
+    let alice = game.clients[0].player_id().unwrap();
+    game.clients[0].leave().unwrap();
+    game.run(10);
+
+    assert!(game.server.world.avatar(alice).is_none());
+    assert!(game.server_events.iter().any(|e| matches!(
+        e,
+        ServerEvent::Left { id, reason: LeaveReason::Quit, .. } if *id == alice
+    )));
+    assert!(game.events[1].contains(&ClientEvent::PlayerLeft { id: alice }));
+    assert!(game.clients[1].world().unwrap().avatar(alice).is_none());
+}
+
+#[test]
+fn silent_clients_time_out() {
+    let mut game = two_players();
+    let alice = game.clients[0].player_id().unwrap();
+    // alice's machine goes away without a word
+    game.clients.remove(0);
+    game.events.remove(0);
+    game.run(game.server.config.timeout as u32 * 60 + 30);
+
+    assert!(game.server.world.avatar(alice).is_none());
+    assert!(game.server_events.iter().any(|e| matches!(
+        e,
+        ServerEvent::Left { id, reason: LeaveReason::TimedOut, .. } if *id == alice
+    )));
+}
+
+#[test]
+fn a_full_server_refuses() {
+    let mut game = Game::new(ServerConfig {
+        max_players: 1,
+        ..Default::default()
+    });
+    game.connect("alice");
+    game.connect("bob");
+    game.run(30);
+
+    assert!(game.clients[0].player_id().is_some());
+    assert!(game.clients[1].player_id().is_none());
+    assert!(game.events[1].iter().any(|e| matches!(e, ClientEvent::Refused(_))));
+    assert_eq!(game.server.world.avatars.len(), 1);
+}
+
+// a client that sends whatever packets it's told to
+struct RawClient {
+    transport: LoopbackTransport,
+    connection: Connection,
+}
+
+impl RawClient {
+    fn join(game: &mut Game, name: &str) -> RawClient {
+        let join = ClientMessage::Join {
+            name: name.to_string(),
+        };
+        RawClient::join_with(game, join.encode())
+    }
+
+    // joins with `join` as the first message, however it's encoded
+    fn join_with(game: &mut Game, join: Vec<u8>) -> RawClient {
+        let mut raw = RawClient {
+            transport: game.network.endpoint(),
+            connection: Connection::new(game.server.local_addr(), 0.),
+        };
+        raw.connection.send_message(join);
+        raw.send(&[]);
+        game.run(5);
+        raw
+    }
+
+    fn send_message(&mut self, data: Vec<u8>) {
+        self.connection.send_message(data);
+        self.send(&[]);
+    }
+
+    fn send(&mut self, payload: &[u8]) {
+        let packet = self.connection.build_packet(0., payload);
+        self.transport.send(self.connection.addr, &packet).unwrap();
+    }
+
+    fn send_input(&mut self, tick: u32, count: usize, command: InputCommand) {
+        let commands = vec![
+            TimedCommand {
+                command,
+                view_tick: 0,
+            };
+            count
+        ];
+        self.send(&InputPacket { tick, commands }.encode());
+    }
+}
+
+#[test]
+fn input_from_impossible_ticks_is_dropped() {
+    let mut game = Game::new(ServerConfig::default());
+    let mut mallory = RawClient::join(&mut game, "mallory");
+    let (id, _) = game.server.players().next().unwrap();
+    game.run(30);
+    let start = game.server.world.avatar(id).unwrap().player.position;
+    let forward = InputCommand {
+        forward: true,
+        ..Default::default()
+    };
+
+    // once overflowed the tick arithmetic
+    mallory.send_input(u32::MAX, 2, forward);
+    game.run(5);
+    // far ahead of anything sent so far
+    mallory.send_input(1000, 4, forward);
+    game.run(5);
+    assert_eq!(game.server.world.avatar(id).unwrap().player.position, start);
+
+    // and honest input still gets through afterwards
+    for tick in 1..30 {
+        mallory.send_input(tick, 1, forward);
+        game.run(1);
+    }
+    assert!(game.server.world.avatar(id).unwrap().player.position.distance(start) > 0.5);
+}
+
+// a chat message or join of `len` bytes of text, longer than any client sends
+fn oversized(tag: u8, len: usize) -> Vec<u8> {
+    let mut w = Writer::new();
+    w.u8(tag);
+    w.str(&"x".repeat(len));
+    w.into_bytes()
+}
+
+#[test]
+fn oversized_chat_and_names_dont_stall_everyone_elses_messages() {
+    let mut game = two_players();
+    let alice = game.clients[0].player_id().unwrap();
+    let mut mallory = RawClient::join(&mut game, "mallory");
+    // too big to be relayed in any packet
+    mallory.send_message(oversized(1, 1000));
+    RawClient::join_with(&mut game, oversized(0, 1000));
+    game.run(10);
+    assert!(!game.server_events.iter().any(|e| matches!(e, ServerEvent::Chat { .. })));
+    assert_eq!(game.server.players().count(), 3);
+
+    game.clients[0].chat("still there?");
+    game.run(10);
+    let chat = ClientEvent::Chat {
+        from: alice,
+        text: "still there?".to_string(),
+    };
+    assert!(game.events[1].contains(&chat));
+}
+
+// the server's end of the loopback network, except that nothing it sends
+// to `unreachable` goes out
+struct NoRouteTo {
+    transport: LoopbackTransport,
+    unreachable: SocketAddr,
+}
+
+impl Transport for NoRouteTo {
+    fn send(&mut self, to: SocketAddr, packet: &[u8]) -> io::Result<()> {
+        if to == self.unreachable {
+            return Err(io::Error::new(io::ErrorKind::HostUnreachable, "no route to host"));
+        }
+        self.transport.send(to, packet)
+    }
+
+    fn receive(&mut self) -> io::Result<Option<(SocketAddr, Vec<u8>)>> {
+        self.transport.receive()
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.transport.local_addr()
+    }
+}
+
+#[test]
+fn a_client_that_cant_be_sent_to_doesnt_hold_up_the_rest() {
+    let network = LoopbackNetwork::new();
+    let alice = network.endpoint();
+    let transport = NoRouteTo {
+        transport: network.endpoint(),
+        unreachable: alice.local_addr(),
+    };
+    let mut server = Server::new(transport, test_level(), ServerConfig::default());
+    let mut clients = [
+        Client::connect(alice, server.local_addr(), "alice"),
+        Client::connect(network.endpoint(), server.local_addr(), "bob"),
+    ];
+    let mut events = Vec::new();
+    for _ in 0..60 {
+        network.advance(DT);
+        for client in clients.iter_mut() {
+            client.send_command(InputCommand::default()).unwrap();
+        }
+        server.update(DT).unwrap();
+        events.extend(server.take_events());
+        for client in clients.iter_mut() {
+            client.update(DT).unwrap();
+            if client.take_events().iter().any(|e| matches!(e, ClientEvent::Connected { .. })) {
+                client.attach(test_level());
+            }
+        }
+    }
+
+    // alice is in the game but hears nothing back, and it's said just once
+    let failures = events.iter().filter(|e| matches!(e, ServerEvent::SendFailed { .. }));
+    assert_eq!(failures.count(), 1);
+    assert_eq!(server.world.avatars.len(), 2);
+    assert!(clients[0].player_id().is_none());
+    // bob, served after her, is kept up to date
+    let bob = clients[1].world().expect("bob was welcomed");
+    assert!(server.world.tick - bob.tick <= 1);
+}
@@ -0,0 +1,121 @@
+// Client-side prediction of the client's own movement, checked against the
+// authoritative server over a loopback network with latency and loss.
+
+mod common;
+
+use macroquad::prelude::*;
+
+use fps_engine::input::InputCommand;
+use fps_engine::server::ServerConfig;
+use fps_engine::transport::LinkConditions;
+
+use common::{Game, flat_level};
+
+// a few seconds of running about: forward, strafing, turning and jumping
+fn wander(tick: u32) -> InputCommand {
+    InputCommand {
+        theta: tick as f32 * 0.01,
+        forward: true,
+        left: (tick / 40).is_multiple_of(2),
+        jump: tick.is_multiple_of(50),
+        sprint: tick > 100,
+        ..Default::default()
+    }
+}
+
+fn joined(conditions: LinkConditions) -> Game {
+    let mut game = Game::with_level(flat_level, ServerConfig::default());
+    game.set_conditions(conditions);
+    game.connect("alice");
+    game.wait_for_snapshots();
+    game
+}
+
+#[test]
+fn own_movement_does_not_wait_for_the_server() {
+    let mut game = joined(LinkConditions {
+        latency: 0.1,
+        ..Default::default()
+    });
+    let start = game.predicted_position(0);
+    let server_start = game.server_position(0);
+    for _ in 0..3 {
+        game.step(|_| {
+            Some(InputCommand {
+                forward: true,
+                ..Default::default()
+            })
+        });
+    }
+    // the commands are still on their way, but the client has already moved
+    assert_eq!(game.server_position(0), server_start);
+    assert!(game.predicted_position(0).x > start.x + 0.01);
+}
+
+#[test]
+fn prediction_matches_the_server_under_latency() {
+    let mut game = joined(LinkConditions {
+        latency: 0.1,
+        ..Default::default()
+    });
+    let start = game.server_position(0);
+    let mut worst: f32 = 0.;
+    for tick in 0..240 {
+        game.step(|_| Some(wander(tick)));
+        worst = worst.max(game.clients[0].correction());
+    }
+    game.run(30);
+
+    assert!(game.server_position(0).distance(start) > 10.);
+    // same code, same commands, same ticks: no correction was ever needed
+    assert!(worst < 1e-4, "prediction was off by up to {}", worst);
+    assert!(game.predicted_position(0).distance(game.server_position(0)) < 1e-4);
+}
+
+#[test]
+fn prediction_converges_under_loss_and_jitter() {
+    let mut game = joined(LinkConditions {
+        latency: 0.08,
+        jitter: 0.04,
+        loss: 0.2,
+    });
+    let start = game.server_position(0);
+    for tick in 0..240 {
+        game.step(|_| Some(wander(tick)));
+    }
+    // the server has been guessing at lost input, so the two have drifted
+    // apart; once input stops they must agree again
+    game.run(60);
+
+    let server = game.server_position(0);
+    let predicted = game.predicted_position(0);
+    assert!(server.distance(start) > 10.);
+    assert!(
+        predicted.distance(server) < 1e-3,
+        "predicted {} but the server has {}",
+        predicted,
+        server
+    );
+}
+
+#[test]
+fn server_corrections_win() {
+    let mut game = joined(LinkConditions {
+        latency: 0.05,
+        ..Default::default()
+    });
+    for tick in 0..30 {
+        game.step(|_| Some(wander(tick)));
+    }
+    // something only the server knows about moves the player
+    let id = game.clients[0].player_id().unwrap();
+    game.server.world.avatar_mut(id).unwrap().player.position.z += 5.;
+    for tick in 30..90 {
+        game.step(|_| Some(wander(tick)));
+    }
+    game.run(30);
+
+    let server = game.server_position(0);
+    let predicted = game.predicted_position(0);
+    assert!(predicted.distance(server) < 1e-3, "predicted {} but the server has {}", predicted, server);
+}
@@ -0,0 +1,103 @@
+// Projectiles flown through a level: fast rockets don't tunnel through
+// thin walls, and grenades bounce until they settle.
+
+mod common;
+
+use macroquad::prelude::*;
+
+use fps_engine::game::State;
+use fps_engine::objects::{CollisionBox, Transform};
+use fps_engine::projectile::{Explosion, Projectile, ProjectileConfig};
+use fps_engine::rewind::Hitbox;
+
+use common::{DT, flat_level};
+
+fn add_box(state: &mut State, position: Vec3, half_extents: Vec3) {
+    state.add_object(Box::new(CollisionBox::new(
+        Transform::from_position(position),
+        half_extents,
+    )));
+}
+
+// steps the projectiles among `players` for up to `seconds`, until
+// something explodes
+fn fly(state: &mut State, players: &[Hitbox], seconds: f32) -> Option<Explosion> {
+    for _ in 0..(seconds / DT).round() as u32 {
+        if let Some(explosion) = state.update_projectiles(players, DT).pop() {
+            return Some(explosion);
+        }
+    }
+    None
+}
+
+#[test]
+fn a_rocket_explodes_at_a_wall_thinner_than_a_tick_of_its_flight() {
+    let mut state = State::new();
+    // 0.05 thick, where the rocket covers over 0.4 a tick
+    add_box(&mut state, vec3(5., 0., 0.), vec3(0.025, 5., 5.));
+    let rocket = ProjectileConfig::rocket();
+    assert!(rocket.speed * DT > 0.4);
+    state.projectiles.push(Projectile::new(rocket, Vec3::ZERO, Vec3::X));
+
+    let explosion = fly(&mut state, &[], 1.).expect("flew through the wall");
+    let face = 5. - 0.025 - rocket.radius;
+    assert!((explosion.position.x - face).abs() < 0.02, "went off at {}", explosion.position);
+    assert!(state.projectiles.is_empty());
+}
+
+#[test]
+fn a_rocket_explodes_on_a_player_it_hits() {
+    let mut state = State::new();
+    let player = |id, x| Hitbox {
+        id,
+        position: vec3(x, -1., 0.),
+        radius: 0.3,
+        half_height: 0.5,
+    };
+    let rocket = ProjectileConfig::rocket();
+    // out of its owner, who it doesn't hit, and at someone out in the open
+    state.projectiles.push(Projectile {
+        owner: Some(0),
+        ..Projectile::new(rocket, Vec3::ZERO, Vec3::X)
+    });
+    let players = [player(0, 0.), player(1, 8.)];
+
+    let explosion = fly(&mut state, &players, 1.).expect("flew through them");
+    assert_eq!(explosion.owner, Some(0));
+    let face = 8. - 0.3 - rocket.radius;
+    assert!((explosion.position.x - face).abs() < 0.02, "went off at {}", explosion.position);
+}
+
+#[test]
+fn a_grenade_bounces_and_comes_to_rest() {
+    let mut state = flat_level();
+    // with time to settle before it goes off
+    let fuse = 5.;
+    let grenade = ProjectileConfig {
+        fuse: Some(fuse),
+        ..ProjectileConfig::grenade()
+    };
+    // lobbed up and ahead from head height
+    let direction = vec3(1., 1., 0.).normalize();
+    state.projectiles.push(Projectile::new(grenade, vec3(0., 1.5, 0.), direction));
+
+    let mut bounces = 0;
+    let mut falling = false;
+    for _ in 0..((fuse - 0.1) / DT) as u32 {
+        assert!(state.update_projectiles(&[], DT).is_empty());
+        let projectile = &state.projectiles[0];
+        assert!(projectile.position.y >= grenade.radius - 0.01, "sank to {}", projectile.position);
+        if falling && projectile.velocity.y > 0. {
+            bounces += 1;
+        }
+        falling = projectile.velocity.y < 0.;
+    }
+    assert!(bounces >= 2, "bounced {} times", bounces);
+    let rest = state.projectiles[0].position;
+    assert!(rest.x > 2., "stopped at {}", rest);
+    assert!((rest.y - grenade.radius).abs() < 0.02, "resting at {}", rest);
+
+    // lying still until the fuse runs out, and going off there
+    let explosion = fly(&mut state, &[], 0.2).expect("never went off");
+    assert!(explosion.position.distance(rest) < 0.01);
+}
@@ -0,0 +1,99 @@
+// Rigid bodies stepped headless: falling, coming to rest, sleeping, and
+// being pushed, including out from under each other.
+
+mod common;
+
+use macroquad::prelude::*;
+
+use fps_engine::game::State;
+use fps_engine::objects::{PhysicsObject, Transform};
+use fps_engine::rigidbody::{BodyConfig, BodyShape, RigidBody};
+
+use common::{DT, flat_level, test_level};
+
+const HALF: f32 = 0.4;
+
+fn run(state: &mut State, seconds: f32) {
+    for _ in 0..(seconds / DT).round() as u32 {
+        state.update_bodies(DT);
+    }
+}
+
+fn body(state: &State, index: usize) -> &RigidBody {
+    state.objects()[index].rigid_body().unwrap()
+}
+
+fn position(state: &State, index: usize) -> Vec3 {
+    body(state, index).get_transform().position
+}
+
+// a crate held `height` above a wide floor
+fn crate_over_floor(height: f32) -> (State, usize) {
+    let mut state = flat_level();
+    let index = state.add_object(Box::new(RigidBody::new(
+        Transform::from_position(vec3(0., HALF + height, 0.)),
+        BodyShape::Box(Vec3::splat(HALF)),
+        BodyConfig::default(),
+    )));
+    (state, index)
+}
+
+#[test]
+fn a_dropped_crate_comes_to_rest_on_the_floor_and_sleeps() {
+    let (mut state, index) = crate_over_floor(3.);
+    run(&mut state, 3.);
+    let resting = position(&state, index);
+    assert!((resting.y - HALF).abs() < 0.02, "resting at {}", resting);
+    assert!(resting.xz().length() < 0.05, "wandered to {}", resting);
+    assert!(body(&state, index).is_asleep());
+
+    // asleep, it stays exactly where it is
+    run(&mut state, 5.);
+    assert_eq!(position(&state, index), resting);
+}
+
+#[test]
+fn a_push_slides_a_crate_until_friction_stops_it() {
+    let (mut state, index) = crate_over_floor(0.);
+    run(&mut state, 2.);
+    assert!(body(&state, index).is_asleep());
+
+    let start = position(&state, index);
+    state.apply_impulse(index, start, vec3(100., 0., 0.));
+    assert!(!body(&state, index).is_asleep());
+    run(&mut state, 0.1);
+    assert!(position(&state, index).x > start.x);
+
+    run(&mut state, 4.);
+    let end = position(&state, index);
+    // a push through its center slides it without tipping it over
+    assert!(end.x - start.x > 0.5 && end.x - start.x < 10., "slid to {}", end);
+    assert!((end.y - HALF).abs() < 0.02);
+    assert!(body(&state, index).is_asleep());
+}
+
+#[test]
+fn a_crate_falls_when_the_one_under_it_is_pushed_away() {
+    let mut state = test_level();
+    let find = |state: &State, y: f32| {
+        state.bodies().iter().copied().find(|&index| {
+            let position = position(state, index);
+            position.xz().distance(vec2(-3., 1.)) < 0.1 && (position.y - y).abs() < 0.1
+        })
+    };
+    let bottom = find(&state, HALF).expect("bottom crate");
+    let top = find(&state, 3. * HALF).expect("top crate");
+    run(&mut state, 3.);
+    assert!(body(&state, bottom).is_asleep() && body(&state, top).is_asleep());
+    let stacked = position(&state, top);
+    assert!((stacked.y - 3. * HALF).abs() < 0.05, "top crate at {}", stacked);
+
+    let from = position(&state, bottom);
+    state.apply_impulse(bottom, from, vec3(250., 0., 0.));
+    run(&mut state, 4.);
+
+    let slid = position(&state, bottom) - from;
+    assert!(slid.x > 2., "bottom crate only slid {}", slid);
+    let fallen = position(&state, top);
+    assert!(fallen.y < 2. * HALF, "top crate left floating at {}", fallen);
+}
@@ -0,0 +1,210 @@
+// Snapshot bandwidth with delta compression and per-client relevance, over
+// the loopback network. Run with --nocapture to see bytes per tick.
+
+mod common;
+
+use macroquad::prelude::*;
+
+use fps_engine::client::ClientEvent;
+use fps_engine::game::State;
+use fps_engine::input::InputCommand;
+use fps_engine::objects::{CollisionBox, Transform};
+use fps_engine::protocol::MAX_PACKET_SIZE;
+use fps_engine::rigidbody::{BodyConfig, BodyShape, RigidBody};
+use fps_engine::server::ServerConfig;
+use fps_engine::transport::LinkConditions;
+
+use common::{Game, flat_level};
+
+const PLAYERS: usize = 16;
+
+// each player running its own way, turning, jumping now and then
+fn wander(i: usize, frame: u32) -> InputCommand {
+    InputCommand {
+        theta: i as f32 * 0.4 + frame as f32 * 0.02,
+        forward: true,
+        left: (frame / 30 + i as u32).is_multiple_of(2),
+        jump: (frame + i as u32 * 7).is_multiple_of(45),
+        ..Default::default()
+    }
+}
+
+// what a snapshot of `players` players took as plain little endian floats
+// before deltas and quantizing: packet header, snapshot header, 50 bytes a
+// player and empty lists of bodies and projectiles
+fn full_size(players: usize) -> f32 {
+    (13 + 8 + 2 + players * 50 + 2 + 2) as f32
+}
+
+fn joined(players: usize, conditions: LinkConditions) -> Game {
+    let mut game = Game::with_level(flat_level, ServerConfig::default());
+    game.set_conditions(conditions);
+    for i in 0..players {
+        game.connect(&format!("player{}", i));
+    }
+    game.wait_for_snapshots();
+    game
+}
+
+// average bytes each client received per server tick over `frames` frames
+fn bytes_per_tick(game: &mut Game, frames: u32, commands: impl Fn(usize, u32) -> InputCommand) -> f32 {
+    let received = |game: &Game| game.clients.iter().map(|c| c.bytes_received()).sum::<u64>();
+    let start = received(game);
+    let start_tick = game.server.world.tick;
+    for frame in 0..frames {
+        game.step(|i| Some(commands(i, frame)));
+    }
+    let ticks = (game.server.world.tick - start_tick) as f32;
+    (received(game) - start) as f32 / ticks / game.clients.len() as f32
+}
+
+#[test]
+fn sixteen_players_moving_cost_a_fraction_of_full_snapshots() {
+    let mut game = joined(PLAYERS, LinkConditions::default());
+    let moving = bytes_per_tick(&mut game, 180, wander);
+    let full = full_size(PLAYERS);
+    println!(
+        "{} players moving: {:.0} bytes per tick per client, {:.0} without compression",
+        PLAYERS, moving, full
+    );
+    assert!(moving < full / 2., "{} bytes per tick", moving);
+}
+
+#[test]
+fn sixteen_players_standing_still_cost_next_to_nothing() {
+    let mut game = joined(PLAYERS, LinkConditions::default());
+    // let everyone land and settle
+    game.run(60);
+    let idle = bytes_per_tick(&mut game, 120, |_, _| InputCommand::default());
+    println!("{} players idle: {:.0} bytes per tick per client", PLAYERS, idle);
+    assert!(idle < full_size(PLAYERS) / 8., "{} bytes per tick", idle);
+}
+
+#[test]
+fn deltas_survive_loss() {
+    let mut game = joined(
+        4,
+        LinkConditions {
+            latency: 0.05,
+            jitter: 0.02,
+            loss: 0.2,
+        },
+    );
+    for frame in 0..240 {
+        game.step(|i| Some(wander(i, frame)));
+    }
+    game.run(90);
+
+    // every client ends up with everyone where the server has them, to
+    // within what quantizing loses
+    for client in game.clients.iter() {
+        let world = client.world().unwrap();
+        for avatar in game.server.world.avatars.iter() {
+            let seen = world.avatar(avatar.player.id).expect("everyone is in view");
+            assert!(seen.player.position.distance(avatar.player.position) < 0.01);
+        }
+    }
+}
+
+// the flat level with a wall across +x
+fn walled_level() -> State {
+    let mut state = flat_level();
+    state.add_object(Box::new(CollisionBox::new(
+        Transform::from_position(vec3(10., 5., 0.)),
+        vec3(0.5, 5., 20.),
+    )));
+    state
+}
+
+fn place(game: &mut Game, client: usize, position: Vec3) {
+    let id = game.clients[client].player_id().unwrap();
+    let player = &mut game.server.world.avatar_mut(id).unwrap().player;
+    let mut state = player.state();
+    state.position = position;
+    state.velocity = Vec3::ZERO;
+    player.set_state(&state);
+}
+
+fn sees(game: &Game, viewer: usize, other: usize) -> bool {
+    let id = game.clients[other].player_id().unwrap();
+    game.clients[viewer].world().unwrap().avatar(id).is_some()
+}
+
+#[test]
+fn players_out_of_sight_or_range_are_left_out() {
+    let mut game = Game::with_level(
+        walled_level,
+        ServerConfig {
+            relevance_distance: 50.,
+            ..Default::default()
+        },
+    );
+    game.connect("viewer");
+    game.connect("other");
+    game.wait_for_snapshots();
+
+    place(&mut game, 0, vec3(0., 0., 0.));
+    place(&mut game, 1, vec3(20., 0., 0.));
+    game.run(10);
+    assert!(!sees(&game, 0, 1), "seen through the wall");
+    assert!(!sees(&game, 1, 0));
+
+    place(&mut game, 1, vec3(12., 0., 40.));
+    game.run(10);
+    assert!(sees(&game, 0, 1), "not seen around the wall");
+
+    place(&mut game, 1, vec3(-60., 0., 0.));
+    game.run(10);
+    assert!(!sees(&game, 0, 1), "seen beyond the relevance distance");
+
+    // the server still has everyone, and each client its own player
+    assert_eq!(game.server.world.avatars.len(), 2);
+    assert!(sees(&game, 0, 0) && sees(&game, 1, 1));
+}
+
+// the flat level with 300 crates dropping in a grid ahead of the spawn
+fn crowded_level() -> State {
+    let mut state = flat_level();
+    for i in 0..20 {
+        for j in 0..15 {
+            state.add_object(Box::new(RigidBody::new(
+                Transform::from_position(vec3(5. + i as f32 * 2., 2., j as f32 * 2. - 14.)),
+                BodyShape::Box(Vec3::splat(0.4)),
+                BodyConfig::default(),
+            )));
+        }
+    }
+    state
+}
+
+#[test]
+fn crowded_snapshots_fit_in_a_packet_and_leave_room_for_messages() {
+    let mut game = Game::with_level(crowded_level, ServerConfig::default());
+    let whole = game.server.snapshot().encode(None).len();
+    assert!(whole > MAX_PACKET_SIZE, "only {} bytes, so nothing to cut", whole);
+
+    game.connect("alice");
+    game.wait_for_snapshots();
+    // joining and chatting while every crate is falling
+    let bob = game.connect("bob");
+    game.wait_for_snapshots();
+    game.clients[0].chat("look out below");
+    game.run(5);
+    assert!(game.events[bob].iter().any(|event| matches!(
+        event,
+        ClientEvent::Chat { text, .. } if text == "look out below"
+    )));
+
+    // oversized packets are rejected, so every crate the clients got
+    // came in snapshots that fit, some later than others
+    game.run(240);
+    let server = &game.server.world.state;
+    for client in game.clients.iter() {
+        let state = &client.world().unwrap().state;
+        for &index in server.bodies().iter() {
+            let expected = server.objects()[index].get_transform().position;
+            let seen = state.objects()[index].get_transform().position;
+            assert!(seen.distance(expected) < 0.01, "crate {} at {} not {}", index, seen, expected);
+        }
+    }
+}
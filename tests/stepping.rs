@@ -0,0 +1,60 @@
+// The simulation stepped without a window: a level parsed from source and a
+// player driven by fixed commands land the same way every run.
+
+mod common;
+
+use fps_engine::game::State;
+use fps_engine::input::InputCommand;
+use fps_engine::level::Level;
+use fps_engine::player::{Player, PlayerState};
+
+use common::DT;
+
+// a floor with a step up ahead of a spawn up in the air
+const LEVEL: &str = r#"Level(
+    spawn: (position: (0, 3, 0), theta: 0, phi: 0),
+    objects: [
+        Box(position: (0, -0.5, 0), half_extents: (20, 0.5, 20)),
+        Box(position: (8, 0.1, 0), half_extents: (2, 0.1, 4)),
+    ],
+)"#;
+
+fn level() -> State {
+    Level::parse("stepping.ron", LEVEL).unwrap().build_state().unwrap()
+}
+
+// every tick of a player spawned in `state` running forward for two seconds
+fn run(state: &State) -> Vec<PlayerState> {
+    let mut player = Player::new(&state.spawn);
+    let cmd = InputCommand {
+        forward: true,
+        ..Default::default()
+    };
+    (0..120)
+        .map(|_| {
+            player.update(state, &cmd, DT);
+            player.state()
+        })
+        .collect()
+}
+
+#[test]
+fn a_falling_player_lands_on_the_floor() {
+    let ticks = run(&level());
+    let landed = ticks.iter().position(|tick| tick.on_ground).expect("never landed");
+    // three units under gravity take most of a second
+    assert!((20..60).contains(&landed), "landed on tick {}", landed);
+    let landing = ticks[landed].position;
+    assert!(landing.y.abs() < 0.02, "landed at {}", landing);
+    // moving ahead the whole way down, and on up the step after
+    assert!(landing.x > 0.1 && landing.z.abs() < 0.01, "landed at {}", landing);
+    let end = ticks.last().unwrap().position;
+    assert!(end.x > 6. && (end.y - 0.2).abs() < 0.02, "ended at {}", end);
+}
+
+#[test]
+fn the_same_level_and_commands_step_the_same_every_time() {
+    let first = run(&level());
+    let second = run(&level());
+    assert_eq!(first, second);
+}
@@ -0,0 +1,137 @@
+// Weapons stepped tick by tick: how often they fire, what holding the
+// trigger does, and how ammo and reloading add up.
+
+mod common;
+
+use fps_engine::game::State;
+use fps_engine::input::InputCommand;
+use fps_engine::player::Player;
+use fps_engine::weapon::{Weapon, WeaponConfig};
+
+use common::DT;
+
+fn fire() -> InputCommand {
+    InputCommand {
+        fire: true,
+        ..Default::default()
+    }
+}
+
+// a weapon on its own in an empty level
+struct Range {
+    state: State,
+    player: Player,
+    weapon: Weapon,
+}
+
+impl Range {
+    fn new(config: WeaponConfig) -> Range {
+        let state = State::new();
+        let player = Player::new(&state.spawn);
+        Range {
+            state,
+            player,
+            weapon: Weapon::new(config),
+        }
+    }
+
+    // shots fired over `ticks` ticks of `cmd`
+    fn hold(&mut self, cmd: &InputCommand, ticks: u32) -> usize {
+        (0..ticks)
+            .map(|_| self.weapon.update(&mut self.state, cmd, &self.player, DT).len())
+            .sum()
+    }
+}
+
+fn small_magazine() -> WeaponConfig {
+    WeaponConfig {
+        magazine_size: 3,
+        reserve_ammo: 5,
+        reload_time: 1.,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn an_automatic_weapon_fires_once_per_interval_while_held() {
+    let mut range = Range::new(WeaponConfig::default());
+    // 0.1 seconds apart, starting at once
+    assert_eq!(range.hold(&fire(), 60), 10);
+    assert_eq!(range.weapon.ammo(), 20);
+
+    // letting go doesn't save up shots for later
+    range.hold(&InputCommand::default(), 60);
+    assert_eq!(range.hold(&fire(), 6), 1);
+}
+
+#[test]
+fn a_semi_automatic_weapon_fires_once_per_press() {
+    let mut range = Range::new(WeaponConfig {
+        automatic: false,
+        ..Default::default()
+    });
+    assert_eq!(range.hold(&fire(), 60), 1);
+
+    // pressing faster than the interval is held back by it
+    let mut shots = 0;
+    for _ in 0..30 {
+        shots += range.hold(&InputCommand::default(), 1);
+        shots += range.hold(&fire(), 1);
+    }
+    assert_eq!(shots, 10);
+}
+
+#[test]
+fn firing_empty_reloads_from_the_reserve() {
+    let mut range = Range::new(small_magazine());
+    assert_eq!(range.hold(&fire(), 18), 3);
+    assert_eq!(range.weapon.ammo(), 0);
+    assert!(range.weapon.is_reloading());
+
+    // nothing fires until the reload is done
+    assert_eq!(range.hold(&InputCommand::default(), 55), 0);
+    assert!(range.weapon.is_reloading());
+    range.hold(&InputCommand::default(), 5);
+    assert!(!range.weapon.is_reloading());
+    assert_eq!((range.weapon.ammo(), range.weapon.reserve()), (3, 2));
+
+    // the last of the reserve goes in the next magazine, then it's dry
+    range.hold(&fire(), 240);
+    assert_eq!((range.weapon.ammo(), range.weapon.reserve()), (0, 0));
+    assert!(!range.weapon.is_reloading());
+    assert_eq!(range.hold(&fire(), 60), 0);
+}
+
+#[test]
+fn reloading_tops_up_only_what_was_fired() {
+    let mut range = Range::new(small_magazine());
+    let reload = InputCommand {
+        reload: true,
+        ..Default::default()
+    };
+    // a full magazine has nothing to reload
+    range.hold(&reload, 1);
+    assert!(!range.weapon.is_reloading());
+
+    range.hold(&fire(), 1);
+    range.hold(&reload, 1);
+    assert!(range.weapon.is_reloading());
+    range.hold(&InputCommand::default(), 65);
+    assert_eq!((range.weapon.ammo(), range.weapon.reserve()), (3, 4));
+}
+
+#[test]
+fn weapons_seeded_differently_spread_differently() {
+    let spread = WeaponConfig {
+        spread: 0.1,
+        ..Default::default()
+    };
+    let mut directions = Vec::new();
+    for seed in [1, 2] {
+        let mut range = Range::new(spread.clone());
+        range.weapon.seed(seed);
+        let shots = range.weapon.update(&mut range.state, &fire(), &range.player, DT);
+        directions.push(shots[0].direction);
+    }
+    assert_ne!(directions[0], directions[1]);
+}